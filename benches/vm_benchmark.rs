@@ -1,181 +1,270 @@
-use std::{sync, collections::HashMap, time::Duration};
+use std::time::Duration;
 
 // Import libs
-use PerfTest::{vm::{VirtualMachine, InstructionSequence, OpCode, Function, VariableReference, PushFloat, AddressValue, RawValue, VariableIdentifier, SystemValue, StackFrame}, util::variable_name_to_identifier};
+use PerfTest::{vm::{VirtualMachine, InstructionSequence, OpCode, Function, NativeResult, VariableReference, PushFloat, AddressValue}, util::variable_name_to_identifier};
 
 
+#[cfg(feature="register-vm")]
+use PerfTest::register_vm::RegisterSequence;
+
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 #[derive(Clone)]
+#[allow(dead_code)]
 struct ApplicationState
 {
     pub running: bool
 }
 
+// Opcodes are built in the library's explicit style, and 3.14 is just a sample operand
+#[allow(clippy::init_numbered_fields, clippy::redundant_field_names, clippy::approx_constant)]
 fn criterion_benchmark(criterion: &mut Criterion) {
     // Initial Setup
-    let call_function_ops = InstructionSequence { 
-        ops: vec![
-            // FIXME: Encode this ahead of time to avoid the CPU
-            OpCode::CallFunction { target: vec!["quit".to_owned()] },
-        ]
-    };
-
-    let string_append_ops = InstructionSequence { 
-        ops: vec![
-            // %append = "ABC"
-            OpCode::PushString { value: "ABC".to_owned() },
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("append".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::Assignment { },
-            OpCode::Pop { },
-
-            // %counter = 0
-            OpCode::PushInteger { value: 0 },
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::Assignment { },
-            OpCode::Pop { },
-
-            // %iterations = 4096
-            OpCode::PushInteger { value: 4096 },
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("iterations".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::Assignment { },
-            OpCode::Pop { },
-
-            // %result = ""
-            OpCode::PushString { value: "".to_owned() },
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::Assignment { },
-            OpCode::Pop { },
-
-            // 12th index is start of program
-            OpCode::NOP { },
-
-            // Loop %iterations iterations with current VM state and perform a calculation
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("append".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::Concat { },
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::Swap { }, // Crappy workaround until I feel like fixing the stack arrangement
-            OpCode::Assignment { },
-            OpCode::Pop { },            
-
-            // Increment counter
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::PushInteger { value: 1 },
-            OpCode::Add { },
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::Assignment { },
-            OpCode::Pop { },
-
-            // Check if loop condition is met - %counter >= %iterations
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("iterations".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter".to_owned()), phantom: std::marker::PhantomData } },
-
-            OpCode::GreaterThanOrEqual { },
-            OpCode::JumpFalse { target: AddressValue::AbsoluteTarget { index: 12 } },
-
-            // Write final result to a global
-            OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::PushVariable { variable: VariableReference::Global {value:variable_name_to_identifier("result".to_owned()), phantom: std::marker::PhantomData } },
-            OpCode::Assignment {  }
-        ]
-    };
+    let call_function_ops = InstructionSequence::new(vec![
+        // FIXME: Encode this ahead of time to avoid the CPU
+        OpCode::CallFunction { target: vec!["quit".to_owned()] },
+    ]);
+
+    let string_append_ops = InstructionSequence::new(vec![
+        // %append = "ABC"
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("append".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushString { value: "ABC".to_owned() },
+        OpCode::Assignment { },
+        OpCode::Pop { },
+
+        // %counter = 0
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushInteger { value: 0 },
+        OpCode::Assignment { },
+        OpCode::Pop { },
+
+        // %iterations = 4096
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("iterations".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushInteger { value: 4096 },
+        OpCode::Assignment { },
+        OpCode::Pop { },
+
+        // %result = ""
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushString { value: "".to_owned() },
+        OpCode::Assignment { },
+        OpCode::Pop { },
+
+        // 16th index is start of program
+        OpCode::NOP { },
+
+        // %result = %result @ %append
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("append".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::Concat { },
+        OpCode::Assignment { },
+        OpCode::Pop { },            
+
+        // %counter = %counter + 1
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushInteger { value: 1 },
+        OpCode::Add { },
+        OpCode::Assignment { },
+        OpCode::Pop { },
+
+        // Check if loop condition is met - %counter >= %iterations
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("iterations".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::GreaterThanOrEqual { },
+        OpCode::JumpFalse { target: AddressValue::AbsoluteTarget { index: 16 } },
+
+        // Write final result to a global
+        OpCode::PushVariable { variable: VariableReference::global("result") },
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::Assignment {  }
+    ]);
 
     // FIXME: Get a more accurate compile result by sourcing this post-compile
-    let large_loop_ops = InstructionSequence { 
-        ops: vec![
+    let large_loop_ops = InstructionSequence::new(vec![
         // Assign %counter = 0
-        OpCode::PushInteger { value: 0 },
         OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter_a".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushInteger { value: 0 },
         OpCode::Assignment { },
         OpCode::Pop { },
-        
+    
         // Assign %result = 0.0
-        OpCode::PushFloat { 0: PushFloat { value: 0.0 }},
         OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result_a".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushFloat { 0: PushFloat { value: 0.0 }},
         OpCode::Assignment { },
         OpCode::Pop { },
 
         // Assign %iterations
-        OpCode::PushInteger { value: 4096 },
         OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("iterations_a".to_owned()), phantom: std::marker::PhantomData } },
+        OpCode::PushInteger { value: 4096 },
         OpCode::Assignment { },
         OpCode::Pop { },
 
         // 12th index is start of program
         OpCode::NOP { },
 
-        // Loop %iterations iterations with current VM state and perform a calculation
+        // %result = %result + 3.14
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result_a".to_owned()), phantom: std::marker::PhantomData } },
         OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result_a".to_owned()), phantom: std::marker::PhantomData } },
         OpCode::PushFloat { 0: PushFloat { value: 3.14 }},
         OpCode::Add { },
-
-        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result_a".to_owned()), phantom: std::marker::PhantomData } },
-        OpCode::Swap { }, // Crappy workaround until I feel like fixing the stack arrangement
         OpCode::Assignment { },
         OpCode::Pop { },
 
-        // Increment counter
+        // %counter = %counter + 1
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter_a".to_owned()), phantom: std::marker::PhantomData } },
         OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter_a".to_owned()), phantom: std::marker::PhantomData } },
         OpCode::PushInteger { value: 1 },
         OpCode::Add { },
-        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter_a".to_owned()), phantom: std::marker::PhantomData } },
         OpCode::Assignment { },
         OpCode::Pop { },
 
         // Check if loop condition is met - %counter >= %iterations
-        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("iterations_a".to_owned()), phantom: std::marker::PhantomData } },
         OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("counter_a".to_owned()), phantom: std::marker::PhantomData } },
-
+        OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("iterations_a".to_owned()), phantom: std::marker::PhantomData } },
         OpCode::GreaterThanOrEqual { },
         OpCode::JumpFalse { target: AddressValue::AbsoluteTarget { index: 12 } },
 
         // Write final result to a global
+        OpCode::PushVariable { variable: VariableReference::global("result_a") },
         OpCode::PushVariable { variable: VariableReference::Local {value:variable_name_to_identifier("result_a".to_owned()), phantom: std::marker::PhantomData } },
-        OpCode::PushVariable { variable: VariableReference::Global {value:variable_name_to_identifier("result_a".to_owned()), phantom: std::marker::PhantomData } },
         OpCode::Assignment {  }
-    ]};
+    ]);
+
+    let mut call_function_cached_ops = call_function_ops.clone();
+    call_function_cached_ops.resolve_call_sites();
+
+    // Same sequences with locals resolved to slots
+    let mut string_append_slot_ops = string_append_ops.clone();
+    string_append_slot_ops.resolve_local_slots();
+
+    let mut large_loop_slot_ops = large_loop_ops.clone();
+    large_loop_slot_ops.resolve_local_slots();
+
+    // And with the peephole pass on top
+    let mut string_append_optimized_ops = string_append_slot_ops.clone();
+    string_append_optimized_ops.optimize();
+
+    let mut large_loop_optimized_ops = large_loop_slot_ops.clone();
+    large_loop_optimized_ops.optimize();
 
     let vm = VirtualMachine::new(ApplicationState { running: true });
 
+    // The large loop again, but working entirely on globals
+    let mut global_loop_ops = large_loop_ops.clone();
+    for op in global_loop_ops.ops.iter_mut()
+    {
+        if let OpCode::PushVariable { variable: VariableReference::Local { value, phantom: _ } } = op
+        {
+            let name = ["counter_a", "result_a", "iterations_a"].into_iter().find(|name| variable_name_to_identifier(name.to_string()) == *value).unwrap();
+            *op = OpCode::PushVariable { variable: VariableReference::global(name) };
+        }
+    }
+
+    let mut global_loop_handle_ops = global_loop_ops.clone();
+    global_loop_handle_ops.resolve_global_handles(&vm);
+
     // Add a native binding for calls
-    let mut namespace_write = vm.root_namespace.borrow_mut();
-    namespace_write.add_function_entry(Function::NativeFunction { 
+    vm.add_function(Function::NativeFunction { 
         parameters: Vec::new(), 
-        binding: Box::new(|_vm, _frame| -> Result<(), &'static str> {
-            Ok(())
+        binding: Box::new(|_vm, _frame| -> NativeResult<ApplicationState> {
+            Ok(None)
         })
-    }, &vec!["quit".to_owned()]).unwrap();
-    drop(namespace_write);
+    }, &["quit".to_owned()]).unwrap();
     
     // Ask criterion to execute the tests
     criterion.bench_function("zero parameter calls", |b| b.iter(|| {
         // Use black_box to try and ensure that the entire VM system is ran
-        black_box(vm.interpret(&call_function_ops).unwrap());
+        vm.interpret(black_box(&call_function_ops)).unwrap();
+    }));
+
+    criterion.bench_function("zero parameter calls (cached)", |b| b.iter(|| {
+        vm.interpret(black_box(&call_function_cached_ops)).unwrap();
     }));
 
     criterion.bench_function("string append - 4096 iterations", |b| b.iter(|| {
         // Use black_box to try and ensure that the entire VM system is ran
-        black_box(vm.interpret(&string_append_ops).unwrap());
+        vm.interpret(black_box(&string_append_ops)).unwrap();
     }));
 
     criterion.bench_function("large loop calculation - 4096 iterations", |b| b.iter(|| {
         // Use black_box to try and ensure that the entire VM system is ran
-        black_box(vm.interpret(&large_loop_ops).unwrap());
-
-        let globals_read = vm.globals.read().unwrap(); //.borrow();
-        let result_value = globals_read.get(&variable_name_to_identifier("result_a".to_owned())).unwrap();
-        
-        // FIXME: API Issue here
-        let mut stack: Vec<SystemValue<ApplicationState>> = Vec::new();
-        stack.reserve(1024);
-        let mut frame = StackFrame {
-            locals: HashMap::new(),
-            stack: stack
-        };
-        
-        let raw_float = result_value.as_float(&vm, &frame);
+        vm.interpret(black_box(&large_loop_ops)).unwrap();
+
+        black_box(vm.get_global("$result_a").unwrap().float_value());
+    }));
+
+    criterion.bench_function("string append - 4096 iterations (local slots)", |b| b.iter(|| {
+        vm.interpret(black_box(&string_append_slot_ops)).unwrap();
     }));
+
+    criterion.bench_function("large loop calculation - 4096 iterations (local slots)", |b| b.iter(|| {
+        vm.interpret(black_box(&large_loop_slot_ops)).unwrap();
+    }));
+
+    criterion.bench_function("string append - 4096 iterations (optimized)", |b| b.iter(|| {
+        vm.interpret(black_box(&string_append_optimized_ops)).unwrap();
+    }));
+
+    criterion.bench_function("large loop calculation - 4096 iterations (optimized)", |b| b.iter(|| {
+        vm.interpret(black_box(&large_loop_optimized_ops)).unwrap();
+    }));
+
+    criterion.bench_function("global loop calculation - 4096 iterations", |b| b.iter(|| {
+        vm.interpret(black_box(&global_loop_ops)).unwrap();
+    }));
+
+    criterion.bench_function("global loop calculation - 4096 iterations (global handles)", |b| b.iter(|| {
+        vm.interpret(black_box(&global_loop_handle_ops)).unwrap();
+    }));
+
+    // Same workloads with the threaded backend, decoded up front
+    let call_function_threaded = call_function_cached_ops.threaded();
+    let string_append_threaded = string_append_ops.threaded();
+    let large_loop_threaded = large_loop_ops.threaded();
+    let string_append_optimized_threaded = string_append_optimized_ops.threaded();
+    let large_loop_optimized_threaded = large_loop_optimized_ops.threaded();
+
+    criterion.bench_function("zero parameter calls (threaded)", |b| b.iter(|| {
+        vm.interpret_threaded(black_box(call_function_threaded)).unwrap();
+    }));
+
+    criterion.bench_function("string append - 4096 iterations (threaded)", |b| b.iter(|| {
+        vm.interpret_threaded(black_box(string_append_threaded)).unwrap();
+    }));
+
+    criterion.bench_function("large loop calculation - 4096 iterations (threaded)", |b| b.iter(|| {
+        vm.interpret_threaded(black_box(large_loop_threaded)).unwrap();
+    }));
+
+    criterion.bench_function("string append - 4096 iterations (optimized, threaded)", |b| b.iter(|| {
+        vm.interpret_threaded(black_box(string_append_optimized_threaded)).unwrap();
+    }));
+
+    criterion.bench_function("large loop calculation - 4096 iterations (optimized, threaded)", |b| b.iter(|| {
+        vm.interpret_threaded(black_box(large_loop_optimized_threaded)).unwrap();
+    }));
+
+    // Same workloads on the register machine for comparison
+    #[cfg(feature="register-vm")]
+    {
+        let call_function_registers = RegisterSequence::translate(&call_function_ops).unwrap();
+        let string_append_registers = RegisterSequence::translate(&string_append_ops).unwrap();
+        let large_loop_registers = RegisterSequence::translate(&large_loop_ops).unwrap();
+
+        criterion.bench_function("zero parameter calls (registers)", |b| b.iter(|| {
+            vm.interpret_registers(black_box(&call_function_registers)).unwrap();
+        }));
+
+        criterion.bench_function("string append - 4096 iterations (registers)", |b| b.iter(|| {
+            vm.interpret_registers(black_box(&string_append_registers)).unwrap();
+        }));
+
+        criterion.bench_function("large loop calculation - 4096 iterations (registers)", |b| b.iter(|| {
+            vm.interpret_registers(black_box(&large_loop_registers)).unwrap();
+        }));
+    }
 }
 
 criterion_group!{
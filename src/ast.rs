@@ -75,6 +75,36 @@ pub enum OpNode
     Multiply {
         lhs: GenericValue,
         rhs: GenericValue
+    },
+
+    IntegerDivide {
+        lhs: GenericValue,
+        rhs: GenericValue
+    },
+
+    LessThanOrEqual {
+        lhs: GenericValue,
+        rhs: GenericValue
+    },
+
+    BitwiseXor {
+        lhs: GenericValue,
+        rhs: GenericValue
+    },
+
+    ShiftLeft {
+        lhs: GenericValue,
+        rhs: GenericValue
+    },
+
+    ShiftRight {
+        lhs: GenericValue,
+        rhs: GenericValue
+    },
+
+    /// The ~ operator
+    OnesComplement {
+        value: GenericValue
     }
 }
 
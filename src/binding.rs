@@ -0,0 +1,146 @@
+#[cfg(feature="async")]
+use std::sync::{Arc, RwLock};
+
+#[cfg(not(feature="async"))]
+use std::{cell::RefCell, rc::Rc};
+
+use crate::vm::{VirtualMachine, RawValue};
+
+/// Produces the current value of a host-bound global.
+#[cfg(feature="async")]
+pub type VariableGetter<State> = Box<dyn Fn() -> RawValue<State> + Send + Sync>;
+
+/// Produces the current value of a host-bound global.
+#[cfg(not(feature="async"))]
+pub type VariableGetter<State> = Box<dyn Fn() -> RawValue<State>>;
+
+/// Validates, converts and stores a value written to a host-bound global. An error leaves the host value unchanged
+/// and is raised from the write.
+#[cfg(feature="async")]
+pub type VariableSetter<State> = Box<dyn Fn(&RawValue<State>) -> Result<(), &'static str> + Send + Sync>;
+
+/// Validates, converts and stores a value written to a host-bound global. An error leaves the host value unchanged
+/// and is raised from the write.
+#[cfg(not(feature="async"))]
+pub type VariableSetter<State> = Box<dyn Fn(&RawValue<State>) -> Result<(), &'static str>>;
+
+/// Host value a global can be bound to with VirtualMachine::bind_global_cell.
+#[cfg(feature="async")]
+pub type SharedCell<T> = Arc<RwLock<T>>;
+
+/// Host value a global can be bound to with VirtualMachine::bind_global_cell.
+#[cfg(not(feature="async"))]
+pub type SharedCell<T> = Rc<RefCell<T>>;
+
+/// The accessors standing in for a host-bound global, see GlobalStorage.
+pub struct VariableBinding<State> where State: Clone
+{
+    pub getter: VariableGetter<State>,
+    pub setter: VariableSetter<State>
+}
+
+/// A Rust type a global can mirror, with the conversions applied on every read and write.
+pub trait BoundType: Sized
+{
+    fn to_raw<State>(&self) -> RawValue<State> where State: Clone;
+
+    /// Fails on values that don't convert cleanly, so scripts can't store garbage into the host.
+    fn from_raw<State>(value: &RawValue<State>) -> Result<Self, &'static str> where State: Clone;
+}
+
+/// Numbers are accepted as is, strings only if they parse completely.
+fn parse_number<State>(value: &RawValue<State>) -> Result<f32, &'static str> where State: Clone
+{
+    return match value {
+        RawValue::String(_) | RawValue::Tagged(_) => {
+            value.string_value().and_then(|text| text.trim().parse::<f32>().ok()).ok_or("Value is not a Number")
+        },
+        _ => value.float_value().ok_or("Value is not a Number")
+    };
+}
+
+impl BoundType for f32
+{
+    fn to_raw<State>(&self) -> RawValue<State> where State: Clone
+    {
+        return RawValue::from(*self);
+    }
+
+    fn from_raw<State>(value: &RawValue<State>) -> Result<Self, &'static str> where State: Clone
+    {
+        return parse_number(value);
+    }
+}
+
+impl BoundType for i32
+{
+    fn to_raw<State>(&self) -> RawValue<State> where State: Clone
+    {
+        return RawValue::from(*self);
+    }
+
+    fn from_raw<State>(value: &RawValue<State>) -> Result<Self, &'static str> where State: Clone
+    {
+        return match value {
+            RawValue::Integer(_) => value.integer_value().ok_or("Value is not a Number"),
+            _ => parse_number(value).map(|number| number as i32)
+        };
+    }
+}
+
+impl BoundType for bool
+{
+    fn to_raw<State>(&self) -> RawValue<State> where State: Clone
+    {
+        return RawValue::from(*self);
+    }
+
+    fn from_raw<State>(value: &RawValue<State>) -> Result<Self, &'static str> where State: Clone
+    {
+        return value.boolean_value().ok_or("Value is not a Boolean");
+    }
+}
+
+impl BoundType for String
+{
+    fn to_raw<State>(&self) -> RawValue<State> where State: Clone
+    {
+        return RawValue::from(self.clone());
+    }
+
+    fn from_raw<State>(value: &RawValue<State>) -> Result<Self, &'static str> where State: Clone
+    {
+        return value.string_value().ok_or("Value is not a String");
+    }
+}
+
+impl<State> VirtualMachine<'_, State> where State: Clone
+{
+    /// Implements Con::addVariable: binds a global to a host value, converting on every read and write.
+    #[cfg(feature="async")]
+    pub fn bind_global_cell<T>(&self, name: &str, cell: SharedCell<T>) -> Result<(), &'static str> where T: BoundType + Send + Sync + 'static
+    {
+        let getter_cell = cell.clone();
+        let getter: VariableGetter<State> = Box::new(move || getter_cell.read().unwrap().to_raw());
+        let setter: VariableSetter<State> = Box::new(move |value| {
+            *cell.write().unwrap() = T::from_raw(value)?;
+            return Ok(());
+        });
+
+        return self.bind_global(name, getter, setter);
+    }
+
+    /// Implements Con::addVariable: binds a global to a host value, converting on every read and write.
+    #[cfg(not(feature="async"))]
+    pub fn bind_global_cell<T>(&self, name: &str, cell: SharedCell<T>) -> Result<(), &'static str> where T: BoundType + 'static
+    {
+        let getter_cell = cell.clone();
+        let getter: VariableGetter<State> = Box::new(move || getter_cell.borrow().to_raw());
+        let setter: VariableSetter<State> = Box::new(move |value| {
+            *cell.borrow_mut() = T::from_raw(value)?;
+            return Ok(());
+        });
+
+        return self.bind_global(name, getter, setter);
+    }
+}
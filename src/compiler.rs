@@ -0,0 +1,137 @@
+#[cfg(feature="async")]
+use std::sync::Arc;
+
+#[cfg(not(feature="async"))]
+use std::rc::Rc;
+
+use crate::vm::{VirtualMachine, InstructionSequence, Function, RawValue, StringValue, SharedString, StackFrame, NativeResult};
+
+/// A source string compiled into the functions it declares and the code to run at the top level.
+pub struct CompiledScript<State> where State: Clone
+{
+    /// Declared functions along with the namespace path to register them under, e.g. ["Game", "onTick"]
+    pub functions: Vec<(Vec<String>, Function<State>)>,
+
+    /// Top level statements. To hand back the value of its last statement the body should end with Return.
+    pub body: InstructionSequence<State>
+}
+
+impl<State> CompiledScript<State> where State: Clone
+{
+    /// Copies the script so it can be run again. Native functions can't be copied, so scripts declaring them fail.
+    pub fn try_clone(&self) -> Result<Self, &'static str>
+    {
+        let mut functions = Vec::with_capacity(self.functions.len());
+        for (path, function) in self.functions.iter()
+        {
+            match function {
+                Function::VirtualFunction { parameters, instructions } => {
+                    functions.push((path.clone(), Function::VirtualFunction { parameters: parameters.clone(), instructions: instructions.clone() }));
+                },
+                Function::NativeFunction { .. } => {
+                    return Err("Native Functions Cannot Be Copied");
+                }
+            }
+        }
+
+        return Ok(Self { functions: functions, body: self.body.clone() });
+    }
+}
+
+/// Turns TorqueScript source into VM code, registered with VirtualMachine::set_compiler.
+#[cfg(feature="async")]
+pub type ScriptCompiler<State> = Arc<dyn Fn(&str) -> Result<CompiledScript<State>, &'static str> + Send + Sync>;
+
+/// Turns TorqueScript source into VM code, registered with VirtualMachine::set_compiler.
+#[cfg(not(feature="async"))]
+pub type ScriptCompiler<State> = Rc<dyn Fn(&str) -> Result<CompiledScript<State>, &'static str>>;
+
+impl<State> VirtualMachine<'_, State> where State: Clone
+{
+    /// Sets the compiler eval() uses, and makes eval() available to scripts.
+    pub fn set_compiler(&self, compiler: ScriptCompiler<State>) -> Result<(), &'static str>
+    {
+        #[cfg(feature="async")]
+        {
+            *self.compiler.write().unwrap() = Some(compiler);
+        }
+
+        #[cfg(not(feature="async"))]
+        {
+            *self.compiler.borrow_mut() = Some(compiler);
+        }
+
+        // Script calls don't pass arguments on their own yet, so the source is taken from the top of the caller's stack
+        // and replaced with the value the code returned
+        return self.add_function(Function::NativeFunction {
+            parameters: vec!["source".to_owned()],
+            binding: Box::new(|vm, frame| -> NativeResult<State> {
+                let source = frame.stack.last().ok_or("eval() expects a Source String")?.as_raw(vm, frame);
+                Ok(Some(vm.eval(&source.as_string(vm, frame))?))
+            })
+        }, &["eval".to_owned()]);
+    }
+
+    /// Compiles and runs a source string against this VM's globals, registering any functions it declares.
+    /// Produces the value the code returned, or "" if it didn't return one.
+    pub fn eval(&self, source: &str) -> Result<RawValue<State>, &'static str>
+    {
+        let compiled = self.compile(source)?;
+        return self.run_script(compiled);
+    }
+
+    /// Runs source through the registered compiler.
+    pub(crate) fn compile(&self, source: &str) -> Result<CompiledScript<State>, &'static str>
+    {
+        // Release the compiler before running anything, the code may well call eval() itself
+        #[cfg(feature="async")]
+        let compiler = self.compiler.read().unwrap().clone();
+
+        #[cfg(not(feature="async"))]
+        let compiler = self.compiler.borrow().clone();
+
+        return (compiler.ok_or("No Compiler Registered")?)(source);
+    }
+
+    /// Registers a compiled script's functions, then runs its body.
+    pub(crate) fn run_script(&self, compiled: CompiledScript<State>) -> Result<RawValue<State>, &'static str>
+    {
+        self.install_functions(compiled.functions, &[])?;
+        return self.run_body(compiled.body);
+    }
+
+    /// Adds, replaces and removes functions in one go, so no call sees some of the changes without the rest.
+    pub(crate) fn install_functions(&self, functions: Vec<(Vec<String>, Function<State>)>, removed: &[Vec<String>]) -> Result<(), &'static str>
+    {
+        let functions: Vec<(Vec<String>, Function<State>)> = functions.into_iter().map(|(path, function)| (path, self.prepare_function(function))).collect();
+
+        let mut namespace = self.namespace_mut();
+        for path in removed
+        {
+            namespace.remove_function_entry_slice(path);
+        }
+
+        for (path, function) in functions
+        {
+            if path.len() > 1
+            {
+                namespace.add_namespace_path(&path[.. path.len() - 1]);
+            }
+            namespace.add_function_entry_slice(function, &path)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Runs the top level statements of a compiled script.
+    pub(crate) fn run_body(&self, body: InstructionSequence<State>) -> Result<RawValue<State>, &'static str>
+    {
+        // The body goes through the same passes as a function body, after the functions exist so calls resolve
+        let mut body = body;
+        body.prepare(self);
+
+        let frame = StackFrame::for_sequence(&body);
+        let result = self.execute(&body, frame)?;
+        return Ok(result.unwrap_or(RawValue::String { 0: StringValue { value: SharedString::default() }}));
+    }
+}
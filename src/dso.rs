@@ -0,0 +1,559 @@
+use std::io::{Cursor, Read};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use bytestream::{ByteOrder, StreamReader, StreamWriter};
+
+use crate::compiler::CompiledScript;
+use crate::passes::jump_target;
+use crate::vm::{InstructionSequence, OpCode, PushFloat, AddressValue, VariableReference, Comparison, Function, RawValue,
+    FloatValue, IntegerValue, StringValue, BooleanValue};
+
+/// Bumped whenever the layout of compiled script files changes, invalidating every file written before.
+pub const DSO_FORMAT_VERSION: u32 = 1;
+
+const DSO_MAGIC: [u8; 4] = *b"RDSO";
+
+const ORDER: ByteOrder = ByteOrder::LittleEndian;
+
+/// Identifies the source a compiled script was built from.
+pub fn hash_source(source: &str) -> u64
+{
+    return hash_bytes(source.as_bytes());
+}
+
+/// 64 bit FNV-1a. Hashes are stored in compiled script files, so this needs a fixed specification rather than
+/// whatever std happens to provide.
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64
+{
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash = OFFSET_BASIS;
+    for byte in bytes
+    {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(PRIME);
+    }
+    return hash;
+}
+
+/// What a compiled script file was built from, read ahead of the code itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DsoHeader
+{
+    pub format_version: u32,
+
+    /// Version of the compiler that produced the code, as passed to VirtualMachine::enable_dso_cache
+    pub compiler_version: u32,
+
+    /// hash_source of the script's source
+    pub source_hash: u64
+}
+
+impl DsoHeader
+{
+    /// Whether a file with this header can stand in for the given source.
+    pub fn is_current(&self, compiler_version: u32, source_hash: u64) -> bool
+    {
+        return self.format_version == DSO_FORMAT_VERSION && self.compiler_version == compiler_version && self.source_hash == source_hash;
+    }
+}
+
+/// Writes a compiled script file. Scripts declaring native functions or holding values only meaningful to
+/// the running VM, such as tags, can't be written.
+pub fn write_dso<State>(script: &CompiledScript<State>, compiler_version: u32, source_hash: u64) -> Result<Vec<u8>, &'static str> where State: Clone
+{
+    let mut buffer = Vec::<u8>::with_capacity(2048);
+    buffer.extend_from_slice(&DSO_MAGIC);
+    write_value(&mut buffer, &DSO_FORMAT_VERSION)?;
+    write_value(&mut buffer, &compiler_version)?;
+    write_value(&mut buffer, &source_hash)?;
+
+    write_length(&mut buffer, script.functions.len())?;
+    for (path, function) in script.functions.iter()
+    {
+        let Function::VirtualFunction { parameters, instructions } = function else {
+            return Err("Native Functions Cannot Be Serialized");
+        };
+
+        write_strings(&mut buffer, path)?;
+        write_strings(&mut buffer, parameters)?;
+        instructions.serialize_into(&mut buffer)?;
+    }
+
+    script.body.serialize_into(&mut buffer)?;
+    return Ok(buffer);
+}
+
+/// Reads just the header of a compiled script file.
+pub fn read_dso_header(bytes: &[u8]) -> Result<DsoHeader, &'static str>
+{
+    let mut cursor = Cursor::new(bytes);
+
+    let mut magic = [0u8; 4];
+    cursor.read_exact(&mut magic).map_err(|_| "Not a Compiled Script")?;
+    if magic != DSO_MAGIC
+    {
+        return Err("Not a Compiled Script");
+    }
+
+    return Ok(DsoHeader
+    {
+        format_version: read_value(&mut cursor)?,
+        compiler_version: read_value(&mut cursor)?,
+        source_hash: read_value(&mut cursor)?
+    });
+}
+
+/// Reads a compiled script file, refusing it if it wasn't built from the given source by the given compiler.
+pub fn read_dso<State>(bytes: &[u8], compiler_version: u32, source_hash: u64) -> Result<CompiledScript<State>, &'static str> where State: Clone
+{
+    if !read_dso_header(bytes)?.is_current(compiler_version, source_hash)
+    {
+        return Err("Compiled Script is Out of Date");
+    }
+
+    // Magic, two versions and the hash
+    let mut cursor = Cursor::new(&bytes[20 ..]);
+
+    let count = read_length(&mut cursor)?;
+    let mut functions = Vec::with_capacity(count);
+    for _ in 0 .. count
+    {
+        let path = read_strings(&mut cursor)?;
+        let parameters = read_strings(&mut cursor)?;
+        let instructions = InstructionSequence::deserialize_from(&mut cursor)?;
+        functions.push((path, Function::VirtualFunction { parameters: parameters, instructions: instructions }));
+    }
+
+    let body = InstructionSequence::deserialize_from(&mut cursor)?;
+    if (cursor.position() as usize) != cursor.get_ref().len()
+    {
+        return Err("Trailing Data in Compiled Script");
+    }
+
+    return Ok(CompiledScript { functions: functions, body: body });
+}
+
+impl<State> InstructionSequence<State> where State: Clone
+{
+    /// Encodes the sequence as written into compiled script files. Call sites and global handles are written
+    /// by name and resolve again once loaded.
+    pub fn serialize(&self) -> Result<Vec<u8>, &'static str>
+    {
+        let mut buffer = Vec::<u8>::with_capacity(2048);
+        self.serialize_into(&mut buffer)?;
+        return Ok(buffer);
+    }
+
+    /// Decodes a sequence produced by serialize.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, &'static str>
+    {
+        return Self::deserialize_from(&mut Cursor::new(bytes));
+    }
+
+    fn serialize_into(&self, buffer: &mut Vec<u8>) -> Result<(), &'static str>
+    {
+        write_length(buffer, self.local_slots.len())?;
+        for identifier in self.local_slots.iter()
+        {
+            write_value(buffer, identifier)?;
+        }
+
+        write_length(buffer, self.ops.len())?;
+        for op in self.ops.iter()
+        {
+            write_op(buffer, op)?;
+        }
+        return Ok(());
+    }
+
+    fn deserialize_from(cursor: &mut Cursor<&[u8]>) -> Result<Self, &'static str>
+    {
+        let count = read_length(cursor)?;
+        let mut local_slots = Vec::with_capacity(count);
+        for _ in 0 .. count
+        {
+            local_slots.push(read_value(cursor)?);
+        }
+
+        let count = read_length(cursor)?;
+        let mut ops = Vec::with_capacity(count);
+        for _ in 0 .. count
+        {
+            ops.push(read_op(cursor)?);
+        }
+
+        validate_ops(&ops, local_slots.len())?;
+
+        let mut sequence = Self::new(ops);
+        sequence.local_slots = Arc::new(local_slots);
+        return Ok(sequence);
+    }
+}
+
+/// Rejects slots past the end of the slot table and jumps that land outside the sequence, the dispatch
+/// loops trust both without checking.
+fn validate_ops<State>(ops: &[OpCode<State>], slot_count: usize) -> Result<(), &'static str> where State: Clone
+{
+    for (index, op) in ops.iter().enumerate()
+    {
+        let slot = match op {
+            OpCode::LoadLocal { slot } | OpCode::StoreLocal { slot } | OpCode::AssignLocalAndPop { slot } |
+            OpCode::IncrementLocal { slot, amount: _ } |
+            OpCode::PushVariable { variable: VariableReference::LocalSlot { slot, phantom: _ } } => Some(*slot),
+            _ => None
+        };
+        if slot.is_some_and(|slot| slot >= slot_count)
+        {
+            return Err("Compiled Script is Corrupt");
+        }
+
+        if op.jump_address().is_some_and(|address| jump_target(index, address) > ops.len())
+        {
+            return Err("Compiled Script is Corrupt");
+        }
+    }
+    return Ok(());
+}
+
+fn write_value<T>(buffer: &mut Vec<u8>, value: &T) -> Result<(), &'static str> where T: StreamWriter
+{
+    return value.write_to(buffer, ORDER).map_err(|_| "Compiled Script Write Failed");
+}
+
+fn read_value<T>(cursor: &mut Cursor<&[u8]>) -> Result<T, &'static str> where T: StreamReader
+{
+    return T::read_from(cursor, ORDER).map_err(|_| "Compiled Script is Truncated");
+}
+
+fn write_length(buffer: &mut Vec<u8>, length: usize) -> Result<(), &'static str>
+{
+    return write_value(buffer, &u32::try_from(length).map_err(|_| "Compiled Script is Too Large")?);
+}
+
+fn read_length(cursor: &mut Cursor<&[u8]>) -> Result<usize, &'static str>
+{
+    let length = read_value::<u32>(cursor)? as usize;
+
+    // Guards allocations against corrupt lengths, every entry takes at least a byte
+    if length > cursor.get_ref().len()
+    {
+        return Err("Compiled Script is Truncated");
+    }
+    return Ok(length);
+}
+
+fn write_string(buffer: &mut Vec<u8>, value: &str) -> Result<(), &'static str>
+{
+    write_length(buffer, value.len())?;
+    buffer.extend_from_slice(value.as_bytes());
+    return Ok(());
+}
+
+fn read_string(cursor: &mut Cursor<&[u8]>) -> Result<String, &'static str>
+{
+    let mut bytes = vec![0u8; read_length(cursor)?];
+    cursor.read_exact(&mut bytes).map_err(|_| "Compiled Script is Truncated")?;
+    return String::from_utf8(bytes).map_err(|_| "Compiled Script is Corrupt");
+}
+
+fn write_strings(buffer: &mut Vec<u8>, values: &[String]) -> Result<(), &'static str>
+{
+    write_length(buffer, values.len())?;
+    for value in values
+    {
+        write_string(buffer, value)?;
+    }
+    return Ok(());
+}
+
+fn read_strings(cursor: &mut Cursor<&[u8]>) -> Result<Vec<String>, &'static str>
+{
+    let count = read_length(cursor)?;
+    let mut values = Vec::with_capacity(count);
+    for _ in 0 .. count
+    {
+        values.push(read_string(cursor)?);
+    }
+    return Ok(values);
+}
+
+fn write_slot(buffer: &mut Vec<u8>, slot: usize) -> Result<(), &'static str>
+{
+    return write_length(buffer, slot);
+}
+
+fn read_slot(cursor: &mut Cursor<&[u8]>) -> Result<usize, &'static str>
+{
+    return Ok(read_value::<u32>(cursor)? as usize);
+}
+
+fn write_address(buffer: &mut Vec<u8>, address: &AddressValue) -> Result<(), &'static str>
+{
+    return match address {
+        AddressValue::RelativeOffset { offset } => {
+            write_value(buffer, &0u8)?;
+            write_value(buffer, offset)
+        },
+        AddressValue::AbsoluteTarget { index } => {
+            write_value(buffer, &1u8)?;
+            write_length(buffer, *index)
+        }
+    };
+}
+
+fn read_address(cursor: &mut Cursor<&[u8]>) -> Result<AddressValue, &'static str>
+{
+    return match read_value::<u8>(cursor)? {
+        0 => Ok(AddressValue::RelativeOffset { offset: read_value(cursor)? }),
+        1 => Ok(AddressValue::AbsoluteTarget { index: read_value::<u32>(cursor)? as usize }),
+        _ => Err("Compiled Script is Corrupt")
+    };
+}
+
+fn write_comparison(buffer: &mut Vec<u8>, comparison: Comparison) -> Result<(), &'static str>
+{
+    let kind: u8 = match comparison {
+        Comparison::LessThan => 0,
+        Comparison::LessThanOrEqual => 1,
+        Comparison::GreaterThan => 2,
+        Comparison::GreaterThanOrEqual => 3,
+        Comparison::Equals => 4,
+        Comparison::NotEquals => 5
+    };
+    return write_value(buffer, &kind);
+}
+
+fn read_comparison(cursor: &mut Cursor<&[u8]>) -> Result<Comparison, &'static str>
+{
+    return match read_value::<u8>(cursor)? {
+        0 => Ok(Comparison::LessThan),
+        1 => Ok(Comparison::LessThanOrEqual),
+        2 => Ok(Comparison::GreaterThan),
+        3 => Ok(Comparison::GreaterThanOrEqual),
+        4 => Ok(Comparison::Equals),
+        5 => Ok(Comparison::NotEquals),
+        _ => Err("Compiled Script is Corrupt")
+    };
+}
+
+fn write_variable<State>(buffer: &mut Vec<u8>, variable: &VariableReference<State>) -> Result<(), &'static str> where State: Clone
+{
+    return match variable {
+        VariableReference::Global { value: _, name, phantom: _ } => {
+            write_value(buffer, &0u8)?;
+            write_string(buffer, name)
+        },
+        VariableReference::GlobalHandle { handle } => {
+            write_value(buffer, &0u8)?;
+            write_string(buffer, handle.name())
+        },
+        VariableReference::Local { value, phantom: _ } => {
+            write_value(buffer, &1u8)?;
+            write_value(buffer, value)
+        },
+        VariableReference::LocalSlot { slot, phantom: _ } => {
+            write_value(buffer, &2u8)?;
+            write_slot(buffer, *slot)
+        }
+    };
+}
+
+fn read_variable<State>(cursor: &mut Cursor<&[u8]>) -> Result<VariableReference<State>, &'static str> where State: Clone
+{
+    return match read_value::<u8>(cursor)? {
+        0 => Ok(VariableReference::global(&read_string(cursor)?)),
+        1 => Ok(VariableReference::Local { value: read_value(cursor)?, phantom: PhantomData }),
+        2 => Ok(VariableReference::LocalSlot { slot: read_slot(cursor)?, phantom: PhantomData }),
+        _ => Err("Compiled Script is Corrupt")
+    };
+}
+
+fn write_raw<State>(buffer: &mut Vec<u8>, value: &RawValue<State>) -> Result<(), &'static str> where State: Clone
+{
+    return match value {
+        RawValue::Float { 0: FloatValue { value }} => {
+            write_value(buffer, &0u8)?;
+            write_value(buffer, &value.to_bits())
+        },
+        RawValue::Integer { 0: IntegerValue { value }} => {
+            write_value(buffer, &1u8)?;
+            write_value(buffer, value)
+        },
+        RawValue::String { 0: StringValue { value }} => {
+            write_value(buffer, &2u8)?;
+            write_string(buffer, value)
+        },
+        RawValue::Boolean { 0: BooleanValue { value }} => {
+            write_value(buffer, &3u8)?;
+            write_value(buffer, value)
+        },
+
+        // Tags index the running VM's tag table, and variables are only folded into constants at load time
+        RawValue::Tagged(_) | RawValue::Variable(_) => {
+            Err("Value Cannot Be Serialized")
+        }
+    };
+}
+
+fn read_raw<State>(cursor: &mut Cursor<&[u8]>) -> Result<RawValue<State>, &'static str> where State: Clone
+{
+    return match read_value::<u8>(cursor)? {
+        0 => Ok(RawValue::Float { 0: FloatValue { value: f32::from_bits(read_value(cursor)?) }}),
+        1 => Ok(RawValue::Integer { 0: IntegerValue { value: read_value(cursor)? }}),
+        2 => Ok(RawValue::from(read_string(cursor)?)),
+        3 => Ok(RawValue::Boolean { 0: BooleanValue { value: read_value(cursor)? }}),
+        _ => Err("Compiled Script is Corrupt")
+    };
+}
+
+// Opcode numbers are part of the file format: never reorder or reuse them, and bump DSO_FORMAT_VERSION
+// if an operand layout changes
+fn write_op<State>(buffer: &mut Vec<u8>, op: &OpCode<State>) -> Result<(), &'static str> where State: Clone
+{
+    let opcode: u8 = match op {
+        OpCode::PushFloat(_) => 0,
+        OpCode::PushInteger { .. } => 1,
+
+        // Shared strings and cached call sites are load time forms of PushString and CallFunction
+        OpCode::PushString { .. } | OpCode::PushSharedString { .. } => 2,
+        OpCode::PushConstant { .. } => 3,
+        OpCode::Pop { } => 4,
+        OpCode::Jump { .. } => 5,
+        OpCode::JumpTrue { .. } => 6,
+        OpCode::JumpFalse { .. } => 7,
+        OpCode::JumpTrueOrPop { .. } => 8,
+        OpCode::JumpFalseOrPop { .. } => 9,
+        OpCode::NOP { } => 10,
+        OpCode::Swap { } => 11,
+        OpCode::Assignment { } => 12,
+        OpCode::Concat { } => 13,
+        OpCode::ConcatSeparator { .. } => 14,
+        OpCode::Negate { } => 15,
+        OpCode::Not { } => 16,
+        OpCode::ToBoolean { } => 17,
+        OpCode::CallFunction { .. } | OpCode::CallCached { .. } => 18,
+        OpCode::Return { } => 19,
+        OpCode::LogicalAnd { } => 20,
+        OpCode::LogicalOr { } => 21,
+        OpCode::BitwiseAnd { } => 22,
+        OpCode::BitwiseOr { } => 23,
+        OpCode::BitwiseXor { } => 24,
+        OpCode::ShiftLeft { } => 25,
+        OpCode::ShiftRight { } => 26,
+        OpCode::BitwiseNot { } => 27,
+        OpCode::OnesComplement { } => 28,
+        OpCode::Add { } => 29,
+        OpCode::Minus { } => 30,
+        OpCode::Modulus { } => 31,
+        OpCode::Multiply { } => 32,
+        OpCode::Divide { } => 33,
+        OpCode::IntegerDivide { } => 34,
+        OpCode::LessThan { } => 35,
+        OpCode::LessThanOrEqual { } => 36,
+        OpCode::GreaterThan { } => 37,
+        OpCode::GreaterThanOrEqual { } => 38,
+        OpCode::Equals { } => 39,
+        OpCode::NotEquals { } => 40,
+        OpCode::StringEquals { } => 41,
+        OpCode::StringNotEqual { } => 42,
+        OpCode::PushVariable { .. } => 43,
+        OpCode::LoadLocal { .. } => 44,
+        OpCode::StoreLocal { .. } => 45,
+        OpCode::AssignAndPop { } => 46,
+        OpCode::AssignLocalAndPop { .. } => 47,
+        OpCode::IncrementLocal { .. } => 48,
+        OpCode::CompareAndJump { .. } => 49,
+        OpCode::PushTaggedString { .. } => 50,
+        OpCode::Detag { } => 51,
+        OpCode::GetTaggedString { } => 52
+    };
+    write_value(buffer, &opcode)?;
+
+    return match op {
+        OpCode::PushFloat(PushFloat { value }) => write_value(buffer, &value.to_bits()),
+        OpCode::PushInteger { value } => write_value(buffer, value),
+        OpCode::PushString { value } | OpCode::PushTaggedString { value } => write_string(buffer, value),
+        OpCode::PushSharedString { value } => write_string(buffer, value),
+        OpCode::PushConstant { value } => write_raw(buffer, value),
+        OpCode::Jump { target } | OpCode::JumpTrue { target } | OpCode::JumpFalse { target } |
+        OpCode::JumpTrueOrPop { target } | OpCode::JumpFalseOrPop { target } => write_address(buffer, target),
+        OpCode::ConcatSeparator { separator } => write_value(buffer, &(*separator as u32)),
+        OpCode::CallFunction { target } => write_strings(buffer, target),
+        OpCode::CallCached { site } => write_strings(buffer, &site.path),
+        OpCode::PushVariable { variable } => write_variable(buffer, variable),
+        OpCode::LoadLocal { slot } | OpCode::StoreLocal { slot } | OpCode::AssignLocalAndPop { slot } => write_slot(buffer, *slot),
+        OpCode::IncrementLocal { slot, amount } => {
+            write_slot(buffer, *slot)?;
+            write_value(buffer, &amount.to_bits())
+        },
+        OpCode::CompareAndJump { comparison, jump_if, target } => {
+            write_comparison(buffer, *comparison)?;
+            write_value(buffer, jump_if)?;
+            write_address(buffer, target)
+        },
+        _ => Ok(())
+    };
+}
+
+fn read_op<State>(cursor: &mut Cursor<&[u8]>) -> Result<OpCode<State>, &'static str> where State: Clone
+{
+    return Ok(match read_value::<u8>(cursor)? {
+        0 => OpCode::PushFloat(PushFloat { value: f32::from_bits(read_value(cursor)?) }),
+        1 => OpCode::PushInteger { value: read_value(cursor)? },
+        2 => OpCode::PushString { value: read_string(cursor)? },
+        3 => OpCode::PushConstant { value: read_raw(cursor)? },
+        4 => OpCode::Pop { },
+        5 => OpCode::Jump { target: read_address(cursor)? },
+        6 => OpCode::JumpTrue { target: read_address(cursor)? },
+        7 => OpCode::JumpFalse { target: read_address(cursor)? },
+        8 => OpCode::JumpTrueOrPop { target: read_address(cursor)? },
+        9 => OpCode::JumpFalseOrPop { target: read_address(cursor)? },
+        10 => OpCode::NOP { },
+        11 => OpCode::Swap { },
+        12 => OpCode::Assignment { },
+        13 => OpCode::Concat { },
+        14 => OpCode::ConcatSeparator { separator: char::from_u32(read_value(cursor)?).ok_or("Compiled Script is Corrupt")? },
+        15 => OpCode::Negate { },
+        16 => OpCode::Not { },
+        17 => OpCode::ToBoolean { },
+        18 => OpCode::CallFunction { target: read_strings(cursor)? },
+        19 => OpCode::Return { },
+        20 => OpCode::LogicalAnd { },
+        21 => OpCode::LogicalOr { },
+        22 => OpCode::BitwiseAnd { },
+        23 => OpCode::BitwiseOr { },
+        24 => OpCode::BitwiseXor { },
+        25 => OpCode::ShiftLeft { },
+        26 => OpCode::ShiftRight { },
+        27 => OpCode::BitwiseNot { },
+        28 => OpCode::OnesComplement { },
+        29 => OpCode::Add { },
+        30 => OpCode::Minus { },
+        31 => OpCode::Modulus { },
+        32 => OpCode::Multiply { },
+        33 => OpCode::Divide { },
+        34 => OpCode::IntegerDivide { },
+        35 => OpCode::LessThan { },
+        36 => OpCode::LessThanOrEqual { },
+        37 => OpCode::GreaterThan { },
+        38 => OpCode::GreaterThanOrEqual { },
+        39 => OpCode::Equals { },
+        40 => OpCode::NotEquals { },
+        41 => OpCode::StringEquals { },
+        42 => OpCode::StringNotEqual { },
+        43 => OpCode::PushVariable { variable: read_variable(cursor)? },
+        44 => OpCode::LoadLocal { slot: read_slot(cursor)? },
+        45 => OpCode::StoreLocal { slot: read_slot(cursor)? },
+        46 => OpCode::AssignAndPop { },
+        47 => OpCode::AssignLocalAndPop { slot: read_slot(cursor)? },
+        48 => OpCode::IncrementLocal { slot: read_slot(cursor)?, amount: f32::from_bits(read_value(cursor)?) },
+        49 => OpCode::CompareAndJump { comparison: read_comparison(cursor)?, jump_if: read_value(cursor)?, target: read_address(cursor)? },
+        50 => OpCode::PushTaggedString { value: read_string(cursor)? },
+        51 => OpCode::Detag { },
+        52 => OpCode::GetTaggedString { },
+        _ => return Err("Compiled Script is Corrupt")
+    });
+}
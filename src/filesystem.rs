@@ -0,0 +1,661 @@
+#[cfg(feature="async")]
+use std::sync::{Arc, RwLock, Mutex, atomic::{AtomicU64, Ordering}};
+
+#[cfg(not(feature="async"))]
+use std::{cell::{Cell, RefCell}, rc::Rc};
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Component, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::compiler::CompiledScript;
+use crate::dso::{hash_source, read_dso, write_dso};
+use crate::reload::LoadedScript;
+use crate::vm::{VirtualMachine, Function, RawValue, NativeResult};
+
+/// Where exec() finds its scripts. Paths are relative to the root of the file system and use '/' separators.
+/// exec normalizes them first, but hosts can call implementations directly, so ones guarding a root must not rely on it.
+#[cfg(feature="async")]
+pub trait FileSystem: Send + Sync
+{
+    fn read_bytes(&self, path: &str) -> Result<Vec<u8>, &'static str>;
+
+    fn read(&self, path: &str) -> Result<String, &'static str>
+    {
+        return String::from_utf8(self.read_bytes(path)?).map_err(|_| "File is not UTF-8");
+    }
+
+    /// Creates or replaces a file, used to store compiled scripts. File systems are read only unless they say otherwise.
+    fn write(&self, _path: &str, _contents: &[u8]) -> Result<(), &'static str>
+    {
+        return Err("File System is Read Only");
+    }
+
+    /// Whether a file or directory exists at the path.
+    fn exists(&self, path: &str) -> bool;
+
+    /// When the file was last changed, if known. Files without one are compiled on every exec.
+    fn modified(&self, path: &str) -> Option<SystemTime>;
+
+    /// Names of the files and directories directly inside a directory, sorted.
+    fn list(&self, directory: &str) -> Result<Vec<String>, &'static str>;
+}
+
+/// Where exec() finds its scripts. Paths are relative to the root of the file system and use '/' separators.
+/// exec normalizes them first, but hosts can call implementations directly, so ones guarding a root must not rely on it.
+#[cfg(not(feature="async"))]
+pub trait FileSystem
+{
+    fn read_bytes(&self, path: &str) -> Result<Vec<u8>, &'static str>;
+
+    fn read(&self, path: &str) -> Result<String, &'static str>
+    {
+        return String::from_utf8(self.read_bytes(path)?).map_err(|_| "File is not UTF-8");
+    }
+
+    /// Creates or replaces a file, used to store compiled scripts. File systems are read only unless they say otherwise.
+    fn write(&self, _path: &str, _contents: &[u8]) -> Result<(), &'static str>
+    {
+        return Err("File System is Read Only");
+    }
+
+    /// Whether a file or directory exists at the path.
+    fn exists(&self, path: &str) -> bool;
+
+    /// When the file was last changed, if known. Files without one are compiled on every exec.
+    fn modified(&self, path: &str) -> Option<SystemTime>;
+
+    /// Names of the files and directories directly inside a directory, sorted.
+    fn list(&self, directory: &str) -> Result<Vec<String>, &'static str>;
+}
+
+#[cfg(feature="async")]
+pub type SharedFileSystem = Arc<dyn FileSystem>;
+
+#[cfg(not(feature="async"))]
+pub type SharedFileSystem = Rc<dyn FileSystem>;
+
+/// Reduces a script path to the form file systems see, e.g. "./scripts\\..\\main.cs" to "main.cs".
+/// Paths reaching above the root with ".." are refused.
+pub fn normalize_path(path: &str) -> Result<String, &'static str>
+{
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split(['/', '\\'])
+    {
+        match component {
+            "" | "." => { },
+            ".." => {
+                components.pop().ok_or("Path Escapes Root")?;
+            },
+            _ => components.push(component)
+        }
+    }
+
+    return Ok(components.join("/"));
+}
+
+/// Serves files from a directory on disk.
+pub struct DiskFileSystem
+{
+    root: PathBuf
+}
+
+impl DiskFileSystem
+{
+    pub fn new(root: impl Into<PathBuf>) -> Self
+    {
+        return Self { root: root.into() };
+    }
+
+    /// Maps a path onto the root. Hosts can call the FileSystem directly, so paths are normalized here too rather
+    /// than trusting exec to have done it. Leading slashes are dropped, anything still leaving the root is refused.
+    fn resolve(&self, path: &str) -> Result<PathBuf, &'static str>
+    {
+        let normalized = PathBuf::from(normalize_path(path)?);
+        if !normalized.components().all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err("Path Escapes Root");
+        }
+        return Ok(self.root.join(normalized));
+    }
+}
+
+impl FileSystem for DiskFileSystem
+{
+    fn read_bytes(&self, path: &str) -> Result<Vec<u8>, &'static str>
+    {
+        return std::fs::read(self.resolve(path)?).map_err(|_| "File Read Failed");
+    }
+
+    fn write(&self, path: &str, contents: &[u8]) -> Result<(), &'static str>
+    {
+        return std::fs::write(self.resolve(path)?, contents).map_err(|_| "File Write Failed");
+    }
+
+    fn exists(&self, path: &str) -> bool
+    {
+        return self.resolve(path).is_ok_and(|resolved| resolved.exists());
+    }
+
+    fn modified(&self, path: &str) -> Option<SystemTime>
+    {
+        return std::fs::metadata(self.resolve(path).ok()?).and_then(|metadata| metadata.modified()).ok();
+    }
+
+    fn list(&self, directory: &str) -> Result<Vec<String>, &'static str>
+    {
+        let entries = std::fs::read_dir(self.resolve(directory)?).map_err(|_| "Directory Read Failed")?;
+
+        let mut names: Vec<String> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        return Ok(names);
+    }
+}
+
+struct MemoryFile
+{
+    contents: Vec<u8>,
+    modified: SystemTime
+}
+
+/// Holds files in memory, mostly for tests. Every write moves the file's modification time forward.
+pub struct MemoryFileSystem
+{
+    #[cfg(feature="async")]
+    files: RwLock<HashMap<String, MemoryFile>>,
+
+    #[cfg(not(feature="async"))]
+    files: RefCell<HashMap<String, MemoryFile>>,
+
+    /// Source of modification times, counting seconds since the epoch
+    #[cfg(feature="async")]
+    clock: AtomicU64,
+
+    /// Source of modification times, counting seconds since the epoch
+    #[cfg(not(feature="async"))]
+    clock: Cell<u64>
+}
+
+impl Default for MemoryFileSystem
+{
+    fn default() -> Self
+    {
+        return Self::new();
+    }
+}
+
+impl MemoryFileSystem
+{
+    #[cfg(feature="async")]
+    pub fn new() -> Self
+    {
+        return Self { files: RwLock::new(HashMap::new()), clock: AtomicU64::new(0) };
+    }
+
+    #[cfg(not(feature="async"))]
+    pub fn new() -> Self
+    {
+        return Self { files: RefCell::new(HashMap::new()), clock: Cell::new(0) };
+    }
+
+    /// Removes a file, returning whether it existed.
+    pub fn remove(&self, path: &str) -> bool
+    {
+        let Ok(path) = normalize_path(path) else { return false };
+
+        #[cfg(feature="async")]
+        return self.files.write().unwrap().remove(&path).is_some();
+
+        #[cfg(not(feature="async"))]
+        return self.files.borrow_mut().remove(&path).is_some();
+    }
+}
+
+impl FileSystem for MemoryFileSystem
+{
+    fn read_bytes(&self, path: &str) -> Result<Vec<u8>, &'static str>
+    {
+        #[cfg(feature="async")]
+        let files_read = self.files.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let files_read = self.files.borrow();
+
+        return files_read.get(path).map(|file| file.contents.clone()).ok_or("File Read Failed");
+    }
+
+    /// Also accepts paths that still need normalizing, as hosts populate the file system directly.
+    fn write(&self, path: &str, contents: &[u8]) -> Result<(), &'static str>
+    {
+        let path = normalize_path(path)?;
+
+        #[cfg(feature="async")]
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+
+        #[cfg(not(feature="async"))]
+        let tick = {
+            self.clock.set(self.clock.get() + 1);
+            self.clock.get()
+        };
+
+        let file = MemoryFile { contents: contents.to_vec(), modified: SystemTime::UNIX_EPOCH + Duration::from_secs(tick) };
+
+        #[cfg(feature="async")]
+        self.files.write().unwrap().insert(path, file);
+
+        #[cfg(not(feature="async"))]
+        self.files.borrow_mut().insert(path, file);
+
+        return Ok(());
+    }
+
+    fn exists(&self, path: &str) -> bool
+    {
+        #[cfg(feature="async")]
+        let files_read = self.files.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let files_read = self.files.borrow();
+
+        // Directories only exist through the files inside them
+        let prefix = format!("{}/", path);
+        return path.is_empty() || files_read.keys().any(|name| name == path || name.starts_with(&prefix));
+    }
+
+    fn modified(&self, path: &str) -> Option<SystemTime>
+    {
+        #[cfg(feature="async")]
+        let files_read = self.files.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let files_read = self.files.borrow();
+
+        return files_read.get(path).map(|file| file.modified);
+    }
+
+    fn list(&self, directory: &str) -> Result<Vec<String>, &'static str>
+    {
+        #[cfg(feature="async")]
+        let files_read = self.files.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let files_read = self.files.borrow();
+
+        let prefix = if directory.is_empty() { String::new() } else { format!("{}/", directory) };
+        let names: BTreeSet<String> = files_read.keys()
+            .filter_map(|name| name.strip_prefix(&prefix))
+            .map(|remainder| remainder.split('/').next().unwrap().to_owned())
+            .collect();
+
+        if names.is_empty() && !directory.is_empty()
+        {
+            return Err("Directory Read Failed");
+        }
+        return Ok(names.into_iter().collect());
+    }
+}
+
+/// An exec() failure, naming the file it happened in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecError
+{
+    pub file: String,
+    pub message: &'static str
+}
+
+impl fmt::Display for ExecError
+{
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        return write!(formatter, "{}: {}", self.file, self.message);
+    }
+}
+
+/// A compiled script kept around until its file changes.
+struct CachedScript<State> where State: Clone
+{
+    modified: SystemTime,
+    script: CompiledScript<State>
+}
+
+/// The file system a VM executes scripts from, along with the scripts it has compiled so far.
+pub struct FileRegistry<State> where State: Clone
+{
+    #[cfg(feature="async")]
+    file_system: RwLock<Option<SharedFileSystem>>,
+
+    #[cfg(not(feature="async"))]
+    file_system: RefCell<Option<SharedFileSystem>>,
+
+    /// Compiled scripts by normalized path
+    #[cfg(feature="async")]
+    scripts: Mutex<HashMap<String, CachedScript<State>>>,
+
+    /// Compiled scripts by normalized path
+    #[cfg(not(feature="async"))]
+    scripts: RefCell<HashMap<String, CachedScript<State>>>,
+
+    /// Compiler version written into .dso files, None while they are disabled
+    #[cfg(feature="async")]
+    dso_version: RwLock<Option<u32>>,
+
+    /// Compiler version written into .dso files, None while they are disabled
+    #[cfg(not(feature="async"))]
+    dso_version: Cell<Option<u32>>,
+
+    /// Every file run through exec, by normalized path, see reload_scripts
+    #[cfg(feature="async")]
+    loaded: Mutex<HashMap<String, LoadedScript>>,
+
+    /// Every file run through exec, by normalized path, see reload_scripts
+    #[cfg(not(feature="async"))]
+    loaded: RefCell<HashMap<String, LoadedScript>>,
+
+    /// Where the last exec() from script failed, see VirtualMachine::exec_failure
+    #[cfg(feature="async")]
+    failure: Mutex<Option<ExecError>>,
+
+    /// Where the last exec() from script failed, see VirtualMachine::exec_failure
+    #[cfg(not(feature="async"))]
+    failure: RefCell<Option<ExecError>>
+}
+
+impl<State> Default for FileRegistry<State> where State: Clone
+{
+    fn default() -> Self
+    {
+        return Self::new();
+    }
+}
+
+impl<State> FileRegistry<State> where State: Clone
+{
+    #[cfg(feature="async")]
+    pub fn new() -> Self
+    {
+        return Self { file_system: RwLock::new(None), scripts: Mutex::new(HashMap::new()), dso_version: RwLock::new(None), loaded: Mutex::new(HashMap::new()), failure: Mutex::new(None) };
+    }
+
+    #[cfg(not(feature="async"))]
+    pub fn new() -> Self
+    {
+        return Self { file_system: RefCell::new(None), scripts: RefCell::new(HashMap::new()), dso_version: Cell::new(None), loaded: RefCell::new(HashMap::new()), failure: RefCell::new(None) };
+    }
+
+    pub(crate) fn file_system(&self) -> Option<SharedFileSystem>
+    {
+        #[cfg(feature="async")]
+        return self.file_system.read().unwrap().clone();
+
+        #[cfg(not(feature="async"))]
+        return self.file_system.borrow().clone();
+    }
+
+    fn dso_version(&self) -> Option<u32>
+    {
+        #[cfg(feature="async")]
+        return *self.dso_version.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        return self.dso_version.get();
+    }
+
+    /// A copy of the script compiled for the path, if it was compiled from the file as it is now.
+    fn cached(&self, path: &str, modified: SystemTime) -> Option<Result<CompiledScript<State>, &'static str>>
+    {
+        #[cfg(feature="async")]
+        let scripts_read = self.scripts.lock().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let scripts_read = self.scripts.borrow();
+
+        return scripts_read.get(path).filter(|cached| cached.modified == modified).map(|cached| cached.script.try_clone());
+    }
+
+    /// The files run so far, sorted by path.
+    pub(crate) fn loaded_paths(&self) -> Vec<String>
+    {
+        #[cfg(feature="async")]
+        let loaded_read = self.loaded.lock().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let loaded_read = self.loaded.borrow();
+
+        let mut paths: Vec<String> = loaded_read.keys().cloned().collect();
+        paths.sort();
+        return paths;
+    }
+
+    pub(crate) fn loaded(&self, path: &str) -> Option<LoadedScript>
+    {
+        #[cfg(feature="async")]
+        return self.loaded.lock().unwrap().get(path).cloned();
+
+        #[cfg(not(feature="async"))]
+        return self.loaded.borrow().get(path).cloned();
+    }
+
+    /// Records what a file declared. Functions it declares are no longer owned by whichever file declared them before.
+    pub(crate) fn record_loaded(&self, path: String, script: LoadedScript)
+    {
+        #[cfg(feature="async")]
+        let mut loaded_write = self.loaded.lock().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let mut loaded_write = self.loaded.borrow_mut();
+
+        for (other_path, other) in loaded_write.iter_mut()
+        {
+            if other_path != &path
+            {
+                other.disown(&script);
+            }
+        }
+        loaded_write.insert(path, script);
+    }
+
+    fn failure(&self) -> Option<ExecError>
+    {
+        #[cfg(feature="async")]
+        return self.failure.lock().unwrap().clone();
+
+        #[cfg(not(feature="async"))]
+        return self.failure.borrow().clone();
+    }
+
+    fn set_failure(&self, failure: Option<ExecError>)
+    {
+        #[cfg(feature="async")]
+        {
+            *self.failure.lock().unwrap() = failure;
+        }
+
+        #[cfg(not(feature="async"))]
+        {
+            *self.failure.borrow_mut() = failure;
+        }
+    }
+
+    fn store(&self, path: String, modified: SystemTime, script: CompiledScript<State>)
+    {
+        #[cfg(feature="async")]
+        self.scripts.lock().unwrap().insert(path, CachedScript { modified: modified, script: script });
+
+        #[cfg(not(feature="async"))]
+        self.scripts.borrow_mut().insert(path, CachedScript { modified: modified, script: script });
+    }
+}
+
+impl<State> VirtualMachine<'_, State> where State: Clone
+{
+    /// Sets the file system exec() reads scripts from, and makes exec() available to scripts.
+    /// Scripts compiled from the previous file system are forgotten.
+    pub fn set_file_system(&self, file_system: SharedFileSystem) -> Result<(), &'static str>
+    {
+        #[cfg(feature="async")]
+        {
+            *self.files.file_system.write().unwrap() = Some(file_system);
+            self.files.scripts.lock().unwrap().clear();
+        }
+
+        #[cfg(not(feature="async"))]
+        {
+            *self.files.file_system.borrow_mut() = Some(file_system);
+            self.files.scripts.borrow_mut().clear();
+        }
+
+        // As with eval(), the path is taken from the top of the caller's stack
+        return self.add_function(Function::NativeFunction {
+            parameters: vec!["path".to_owned()],
+            binding: Box::new(|vm, frame| -> NativeResult<State> {
+                let path = frame.stack.last().ok_or("exec() expects a Path")?.as_raw(vm, frame);
+
+                // Only the message makes it out of the script, so the file is kept for exec_failure. A failing
+                // nested exec records its file first, which the execs it unwinds through leave in place.
+                vm.files.set_failure(None);
+                vm.exec(&path.as_string(vm, frame)).map_err(|error| {
+                    if vm.files.failure().is_none()
+                    {
+                        vm.files.set_failure(Some(error.clone()));
+                    }
+                    error.message
+                })?;
+                Ok(None)
+            })
+        }, &["exec".to_owned()]);
+    }
+
+    /// Stores compiled scripts in .dso files next to their sources, e.g. "main.cs.dso", and loads them instead of
+    /// compiling while they match the source and compiler version. Bump the version whenever the compiler's output changes.
+    pub fn enable_dso_cache(&self, compiler_version: u32)
+    {
+        #[cfg(feature="async")]
+        {
+            *self.files.dso_version.write().unwrap() = Some(compiler_version);
+        }
+
+        #[cfg(not(feature="async"))]
+        self.files.dso_version.set(Some(compiler_version));
+    }
+
+    /// Compiles and runs a script file, see eval. The compiled script is reused until the file's modification time changes.
+    pub fn exec(&self, path: &str) -> Result<RawValue<State>, ExecError>
+    {
+        let fail = |message| ExecError { file: path.to_owned(), message: message };
+
+        let path = normalize_path(path).map_err(fail)?;
+        let file_system = self.files.file_system().ok_or_else(|| fail("No File System Registered"))?;
+        if !file_system.exists(&path)
+        {
+            return Err(fail("File Not Found"));
+        }
+
+        let modified = file_system.modified(&path);
+        let script = self.load_file(&file_system, &path, modified).map_err(fail)?;
+        self.files.record_loaded(path, LoadedScript::new(modified, &script));
+
+        return self.run_script(script).map_err(fail);
+    }
+
+    /// The last exec() called from script that failed, naming the file the failure happened in. Errors coming out
+    /// of script only carry the message; for nested execs this is the innermost file.
+    pub fn exec_failure(&self) -> Option<ExecError>
+    {
+        return self.files.failure();
+    }
+
+    /// Compiles a file, going through the compiled script caches.
+    pub(crate) fn load_file(&self, file_system: &SharedFileSystem, path: &str, modified: Option<SystemTime>) -> Result<CompiledScript<State>, &'static str>
+    {
+        if let Some(cached) = modified.and_then(|modified| self.files.cached(path, modified))
+        {
+            return cached;
+        }
+
+        let source = file_system.read(path)?;
+        let script = match self.files.dso_version() {
+            Some(version) => self.load_dso(file_system, path, &source, version)?,
+            None => self.compile(&source)?
+        };
+
+        // Scripts declaring native functions still run, they just aren't kept
+        if let Some(modified) = modified
+        {
+            if let Ok(copy) = script.try_clone()
+            {
+                self.files.store(path.to_owned(), modified, copy);
+            }
+        }
+
+        return Ok(script);
+    }
+
+    /// Writes an up to date .dso file for every script under a directory, so they never have to be compiled at
+    /// runtime. Produces how many scripts had to be compiled.
+    pub fn precompile_scripts(&self, directory: &str) -> Result<usize, ExecError>
+    {
+        let fail = |message| ExecError { file: directory.to_owned(), message: message };
+
+        let directory = normalize_path(directory).map_err(fail)?;
+        let file_system = self.files.file_system().ok_or_else(|| fail("No File System Registered"))?;
+        let version = self.files.dso_version().ok_or_else(|| fail("DSO Cache is Disabled"))?;
+
+        return self.precompile_directory(&file_system, &directory, version);
+    }
+
+    fn precompile_directory(&self, file_system: &SharedFileSystem, directory: &str, version: u32) -> Result<usize, ExecError>
+    {
+        let mut compiled = 0;
+        for name in file_system.list(directory).map_err(|message| ExecError { file: directory.to_owned(), message: message })?
+        {
+            let path = if directory.is_empty() { name.clone() } else { format!("{}/{}", directory, name) };
+
+            if name.to_lowercase().ends_with(".cs")
+            {
+                let fail = |message| ExecError { file: path.clone(), message: message };
+
+                let source = file_system.read(&path).map_err(fail)?;
+                if self.find_dso(file_system, &path, &source, version).is_none()
+                {
+                    let script = self.compile(&source).map_err(fail)?;
+                    self.store_dso(file_system, &path, &source, version, &script).map_err(fail)?;
+                    compiled += 1;
+                }
+            }
+            else if file_system.list(&path).is_ok()
+            {
+                compiled += self.precompile_directory(file_system, &path, version)?;
+            }
+        }
+
+        return Ok(compiled);
+    }
+
+    /// Loads the script's .dso file, compiling and rewriting it if it's missing or out of date.
+    fn load_dso(&self, file_system: &SharedFileSystem, path: &str, source: &str, version: u32) -> Result<CompiledScript<State>, &'static str>
+    {
+        if let Some(script) = self.find_dso(file_system, path, source, version)
+        {
+            return Ok(script);
+        }
+
+        let script = self.compile(source)?;
+
+        // Failing to write only costs the next run a compile, e.g. on a read only file system
+        let _ = self.store_dso(file_system, path, source, version, &script);
+        return Ok(script);
+    }
+
+    fn find_dso(&self, file_system: &SharedFileSystem, path: &str, source: &str, version: u32) -> Option<CompiledScript<State>>
+    {
+        let bytes = file_system.read_bytes(&format!("{}.dso", path)).ok()?;
+        return read_dso(&bytes, version, hash_source(source)).ok();
+    }
+
+    fn store_dso(&self, file_system: &SharedFileSystem, path: &str, source: &str, version: u32, script: &CompiledScript<State>) -> Result<(), &'static str>
+    {
+        let bytes = write_dso(script, version, hash_source(source))?;
+        return file_system.write(&format!("{}.dso", path), &bytes);
+    }
+}
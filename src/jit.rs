@@ -0,0 +1,619 @@
+use std::sync::{atomic::{AtomicU32, Ordering}, OnceLock};
+
+use crate::vm::{VirtualMachine, InstructionSequence, OpCode, RawValue, FloatValue, IntegerValue, StackFrame, LocalSlot, Comparison};
+use crate::passes::jump_target;
+
+/// Number of times a loop has to be entered before it is compiled
+pub const JIT_HOT_THRESHOLD: u32 = 16;
+
+/// Number of failed type guards after which a loop is left to the interpreter for good
+pub const JIT_MAX_DEOPTS: u32 = 8;
+
+/// Deepest operand stack a region may use. xmm0 up to this hold the stack, the rest are scratch.
+const MAX_DEPTH: usize = 14;
+const SCRATCH: u8 = 15;
+
+/// Signature of compiled code: local slot values as floats, a flag per slot set when it is written,
+/// and the index of the instruction to resume interpreting at as the result.
+type NativeEntry = unsafe extern "C" fn(*mut f32, *mut u8) -> u32;
+
+/// A loop the JIT can compile. It runs from start to end inclusive with nothing of its own on the stack
+/// at start, and only touches numeric locals, so a single type guard on entry covers the whole loop.
+pub struct Region
+{
+    pub start: usize,
+    pub end: usize,
+
+    /// Every slot the loop reads or writes
+    pub slots: Vec<LocalSlot>,
+
+    hits: AtomicU32,
+    deopts: AtomicU32,
+
+    /// None if the code could not be mapped
+    code: OnceLock<Option<NativeCode>>
+}
+
+/// Compilable loops of a sequence, found the first time a jump is taken with the JIT enabled.
+pub struct JitRegions
+{
+    pub regions: Vec<Region>,
+
+    /// Region starting at each instruction, if any
+    entries: Vec<Option<usize>>
+}
+
+impl JitRegions
+{
+    pub fn find<State>(sequence: &InstructionSequence<State>) -> Self where State: Clone
+    {
+        let ops = &sequence.ops;
+        let mut regions: Vec<Region> = Vec::new();
+        let mut entries: Vec<Option<usize>> = vec![None; ops.len()];
+
+        // Every backward jump closes a candidate loop. Check the widest first so an outer loop wins over the
+        // inner loops sharing its start.
+        let mut candidates: Vec<(usize, usize)> = ops.iter().enumerate()
+            .filter_map(|(index, op)| op.jump_address().map(|address| (jump_target(index, address), index)))
+            .filter(|(target, index)| target <= index)
+            .collect();
+        candidates.sort_by_key(|(start, end)| (*start, usize::MAX - *end));
+
+        for (start, end) in candidates
+        {
+            if entries[start].is_some()
+            {
+                continue;
+            }
+
+            if let Some(slots) = analyze(ops, start, end)
+            {
+                entries[start] = Some(regions.len());
+                regions.push(Region {
+                    start: start,
+                    end: end,
+                    slots: slots,
+                    hits: AtomicU32::new(0),
+                    deopts: AtomicU32::new(0),
+                    code: OnceLock::new()
+                });
+            }
+        }
+
+        return Self
+        {
+            regions: regions,
+            entries: entries
+        };
+    }
+
+    #[inline(always)]
+    pub fn at(&self, index: usize) -> Option<&Region>
+    {
+        return self.entries.get(index).copied().flatten().map(|region| &self.regions[region]);
+    }
+}
+
+impl Region
+{
+    /// Whether the loop has been compiled yet.
+    pub fn is_compiled(&self) -> bool
+    {
+        return matches!(self.code.get(), Some(Some(_)));
+    }
+
+    /// Runs the compiled loop if it is hot and the locals it uses are all numbers. Returns the index to
+    /// resume interpreting at, or None to interpret the loop instead.
+    fn enter<State>(&self, ops: &[OpCode<State>], frame: &mut StackFrame<State>) -> Option<usize> where State: Clone
+    {
+        if self.hits.load(Ordering::Relaxed) < JIT_HOT_THRESHOLD
+        {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        if self.deopts.load(Ordering::Relaxed) >= JIT_MAX_DEOPTS
+        {
+            return None;
+        }
+
+        let code = self.code.get_or_init(|| NativeCode::new(&assemble(ops, self.start, self.end)?)).as_ref()?;
+
+        // Type guard, the interpreter takes over if anything isn't a number
+        let mut values: Vec<f32> = vec![0.0; frame.slots.len()];
+        for slot in self.slots.iter()
+        {
+            let value = match frame.slots.get(*slot) {
+                Some(RawValue::Float { 0: FloatValue { value }}) => *value,
+                Some(RawValue::Integer { 0: IntegerValue { value }}) => *value as f32,
+                _ => {
+                    self.deopts.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+            };
+            values[*slot] = value;
+        }
+
+        let mut written: Vec<u8> = vec![0; frame.slots.len()];
+        let exit = unsafe { (code.entry())(values.as_mut_ptr(), written.as_mut_ptr()) };
+
+        // Everything the loop stores is the result of arithmetic, so written locals become floats
+        for slot in self.slots.iter()
+        {
+            if written[*slot] != 0
+            {
+                frame.slots[*slot] = RawValue::Float { 0: FloatValue { value: values[*slot] }};
+            }
+        }
+
+        return Some(exit as usize);
+    }
+}
+
+impl<State> InstructionSequence<State> where State: Clone
+{
+    /// Returns the compilable loops of this sequence, finding them the first time.
+    pub fn jit_regions(&self) -> &JitRegions
+    {
+        return self.jit.get_or_init(|| JitRegions::find(self));
+    }
+}
+
+impl<State> VirtualMachine<'_, State> where State: Clone
+{
+    /// Called by interpret when a jump lands on index. If a hot compiled loop starts there it is run
+    /// natively, and the index to carry on from is returned.
+    #[inline(always)]
+    pub(crate) fn jit_enter(&self, instructions: &InstructionSequence<State>, frame: &mut StackFrame<State>, index: usize) -> usize
+    {
+        return match instructions.jit_regions().at(index) {
+            Some(region) => region.enter(&instructions.ops, frame).unwrap_or(index),
+            None => index
+        };
+    }
+}
+
+/// What a simulated stack entry holds, as only computed floats may be stored to a local without
+/// changing its type.
+#[derive(Clone, Copy, PartialEq)]
+enum Entry
+{
+    /// Loaded from a local or a constant, and may be an integer to the interpreter
+    Number,
+
+    /// Result of float arithmetic
+    Computed,
+
+    /// Variable reference left behind by StoreLocal, which may only be popped
+    Reference
+}
+
+/// Numeric constant pushed by an op, if any.
+fn constant<State>(op: &OpCode<State>) -> Option<f32> where State: Clone
+{
+    return match op {
+        OpCode::PushInteger { value } => Some(*value as f32),
+        OpCode::PushFloat (value) => Some(value.value),
+        OpCode::PushConstant { value: RawValue::Integer { 0: IntegerValue { value }} } => Some(*value as f32),
+        OpCode::PushConstant { value: RawValue::Float { 0: FloatValue { value }} } => Some(*value),
+        _ => None
+    };
+}
+
+/// Comparison an op pushes, if it is one the JIT handles.
+fn comparison<State>(op: &OpCode<State>) -> Option<Comparison> where State: Clone
+{
+    return match op {
+        OpCode::LessThan {  } => Some(Comparison::LessThan),
+        OpCode::LessThanOrEqual {  } => Some(Comparison::LessThanOrEqual),
+        OpCode::GreaterThan {  } => Some(Comparison::GreaterThan),
+        OpCode::GreaterThanOrEqual {  } => Some(Comparison::GreaterThanOrEqual),
+        _ => None
+    };
+}
+
+/// Conditional jump following a comparison at index, as the value it jumps on. The jump must not be
+/// a target itself, as the comparison and jump are compiled together.
+fn fused_jump<State>(ops: &[OpCode<State>], index: usize, targets: &[usize]) -> Option<(bool, usize)> where State: Clone
+{
+    let next = index + 1;
+    if targets.contains(&next)
+    {
+        return None;
+    }
+
+    return match ops.get(next) {
+        Some(OpCode::JumpTrue { target }) => Some((true, jump_target(next, target))),
+        Some(OpCode::JumpFalse { target }) => Some((false, jump_target(next, target))),
+        _ => None
+    };
+}
+
+/// Checks every op from start to end can be compiled with the stack empty at each jump, and returns the
+/// slots touched if so.
+fn analyze<State>(ops: &[OpCode<State>], start: usize, end: usize) -> Option<Vec<LocalSlot>> where State: Clone
+{
+    let targets: Vec<usize> = (start ..= end).filter_map(|index| ops[index].jump_address().map(|address| jump_target(index, address))).collect();
+    let mut stack: Vec<Entry> = Vec::new();
+    let mut slots: Vec<LocalSlot> = Vec::new();
+    let mut touch = |slot: LocalSlot| if !slots.contains(&slot) { slots.push(slot); };
+
+    let mut index = start;
+    while index <= end
+    {
+        if targets.contains(&index) && !stack.is_empty()
+        {
+            return None;
+        }
+
+        let op = &ops[index];
+        if comparison(op).is_some()
+        {
+            fused_jump(ops, index, &targets)?;
+            if stack.len() != 2 || stack.contains(&Entry::Reference)
+            {
+                return None;
+            }
+            stack.clear();
+            index += 2;
+            continue;
+        }
+
+        match op {
+            OpCode::NOP {  } => {},
+            OpCode::LoadLocal { slot } => {
+                touch(*slot);
+                stack.push(Entry::Number);
+            },
+            OpCode::StoreLocal { slot } => {
+                if stack.pop()? != Entry::Computed
+                {
+                    return None;
+                }
+                touch(*slot);
+                stack.push(Entry::Reference);
+            },
+            OpCode::AssignLocalAndPop { slot } => {
+                if stack.pop()? != Entry::Computed
+                {
+                    return None;
+                }
+                touch(*slot);
+            },
+            OpCode::IncrementLocal { slot, amount: _ } => touch(*slot),
+            OpCode::Pop {  } => {
+                stack.pop()?;
+            },
+            OpCode::Add {  } | OpCode::Minus {  } | OpCode::Multiply {  } | OpCode::Divide {  } => {
+                let rhs = stack.pop()?;
+                let lhs = stack.pop()?;
+                if lhs == Entry::Reference || rhs == Entry::Reference
+                {
+                    return None;
+                }
+                stack.push(Entry::Computed);
+            },
+            OpCode::Negate {  } => {
+                if stack.pop()? == Entry::Reference
+                {
+                    return None;
+                }
+                stack.push(Entry::Computed);
+            },
+            OpCode::CompareAndJump { comparison: Comparison::Equals, jump_if: _, target: _ } |
+            OpCode::CompareAndJump { comparison: Comparison::NotEquals, jump_if: _, target: _ } => return None,
+            OpCode::CompareAndJump { comparison: _, jump_if: _, target: _ } => {
+                if stack.len() != 2 || stack.contains(&Entry::Reference)
+                {
+                    return None;
+                }
+                stack.clear();
+            },
+            OpCode::Jump { target: _ } => {
+                if !stack.is_empty()
+                {
+                    return None;
+                }
+            },
+            op if constant(op).is_some() => stack.push(Entry::Number),
+            _ => return None
+        }
+
+        if stack.len() > MAX_DEPTH
+        {
+            return None;
+        }
+        index += 1;
+    }
+
+    if !stack.is_empty()
+    {
+        return None;
+    }
+
+    return Some(slots);
+}
+
+/// Minimal x86_64 encoder for the handful of scalar SSE instructions the templates need.
+/// Slot values are addressed off rdi and written flags off rsi, as passed in by the caller.
+struct Assembler
+{
+    code: Vec<u8>
+}
+
+impl Assembler
+{
+    /// REX prefix for a register in the ModRM reg field and one in the rm field, if either needs it.
+    fn rex(&mut self, reg: u8, rm: u8)
+    {
+        let rex = 0x40 | ((reg >> 3) << 2) | (rm >> 3);
+        if rex != 0x40
+        {
+            self.code.push(rex);
+        }
+    }
+
+    fn disp32(&mut self, value: i32)
+    {
+        self.code.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// movss xmm, [rdi + slot * 4]
+    fn load_slot(&mut self, xmm: u8, slot: LocalSlot)
+    {
+        self.code.push(0xF3);
+        self.rex(xmm, 0);
+        self.code.extend_from_slice(&[0x0F, 0x10, 0x80 | ((xmm & 7) << 3) | 0x07]);
+        self.disp32((slot * 4) as i32);
+    }
+
+    /// movss [rdi + slot * 4], xmm ; mov byte [rsi + slot], 1
+    fn store_slot(&mut self, xmm: u8, slot: LocalSlot)
+    {
+        self.code.push(0xF3);
+        self.rex(xmm, 0);
+        self.code.extend_from_slice(&[0x0F, 0x11, 0x80 | ((xmm & 7) << 3) | 0x07]);
+        self.disp32((slot * 4) as i32);
+
+        self.code.extend_from_slice(&[0xC6, 0x86]);
+        self.disp32(slot as i32);
+        self.code.push(1);
+    }
+
+    /// mov eax, bits ; movd xmm, eax
+    fn load_constant(&mut self, xmm: u8, value: f32)
+    {
+        self.code.push(0xB8);
+        self.code.extend_from_slice(&value.to_bits().to_le_bytes());
+
+        self.code.push(0x66);
+        self.rex(xmm, 0);
+        self.code.extend_from_slice(&[0x0F, 0x6E, 0xC0 | ((xmm & 7) << 3)]);
+    }
+
+    /// Scalar single precision op between two registers, dest = dest op source
+    fn arithmetic(&mut self, opcode: u8, dest: u8, source: u8)
+    {
+        self.code.push(0xF3);
+        self.rex(dest, source);
+        self.code.extend_from_slice(&[0x0F, opcode, 0xC0 | ((dest & 7) << 3) | (source & 7)]);
+    }
+
+    /// xorps dest, source
+    fn xor(&mut self, dest: u8, source: u8)
+    {
+        self.rex(dest, source);
+        self.code.extend_from_slice(&[0x0F, 0x57, 0xC0 | ((dest & 7) << 3) | (source & 7)]);
+    }
+
+    /// ucomiss lhs, rhs
+    fn compare(&mut self, lhs: u8, rhs: u8)
+    {
+        self.rex(lhs, rhs);
+        self.code.extend_from_slice(&[0x0F, 0x2E, 0xC0 | ((lhs & 7) << 3) | (rhs & 7)]);
+    }
+
+    /// Emits a jcc or jmp with a placeholder offset, returning where the offset goes.
+    fn jump(&mut self, condition: Option<u8>) -> usize
+    {
+        match condition {
+            Some(condition) => self.code.extend_from_slice(&[0x0F, condition]),
+            None => self.code.push(0xE9)
+        }
+        self.disp32(0);
+        return self.code.len() - 4;
+    }
+
+    /// mov eax, index ; ret
+    fn exit(&mut self, index: usize)
+    {
+        self.code.push(0xB8);
+        self.code.extend_from_slice(&(index as u32).to_le_bytes());
+        self.code.push(0xC3);
+    }
+
+    fn patch(&mut self, at: usize, destination: usize)
+    {
+        let offset = destination as i32 - (at as i32 + 4);
+        self.code[at .. at + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+}
+
+const ADDSS: u8 = 0x58;
+const MULSS: u8 = 0x59;
+const SUBSS: u8 = 0x5C;
+const DIVSS: u8 = 0x5E;
+
+const JA: u8 = 0x87;
+const JAE: u8 = 0x83;
+const JB: u8 = 0x82;
+const JBE: u8 = 0x86;
+
+/// Emits a comparison of the top two stack registers and a jump taken when it comes out as jump_if.
+/// ucomiss leaves CF and ZF set when either side is NaN, so the "true" conditions below are false for NaN
+/// just like the float comparisons in the interpreter, and their inverses are true.
+fn compare_and_jump(assembler: &mut Assembler, comparison: Comparison, jump_if: bool, lhs: u8, rhs: u8) -> usize
+{
+    let condition = match comparison {
+        Comparison::GreaterThan => { assembler.compare(lhs, rhs); if jump_if { JA } else { JBE } },
+        Comparison::GreaterThanOrEqual => { assembler.compare(lhs, rhs); if jump_if { JAE } else { JB } },
+        Comparison::LessThan => { assembler.compare(rhs, lhs); if jump_if { JA } else { JBE } },
+        Comparison::LessThanOrEqual => { assembler.compare(rhs, lhs); if jump_if { JAE } else { JB } },
+        Comparison::Equals | Comparison::NotEquals => unreachable!("Rejected by analyze")
+    };
+    return assembler.jump(Some(condition));
+}
+
+/// Translates a region already checked by analyze into machine code, one template per op.
+fn assemble<State>(ops: &[OpCode<State>], start: usize, end: usize) -> Option<Vec<u8>> where State: Clone
+{
+    let targets: Vec<usize> = (start ..= end).filter_map(|index| ops[index].jump_address().map(|address| jump_target(index, address))).collect();
+    let mut assembler = Assembler { code: Vec::with_capacity(64 * (end - start + 1)) };
+
+    let mut labels: Vec<Option<usize>> = vec![None; end - start + 1];
+    let mut jumps: Vec<(usize, usize)> = Vec::new();
+    let mut depth: u8 = 0;
+
+    let mut index = start;
+    while index <= end
+    {
+        labels[index - start] = Some(assembler.code.len());
+        let op = &ops[index];
+
+        if let Some(comparison) = comparison(op)
+        {
+            let (jump_if, target) = fused_jump(ops, index, &targets)?;
+            jumps.push((compare_and_jump(&mut assembler, comparison, jump_if, depth - 2, depth - 1), target));
+            depth = 0;
+            index += 2;
+            continue;
+        }
+
+        match op {
+            OpCode::NOP {  } => {},
+            OpCode::LoadLocal { slot } => {
+                assembler.load_slot(depth, *slot);
+                depth += 1;
+            },
+            OpCode::StoreLocal { slot } => assembler.store_slot(depth - 1, *slot),
+            OpCode::AssignLocalAndPop { slot } => {
+                assembler.store_slot(depth - 1, *slot);
+                depth -= 1;
+            },
+            OpCode::IncrementLocal { slot, amount } => {
+                assembler.load_slot(SCRATCH - 1, *slot);
+                assembler.load_constant(SCRATCH, *amount);
+                assembler.arithmetic(ADDSS, SCRATCH - 1, SCRATCH);
+                assembler.store_slot(SCRATCH - 1, *slot);
+            },
+            OpCode::Pop {  } => depth -= 1,
+            OpCode::Add {  } | OpCode::Minus {  } | OpCode::Multiply {  } | OpCode::Divide {  } => {
+                let opcode = match op {
+                    OpCode::Add {  } => ADDSS,
+                    OpCode::Minus {  } => SUBSS,
+                    OpCode::Multiply {  } => MULSS,
+                    _ => DIVSS
+                };
+                assembler.arithmetic(opcode, depth - 2, depth - 1);
+                depth -= 1;
+            },
+            OpCode::Negate {  } => {
+                // Flip the sign bit, as -x does
+                assembler.load_constant(SCRATCH, -0.0);
+                assembler.xor(depth - 1, SCRATCH);
+            },
+            OpCode::CompareAndJump { comparison, jump_if, target } => {
+                jumps.push((compare_and_jump(&mut assembler, *comparison, *jump_if, depth - 2, depth - 1), jump_target(index, target)));
+                depth = 0;
+            },
+            OpCode::Jump { target } => jumps.push((assembler.jump(None), jump_target(index, target))),
+            op => {
+                assembler.load_constant(depth, constant(op)?);
+                depth += 1;
+            }
+        }
+        index += 1;
+    }
+
+    // Falling off the end of the loop
+    assembler.exit(end + 1);
+
+    // Jumps out of the loop go through a stub returning where to resume
+    let mut exits: Vec<(usize, usize)> = Vec::new();
+    for (at, target) in jumps
+    {
+        let destination = match (target >= start && target <= end).then(|| labels[target - start]).flatten() {
+            Some(label) => label,
+            None => match exits.iter().find(|(exit_target, _)| *exit_target == target) {
+                Some((_, label)) => *label,
+                None => {
+                    let label = assembler.code.len();
+                    assembler.exit(target);
+                    exits.push((target, label));
+                    label
+                }
+            }
+        };
+        assembler.patch(at, destination);
+    }
+
+    return Some(assembler.code);
+}
+
+/// Executable copy of generated code.
+struct NativeCode
+{
+    memory: *mut libc::c_void,
+    length: usize
+}
+
+// The mapping is immutable once created
+unsafe impl Send for NativeCode {}
+unsafe impl Sync for NativeCode {}
+
+impl NativeCode
+{
+    #[cfg(all(target_arch="x86_64", target_os="linux"))]
+    fn new(code: &[u8]) -> Option<Self>
+    {
+        unsafe {
+            let memory = libc::mmap(std::ptr::null_mut(), code.len(), libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0);
+            if memory == libc::MAP_FAILED
+            {
+                return None;
+            }
+
+            std::ptr::copy_nonoverlapping(code.as_ptr(), memory as *mut u8, code.len());
+            if libc::mprotect(memory, code.len(), libc::PROT_READ | libc::PROT_EXEC) != 0
+            {
+                libc::munmap(memory, code.len());
+                return None;
+            }
+
+            return Some(Self { memory: memory, length: code.len() });
+        }
+    }
+
+    /// The templates are x86_64 only, so other targets always interpret
+    #[cfg(not(all(target_arch="x86_64", target_os="linux")))]
+    fn new(_code: &[u8]) -> Option<Self>
+    {
+        return None;
+    }
+
+    fn entry(&self) -> NativeEntry
+    {
+        return unsafe { std::mem::transmute::<*mut libc::c_void, NativeEntry>(self.memory) };
+    }
+}
+
+impl Drop for NativeCode
+{
+    fn drop(&mut self)
+    {
+        #[cfg(all(target_arch="x86_64", target_os="linux"))]
+        unsafe {
+            libc::munmap(self.memory, self.length);
+        }
+    }
+}
@@ -1,4 +1,26 @@
+// The crate name predates the snake case convention
+#![allow(non_snake_case)]
+
+// Explicit returns and field initializers are the house style
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::init_numbered_fields)]
+
 pub mod util;
+pub mod tagged_strings;
 pub mod vm;
+pub mod passes;
+pub mod threaded;
+pub mod binding;
+pub mod watch;
+pub mod compiler;
+pub mod filesystem;
+pub mod dso;
+pub mod reload;
+
+#[cfg(feature="jit")]
+pub mod jit;
+
+#[cfg(feature="register-vm")]
+pub mod register_vm;
+
 pub mod tests;
 pub mod ast;
\ No newline at end of file
@@ -0,0 +1,670 @@
+use std::{collections::{HashMap, HashSet}, marker::PhantomData, sync::Arc};
+
+use crate::vm::{
+    InstructionSequence, OpCode, VariableReference, VariableIdentifier, LocalSlot, AddressValue, VirtualMachine, CallSite, SharedString,
+    Comparison, RawValue, FloatValue, IntegerValue, StringValue, BooleanValue, StackFrame, process_address
+};
+
+/// Index of the instruction a jump lands on.
+#[inline(always)]
+pub(crate) fn jump_target(index: usize, address: &AddressValue) -> usize
+{
+    let mut target = index + 1;
+    process_address(&mut target, address);
+    return target;
+}
+
+/// Number of values an op pops and pushes when it falls through to the next instruction.
+/// Swap and Assignment are handled by the callers as they care which value ends up where.
+pub(crate) fn stack_effect<State>(op: &OpCode<State>) -> (usize, usize) where State: Clone
+{
+    return match op {
+        OpCode::PushFloat (_) | OpCode::PushInteger { value: _ } | OpCode::PushString { value: _ } | OpCode::PushSharedString { value: _ } |
+        OpCode::PushConstant { value: _ } |
+        OpCode::PushVariable { variable: _ } | OpCode::PushTaggedString { value: _ } | OpCode::LoadLocal { slot: _ } => (0, 1),
+
+        OpCode::Pop {  } | OpCode::JumpTrue { target: _ } | OpCode::JumpFalse { target: _ } |
+        OpCode::JumpTrueOrPop { target: _ } | OpCode::JumpFalseOrPop { target: _ } | OpCode::AssignLocalAndPop { slot: _ } |
+        OpCode::Return {  } => (1, 0),
+
+        OpCode::AssignAndPop {  } | OpCode::CompareAndJump { comparison: _, jump_if: _, target: _ } => (2, 0),
+
+        OpCode::NOP {  } | OpCode::Jump { target: _ } | OpCode::CallFunction { target: _ } | OpCode::CallCached { site: _ } |
+        OpCode::IncrementLocal { slot: _, amount: _ } => (0, 0),
+
+        OpCode::Swap {  } => (2, 2),
+
+        OpCode::Negate {  } | OpCode::Not {  } | OpCode::ToBoolean {  } | OpCode::BitwiseNot {  } |
+        OpCode::OnesComplement {  } | OpCode::Detag {  } | OpCode::GetTaggedString {  } | OpCode::StoreLocal { slot: _ } => (1, 1),
+
+        OpCode::Assignment {  } | OpCode::Concat {  } | OpCode::ConcatSeparator { separator: _ } |
+        OpCode::LogicalAnd {  } | OpCode::LogicalOr {  } | OpCode::BitwiseAnd {  } | OpCode::BitwiseOr {  } |
+        OpCode::BitwiseXor {  } | OpCode::ShiftLeft {  } | OpCode::ShiftRight {  } | OpCode::Add {  } |
+        OpCode::Minus {  } | OpCode::Modulus {  } | OpCode::Multiply {  } | OpCode::Divide {  } |
+        OpCode::IntegerDivide {  } | OpCode::LessThan {  } | OpCode::LessThanOrEqual {  } | OpCode::GreaterThan {  } |
+        OpCode::GreaterThanOrEqual {  } | OpCode::Equals {  } | OpCode::NotEquals {  } | OpCode::StringEquals {  } |
+        OpCode::StringNotEqual {  } => (2, 1)
+    };
+}
+
+/// Indices of every instruction that is the target of a jump.
+pub(crate) fn collect_jump_targets<State>(instructions: &InstructionSequence<State>) -> HashSet<usize> where State: Clone
+{
+    let mut targets: HashSet<usize> = HashSet::new();
+    for (index, op) in instructions.ops.iter().enumerate()
+    {
+        if let Some(target) = op.jump_address()
+        {
+            targets.insert(jump_target(index, target));
+        }
+    }
+    return targets;
+}
+
+impl<State> InstructionSequence<State> where State: Clone
+{
+    /// Runs every load time pass in order. Functions and script bodies both go through this as they are loaded,
+    /// so code behaves the same however it reached the VM.
+    pub(crate) fn prepare(&mut self, vm: &VirtualMachine<State>)
+    {
+        self.fold_constants(vm);
+        self.eliminate_dead_code();
+        self.resolve_global_handles(vm);
+        self.resolve_local_slots();
+        self.resolve_call_sites();
+        self.intern_string_constants();
+
+        #[cfg(feature="peephole")]
+        if vm.peephole()
+        {
+            self.optimize();
+        }
+    }
+
+    /// Assigns every local named in the sequence a dense slot so the interpreter can skip the hash lookup.
+    ///
+    /// Reads become LoadLocal, and `PushVariable %x ... Assignment` pairs become `NOP ... StoreLocal`.
+    /// Instructions are only ever replaced one for one, so jump targets are unaffected. A local whose
+    /// push can't be matched to its consumer, e.g. because it is still on the stack at a jump, keeps a
+    /// lazy reference to its slot instead. Names built at runtime are routed to the slot by the frame.
+    ///
+    /// Safe to call more than once; returns the number of slots.
+    pub fn resolve_local_slots(&mut self) -> usize
+    {
+        self.discard_compiled();
+        let mut slots: Vec<VariableIdentifier> = self.local_slots.as_ref().clone();
+        let mut slot_lookup: HashMap<VariableIdentifier, LocalSlot> = slots.iter().enumerate().map(|(slot, identifier)| (*identifier, slot)).collect();
+
+        let targets = collect_jump_targets(self);
+
+        // For each simulated stack entry, the index of the local push that produced it
+        let mut stack: Vec<Option<usize>> = Vec::new();
+        let mut reads: Vec<usize> = Vec::new();
+        let mut stores: Vec<(usize, usize)> = Vec::new();
+
+        for (index, op) in self.ops.iter().enumerate()
+        {
+            // Other paths may reach a label with different values on the stack
+            if targets.contains(&index)
+            {
+                for entry in stack.iter_mut()
+                {
+                    *entry = None;
+                }
+            }
+
+            match op {
+                OpCode::PushVariable { variable: VariableReference::Local { value, phantom: _ } } => {
+                    if !slot_lookup.contains_key(value)
+                    {
+                        slot_lookup.insert(*value, slots.len());
+                        slots.push(*value);
+                    }
+                    stack.push(Some(index));
+                },
+                OpCode::Assignment {  } => {
+                    if let Some(Some(value_push)) = stack.pop()
+                    {
+                        reads.push(value_push);
+                    }
+                    if let Some(Some(variable_push)) = stack.pop()
+                    {
+                        stores.push((variable_push, index));
+                    }
+                    stack.push(None);
+                },
+                OpCode::AssignAndPop {  } => {
+                    if let Some(Some(value_push)) = stack.pop()
+                    {
+                        reads.push(value_push);
+                    }
+                    if let Some(Some(variable_push)) = stack.pop()
+                    {
+                        stores.push((variable_push, index));
+                    }
+                },
+                OpCode::Swap {  } => {
+                    let depth = stack.len();
+                    if depth >= 2
+                    {
+                        stack.swap(depth - 1, depth - 2);
+                    }
+                },
+                _ => {
+                    let (pops, pushes) = stack_effect(op);
+                    for _ in 0 .. pops
+                    {
+                        if let Some(Some(push)) = stack.pop()
+                        {
+                            reads.push(push);
+                        }
+                    }
+                    for _ in 0 .. pushes
+                    {
+                        stack.push(None);
+                    }
+                }
+            }
+
+            // Whatever is left on the stack is carried to the jump target, where its consumer is unknown
+            if op.jump_address().is_some()
+            {
+                for entry in stack.iter_mut()
+                {
+                    *entry = None;
+                }
+            }
+        }
+
+        let slot_of = |op: &OpCode<State>| -> LocalSlot {
+            return match op {
+                OpCode::PushVariable { variable: VariableReference::Local { value, phantom: _ } } => slot_lookup[value],
+                _ => unreachable!()
+            };
+        };
+
+        for push in reads
+        {
+            let slot = slot_of(&self.ops[push]);
+            self.ops[push] = OpCode::LoadLocal { slot: slot };
+        }
+
+        for (push, assignment) in stores
+        {
+            let slot = slot_of(&self.ops[push]);
+            self.ops[push] = OpCode::NOP {  };
+            self.ops[assignment] = match self.ops[assignment] {
+                OpCode::AssignAndPop {  } => OpCode::AssignLocalAndPop { slot: slot },
+                _ => OpCode::StoreLocal { slot: slot }
+            };
+        }
+
+        // Anything left still needs to behave as a variable, but can at least skip the hash
+        for op in self.ops.iter_mut()
+        {
+            if let OpCode::PushVariable { variable: VariableReference::Local { value, phantom: _ } } = op
+            {
+                let slot = slot_lookup[value];
+                *op = OpCode::PushVariable { variable: VariableReference::LocalSlot { slot: slot, phantom: PhantomData } };
+            }
+        }
+
+        self.local_slots = Arc::new(slots);
+        return self.local_slots.len();
+    }
+
+    /// Binds every global named in the sequence to its storage in the given VM, creating unassigned entries as needed.
+    /// The rewritten sequence reads and writes globals without going through the globals table, so it must only be
+    /// run on that VM. Returns the number of references resolved.
+    pub fn resolve_global_handles(&mut self, vm: &VirtualMachine<State>) -> usize
+    {
+        self.discard_compiled();
+        let mut resolved: usize = 0;
+
+        for op in self.ops.iter_mut()
+        {
+            if let OpCode::PushVariable { variable: VariableReference::Global { value, name, phantom: _ } } = op
+            {
+                let handle = vm.global_handle(*value, name);
+                *op = OpCode::PushVariable { variable: VariableReference::GlobalHandle { handle: handle } };
+                resolved += 1;
+            }
+        }
+
+        return resolved;
+    }
+
+    /// Hashes every call target up front and gives each call instruction its own inline cache.
+    /// Returns the number of call sites created.
+    pub fn resolve_call_sites(&mut self) -> usize
+    {
+        self.discard_compiled();
+        let mut resolved: usize = 0;
+
+        for op in self.ops.iter_mut()
+        {
+            if let OpCode::CallFunction { target } = op
+            {
+                *op = OpCode::CallCached { site: CallSite::new(std::mem::take(target)) };
+                resolved += 1;
+            }
+        }
+
+        return resolved;
+    }
+
+    /// Moves string constants into shared storage so pushing one no longer allocates. Identical constants
+    /// share one allocation. Returns the number of constants interned.
+    pub fn intern_string_constants(&mut self) -> usize
+    {
+        self.discard_compiled();
+        let mut interned: HashMap<String, SharedString> = HashMap::new();
+        let mut resolved: usize = 0;
+
+        for op in self.ops.iter_mut()
+        {
+            if let OpCode::PushString { value } = op
+            {
+                let shared = interned.entry(std::mem::take(value)).or_insert_with_key(|key| Arc::new(key.clone())).clone();
+                *op = OpCode::PushSharedString { value: shared };
+                resolved += 1;
+            }
+        }
+
+        return resolved;
+    }
+
+    /// Peephole pass: fuses common runs of ops into superinstructions and strips NOPs, remapping every jump
+    /// to the new layout. Runs never span a jump target, so control flow is unchanged. Best run after
+    /// resolve_local_slots, as most of the patterns are on pre-resolved locals. Returns the number of ops removed.
+    pub fn optimize(&mut self) -> usize
+    {
+        return self.rewrite(true);
+    }
+
+    /// Evaluates operations whose operands are all constants at load time, using the same coercions as the
+    /// interpreter, and resolves conditional jumps on constant conditions. Folded ops become PushConstant and
+    /// the pushes they consumed become NOPs. Tagged strings are left alone as they depend on the string table.
+    /// Returns the number of ops folded.
+    pub fn fold_constants(&mut self, vm: &VirtualMachine<State>) -> usize
+    {
+        self.discard_compiled();
+        let frame: StackFrame<State> = StackFrame::new();
+        let targets = collect_jump_targets(self);
+
+        // For each simulated stack entry, the index of the op pushing it if that is a constant
+        let mut stack: Vec<Option<usize>> = Vec::new();
+        let mut folded: usize = 0;
+
+        for index in 0 .. self.ops.len()
+        {
+            if targets.contains(&index)
+            {
+                for entry in stack.iter_mut()
+                {
+                    *entry = None;
+                }
+            }
+
+            let (pops, pushes) = match &self.ops[index] {
+                OpCode::Swap {  } => {
+                    let depth = stack.len();
+                    if depth >= 2
+                    {
+                        stack.swap(depth - 1, depth - 2);
+                    }
+                    continue;
+                },
+                op => stack_effect(op)
+            };
+
+            // Constant operands, in push order
+            let depth = stack.len();
+            let operands: Option<Vec<usize>> = if pops > 0 && depth >= pops { stack[depth - pops ..].iter().copied().collect() } else { None };
+
+            if let Some(operands) = operands
+            {
+                let values: Vec<RawValue<State>> = operands.iter().map(|push| constant_value(&self.ops[*push]).unwrap()).collect();
+
+                let op = self.ops[index].clone();
+                let replacement = match (&op, values.as_slice()) {
+                    (OpCode::JumpTrue { target }, [condition]) | (OpCode::JumpFalse { target }, [condition]) => {
+                        let jump_if = matches!(op, OpCode::JumpTrue { target: _ });
+                        let jumps = condition.as_boolean(vm, &frame) == jump_if;
+                        Some(if jumps { OpCode::Jump { target: target.clone() } } else { OpCode::NOP {  } })
+                    },
+                    (OpCode::JumpTrueOrPop { target }, [condition]) | (OpCode::JumpFalseOrPop { target }, [condition]) => {
+                        let jump_if = matches!(op, OpCode::JumpTrueOrPop { target: _ });
+                        if condition.as_boolean(vm, &frame) == jump_if
+                        {
+                            // The jump leaves the normalized condition behind, so keep one push and jump unconditionally
+                            self.ops[operands[0]] = OpCode::PushConstant { value: RawValue::Boolean { 0: BooleanValue { value: jump_if }}};
+                            self.ops[index] = OpCode::Jump { target: target.clone() };
+                            stack.clear();
+                            folded += 1;
+                            continue;
+                        }
+                        Some(OpCode::NOP {  })
+                    },
+                    (op, [value]) => evaluate_unary(op, value, vm, &frame).map(|value| OpCode::PushConstant { value: value }),
+                    (op, [lhs, rhs]) => evaluate_binary(op, lhs, rhs, vm, &frame).map(|value| OpCode::PushConstant { value: value }),
+                    _ => None
+                };
+
+                if let Some(replacement) = replacement
+                {
+                    for push in operands.iter()
+                    {
+                        self.ops[*push] = OpCode::NOP {  };
+                    }
+
+                    let pushes_constant = matches!(replacement, OpCode::PushConstant { value: _ });
+                    let jumps = replacement.jump_address().is_some();
+                    self.ops[index] = replacement;
+                    stack.truncate(depth - pops);
+                    if pushes_constant
+                    {
+                        stack.push(Some(index));
+                    }
+                    else if jumps
+                    {
+                        // Nothing falls through an unconditional jump
+                        stack.clear();
+                    }
+                    folded += 1;
+                    continue;
+                }
+            }
+
+            for _ in 0 .. pops
+            {
+                stack.pop();
+            }
+            for _ in 0 .. pushes
+            {
+                stack.push(if constant_value(&self.ops[index]).is_some() { Some(index) } else { None });
+            }
+
+            // Whatever is left on the stack is carried to the jump target
+            if self.ops[index].jump_address().is_some()
+            {
+                for entry in stack.iter_mut()
+                {
+                    *entry = None;
+                }
+            }
+        }
+
+        return folded;
+    }
+
+    /// Threads jumps that land on unconditional jumps straight to their destination, drops jumps to the
+    /// next instruction and removes code no path can reach. Returns the number of ops removed.
+    pub fn eliminate_dead_code(&mut self) -> usize
+    {
+        self.discard_compiled();
+        let op_count = self.ops.len();
+
+        // Jumps to jumps
+        for index in 0 .. op_count
+        {
+            let mut target = match self.ops[index].jump_address() {
+                Some(address) => jump_target(index, address),
+                None => continue
+            };
+
+            // Bounded so a cycle of jumps can't hang the pass
+            let mut hops: usize = 0;
+            while hops < op_count
+            {
+                match self.ops.get(target) {
+                    Some(OpCode::Jump { target: next }) => target = jump_target(target, next),
+                    _ => break
+                }
+                hops += 1;
+            }
+
+            let address = self.ops[index].jump_address_mut().unwrap();
+            *address = retarget(address, index, target.min(op_count));
+        }
+
+        // Reachability from the entry point
+        let mut reachable: Vec<bool> = vec![false; op_count];
+        let mut pending: Vec<usize> = vec![0];
+        while let Some(index) = pending.pop()
+        {
+            if index >= op_count || reachable[index]
+            {
+                continue;
+            }
+            reachable[index] = true;
+
+            let op = &self.ops[index];
+            if let Some(address) = op.jump_address()
+            {
+                pending.push(jump_target(index, address));
+            }
+            if !matches!(op, OpCode::Jump { target: _ } | OpCode::Return {  })
+            {
+                pending.push(index + 1);
+            }
+        }
+
+        for (index, op) in self.ops.iter_mut().enumerate()
+        {
+            if !reachable[index]
+            {
+                *op = OpCode::NOP {  };
+            }
+        }
+
+        // Unconditional jumps that only skip NOPs
+        for index in 0 .. op_count
+        {
+            if let OpCode::Jump { target } = &self.ops[index]
+            {
+                let target = jump_target(index, target);
+                if target > index && self.ops[index + 1 .. target.min(op_count)].iter().all(|op| matches!(op, OpCode::NOP {  }))
+                {
+                    self.ops[index] = OpCode::NOP {  };
+                }
+            }
+        }
+
+        return self.rewrite(false);
+    }
+
+    /// Rebuilds the sequence without NOPs, optionally fusing superinstructions, and remaps every jump.
+    fn rewrite(&mut self, fuse_patterns: bool) -> usize
+    {
+        self.discard_compiled();
+        let targets = collect_jump_targets(self);
+        let old_count = self.ops.len();
+
+        // Where each old op ended up; removed ops map to whatever follows them
+        let mut new_index_of: Vec<usize> = vec![0; old_count + 1];
+        let mut optimized: Vec<OpCode<State>> = Vec::with_capacity(old_count);
+
+        // Old index each new op's jump address is relative to
+        let mut jump_origins: Vec<usize> = Vec::with_capacity(old_count);
+
+        let mut index: usize = 0;
+        while index < old_count
+        {
+            new_index_of[index] = optimized.len();
+
+            if let OpCode::NOP {  } = self.ops[index]
+            {
+                index += 1;
+                continue;
+            }
+
+            if let Some((fused, length)) = fuse(&self.ops[index ..]).filter(|_| fuse_patterns)
+            {
+                if !(index + 1 .. index + length).any(|inner| targets.contains(&inner))
+                {
+                    let fused_index = optimized.len();
+                    new_index_of[index + 1 .. index + length].fill(fused_index);
+
+                    // A fused jump is always the last op of its run
+                    jump_origins.push(index + length - 1);
+                    optimized.push(fused);
+                    index += length;
+                    continue;
+                }
+            }
+
+            jump_origins.push(index);
+            optimized.push(self.ops[index].clone());
+            index += 1;
+        }
+        new_index_of[old_count] = optimized.len();
+
+        for (new_index, op) in optimized.iter_mut().enumerate()
+        {
+            let origin = jump_origins[new_index];
+            if let Some(address) = op.jump_address_mut()
+            {
+                let new_target = new_index_of[jump_target(origin, address).min(old_count)];
+                *address = retarget(address, new_index, new_target);
+            }
+        }
+
+        self.ops = optimized;
+        return old_count - self.ops.len();
+    }
+}
+
+/// Encodes a jump from the op at index to target, keeping the kind of address it had.
+fn retarget(address: &AddressValue, index: usize, target: usize) -> AddressValue
+{
+    return match address {
+        AddressValue::AbsoluteTarget { index: _ } => AddressValue::AbsoluteTarget { index: target },
+        AddressValue::RelativeOffset { offset: _ } => AddressValue::RelativeOffset { offset: target as i32 - (index as i32 + 1) }
+    };
+}
+
+/// Numeric constant pushed by an op, if any.
+fn constant_amount<State>(op: &OpCode<State>) -> Option<f32> where State: Clone
+{
+    return match op {
+        OpCode::PushInteger { value } => Some(*value as f32),
+        OpCode::PushFloat (value) => Some(value.value),
+        OpCode::PushConstant { value: RawValue::Integer { 0: IntegerValue { value }} } => Some(*value as f32),
+        OpCode::PushConstant { value: RawValue::Float { 0: FloatValue { value }} } => Some(*value),
+        _ => None
+    };
+}
+
+fn comparison_of<State>(op: &OpCode<State>) -> Option<Comparison> where State: Clone
+{
+    return match op {
+        OpCode::LessThan {  } => Some(Comparison::LessThan),
+        OpCode::LessThanOrEqual {  } => Some(Comparison::LessThanOrEqual),
+        OpCode::GreaterThan {  } => Some(Comparison::GreaterThan),
+        OpCode::GreaterThanOrEqual {  } => Some(Comparison::GreaterThanOrEqual),
+        OpCode::Equals {  } => Some(Comparison::Equals),
+        OpCode::NotEquals {  } => Some(Comparison::NotEquals),
+        _ => None
+    };
+}
+
+/// Matches a superinstruction pattern at the start of the ops, returning it and the number of ops it replaces.
+fn fuse<State>(ops: &[OpCode<State>]) -> Option<(OpCode<State>, usize)> where State: Clone
+{
+    // %x = %x + n and %x = %x - n, with the result discarded
+    if let [OpCode::LoadLocal { slot }, constant, operator, OpCode::StoreLocal { slot: store_slot }, OpCode::Pop {  }, ..] = ops
+    {
+        let sign = match operator {
+            OpCode::Add {  } => Some(1.0),
+            OpCode::Minus {  } => Some(-1.0),
+            _ => None
+        };
+
+        if let (true, Some(amount), Some(sign)) = (slot == store_slot, constant_amount(constant), sign)
+        {
+            return Some((OpCode::IncrementLocal { slot: *slot, amount: amount * sign }, 5));
+        }
+    }
+
+    return match ops {
+        [OpCode::StoreLocal { slot }, OpCode::Pop {  }, ..] => Some((OpCode::AssignLocalAndPop { slot: *slot }, 2)),
+        [OpCode::Assignment {  }, OpCode::Pop {  }, ..] => Some((OpCode::AssignAndPop {  }, 2)),
+        [comparison, OpCode::JumpTrue { target }, ..] => comparison_of(comparison).map(|comparison| {
+            (OpCode::CompareAndJump { comparison: comparison, jump_if: true, target: target.clone() }, 2)
+        }),
+        [comparison, OpCode::JumpFalse { target }, ..] => comparison_of(comparison).map(|comparison| {
+            (OpCode::CompareAndJump { comparison: comparison, jump_if: false, target: target.clone() }, 2)
+        }),
+        _ => None
+    };
+}
+
+/// Value pushed by an op, if it only ever pushes that constant.
+fn constant_value<State>(op: &OpCode<State>) -> Option<RawValue<State>> where State: Clone
+{
+    return match op {
+        OpCode::PushFloat (value) => Some(RawValue::Float { 0: FloatValue { value: value.value }}),
+        OpCode::PushInteger { value } => Some(RawValue::Integer { 0: IntegerValue { value: *value }}),
+        OpCode::PushString { value } => Some(RawValue::String { 0: StringValue { value: Arc::new(value.clone()) }}),
+        OpCode::PushSharedString { value } => Some(RawValue::String { 0: StringValue { value: value.clone() }}),
+        OpCode::PushConstant { value } => Some(value.clone()),
+        _ => None
+    };
+}
+
+/// Mirrors the interpreter's unary ops. None if the op isn't foldable.
+fn evaluate_unary<State>(op: &OpCode<State>, value: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> Option<RawValue<State>> where State: Clone
+{
+    return match op {
+        OpCode::Negate {  } => Some(RawValue::Float { 0: FloatValue { value: value.negate(vm, frame) }}),
+        OpCode::Not {  } => Some(RawValue::Boolean { 0: BooleanValue { value: !value.as_boolean(vm, frame) }}),
+        OpCode::ToBoolean {  } => Some(RawValue::Boolean { 0: BooleanValue { value: value.as_boolean(vm, frame) }}),
+        OpCode::BitwiseNot {  } | OpCode::OnesComplement {  } => Some(RawValue::Integer { 0: IntegerValue { value: !value.as_integer(vm, frame) }}),
+        _ => None
+    };
+}
+
+/// Mirrors the interpreter's binary ops. None if the op isn't foldable.
+fn evaluate_binary<State>(op: &OpCode<State>, lhs: &RawValue<State>, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> Option<RawValue<State>> where State: Clone
+{
+    let float = |value: f32| Some(RawValue::Float { 0: FloatValue { value: value }});
+    let integer = |value: i32| Some(RawValue::Integer { 0: IntegerValue { value: value }});
+    let boolean = |value: bool| Some(RawValue::Boolean { 0: BooleanValue { value: value }});
+
+    return match op {
+        OpCode::Add {  } => float(lhs.add(rhs, vm, frame)),
+        OpCode::Minus {  } => float(lhs.subtract(rhs, vm, frame)),
+        OpCode::Multiply {  } => float(lhs.multiply(rhs, vm, frame)),
+        OpCode::Divide {  } => float(lhs.divide(rhs, vm, frame)),
+        OpCode::IntegerDivide {  } => integer(lhs.integer_divide(rhs, vm, frame)),
+        OpCode::Modulus {  } => integer(lhs.modulus(rhs, vm, frame)),
+        OpCode::BitwiseAnd {  } => integer(lhs.as_integer(vm, frame) & rhs.as_integer(vm, frame)),
+        OpCode::BitwiseOr {  } => integer(lhs.as_integer(vm, frame) | rhs.as_integer(vm, frame)),
+        OpCode::BitwiseXor {  } => integer(lhs.as_integer(vm, frame) ^ rhs.as_integer(vm, frame)),
+        OpCode::ShiftLeft {  } => integer(lhs.as_integer(vm, frame).wrapping_shl(rhs.as_integer(vm, frame) as u32)),
+        OpCode::ShiftRight {  } => integer((lhs.as_integer(vm, frame) as u32).wrapping_shr(rhs.as_integer(vm, frame) as u32) as i32),
+        OpCode::LogicalAnd {  } => boolean(lhs.as_boolean(vm, frame) && rhs.as_boolean(vm, frame)),
+        OpCode::LogicalOr {  } => boolean(lhs.as_boolean(vm, frame) || rhs.as_boolean(vm, frame)),
+        OpCode::LessThan {  } => boolean(Comparison::LessThan.apply(lhs, rhs, vm, frame)),
+        OpCode::LessThanOrEqual {  } => boolean(Comparison::LessThanOrEqual.apply(lhs, rhs, vm, frame)),
+        OpCode::GreaterThan {  } => boolean(Comparison::GreaterThan.apply(lhs, rhs, vm, frame)),
+        OpCode::GreaterThanOrEqual {  } => boolean(Comparison::GreaterThanOrEqual.apply(lhs, rhs, vm, frame)),
+        OpCode::Equals {  } => boolean(Comparison::Equals.apply(lhs, rhs, vm, frame)),
+        OpCode::NotEquals {  } => boolean(Comparison::NotEquals.apply(lhs, rhs, vm, frame)),
+        OpCode::StringEquals {  } => boolean(lhs.as_str(vm, frame) == rhs.as_str(vm, frame)),
+        OpCode::StringNotEqual {  } => boolean(lhs.as_str(vm, frame) != rhs.as_str(vm, frame)),
+        OpCode::Concat {  } => {
+            let mut result = lhs.as_string(vm, frame);
+            result.push_str(&rhs.as_str(vm, frame));
+            Some(RawValue::String { 0: StringValue { value: Arc::new(result) }})
+        },
+        OpCode::ConcatSeparator { separator } => {
+            let mut result = lhs.as_string(vm, frame);
+            result.push(*separator);
+            result.push_str(&rhs.as_str(vm, frame));
+            Some(RawValue::String { 0: StringValue { value: Arc::new(result) }})
+        },
+        _ => None
+    };
+}
@@ -0,0 +1,756 @@
+use std::{collections::{HashMap, HashSet}, marker::PhantomData, sync::Arc};
+
+use crate::vm::{
+    VirtualMachine, InstructionSequence, OpCode, VariableReference, VariableIdentifier, LocalSlot, SystemValue, RawValue, StackFrame, FloatValue,
+    IntegerValue, StringValue, BooleanValue, TaggedValue, Function, CallSite, SharedString, Comparison
+};
+use crate::passes::jump_target;
+
+/// Index of a register in the register file
+pub type Register = usize;
+
+/// Size of the register file when fixed-registers is enabled; sequences needing more fail to translate
+#[cfg(feature="fixed-registers")]
+pub const FIXED_REGISTER_COUNT: usize = 64;
+
+/// A value read by a register instruction. Constants and variables are folded straight into the
+/// instruction instead of occupying a register.
+#[derive(Debug, Clone)]
+pub enum Operand<State> where State: Clone
+{
+    Register(Register),
+    Constant(RawValue<State>),
+
+    /// Dereferenced when the instruction executes, like a variable pushed on the stack
+    Variable(VariableReference<State>),
+
+    /// Local read by LoadLocal. The stack backend copies the value when it is pushed, so the translator moves it
+    /// into a register before anything can assign the local
+    Local(LocalSlot)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BinaryOperator
+{
+    Add,
+    Minus,
+    Multiply,
+    Divide,
+    IntegerDivide,
+    Modulus,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    ShiftLeft,
+    ShiftRight,
+    LogicalAnd,
+    LogicalOr,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Equals,
+    NotEquals,
+    StringEquals,
+    StringNotEqual,
+    Concat,
+    ConcatSeparator(char)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UnaryOperator
+{
+    Negate,
+    Not,
+    ToBoolean,
+    BitwiseNot,
+    Detag,
+    GetTaggedString
+}
+
+/// Register addressed instruction set. Jump targets are absolute indices into the register sequence.
+pub enum RegisterOp<State> where State: Clone
+{
+    Load {
+        dest: Register,
+        source: Operand<State>
+    },
+    LoadTaggedString {
+        dest: Register,
+        value: String
+    },
+    Swap {
+        first: Register,
+        second: Register
+    },
+    Binary {
+        operator: BinaryOperator,
+        dest: Register,
+        lhs: Operand<State>,
+        rhs: Operand<State>
+    },
+    Unary {
+        operator: UnaryOperator,
+        dest: Register,
+        source: Operand<State>
+    },
+    Assign {
+        target: Operand<State>,
+        value: Operand<State>
+    },
+    Jump {
+        target: usize
+    },
+    JumpTrue {
+        condition: Operand<State>,
+        target: usize
+    },
+    JumpFalse {
+        condition: Operand<State>,
+        target: usize
+    },
+
+    /// Jumps leaving true in the register if it is truthy
+    JumpTrueOrPop {
+        condition: Register,
+        target: usize
+    },
+
+    /// Jumps leaving false in the register if it is falsy
+    JumpFalseOrPop {
+        condition: Register,
+        target: usize
+    },
+    /// Calls with the values in the registers below depth as the stack, the result replacing the last argument
+    CallFunction {
+        target: Vec<String>,
+        depth: usize
+    },
+
+    /// Call through an inline cached call site
+    CallCached {
+        site: CallSite<State>,
+        depth: usize
+    },
+
+    /// Ends the sequence, handing back the value if there was one on the stack
+    Return {
+        value: Option<Operand<State>>
+    }
+}
+
+pub struct RegisterSequence<State> where State: Clone
+{
+    pub ops: Vec<RegisterOp<State>>,
+
+    /// Number of registers the sequence addresses
+    pub register_count: usize,
+
+    /// Slot layout carried over from the stack sequence
+    pub local_slots: Arc<Vec<VariableIdentifier>>
+}
+
+/// Walks a stack sequence keeping a symbolic stack of operands. Stack slot N always lives in
+/// register N once materialized, so control flow joins agree on where every value is.
+struct Translator<State> where State: Clone
+{
+    ops: Vec<RegisterOp<State>>,
+    slots: Vec<Operand<State>>,
+    register_count: usize,
+
+    /// Stack depth expected at each jump target
+    label_depths: HashMap<usize, usize>,
+
+    /// Register op indices whose jump target still refers to a stack op index
+    fixups: Vec<(usize, usize)>
+}
+
+impl<State> Translator<State> where State: Clone
+{
+    fn pop(&mut self) -> Result<Operand<State>, &'static str>
+    {
+        return self.slots.pop().ok_or("Stack Underflow during Register Translation");
+    }
+
+    /// Pushes the result written to the register of the next free slot
+    fn push_result(&mut self) -> Register
+    {
+        let dest = self.slots.len();
+        self.slots.push(Operand::Register(dest));
+        self.register_count = self.register_count.max(self.slots.len());
+        return dest;
+    }
+
+    fn push_operand(&mut self, operand: Operand<State>)
+    {
+        self.slots.push(operand);
+        self.register_count = self.register_count.max(self.slots.len());
+    }
+
+    /// Moves every folded operand into its slot register
+    fn flush(&mut self)
+    {
+        for index in 0 .. self.slots.len()
+        {
+            if !matches!(self.slots[index], Operand::Register(_))
+            {
+                let source = std::mem::replace(&mut self.slots[index], Operand::Register(index));
+                self.ops.push(RegisterOp::Load { dest: index, source });
+            }
+        }
+    }
+
+    fn record_label(&mut self, target: usize, depth: usize) -> Result<(), &'static str>
+    {
+        return match self.label_depths.get(&target) {
+            Some(expected) if *expected != depth => Err("Inconsistent Stack Depth at Jump Target"),
+            Some(_) => Ok(()),
+            None => {
+                self.label_depths.insert(target, depth);
+                Ok(())
+            }
+        };
+    }
+
+    /// Emits a jump whose target is patched once every stack op has a register index
+    fn emit_jump(&mut self, op: RegisterOp<State>, target: usize)
+    {
+        self.fixups.push((self.ops.len(), target));
+        self.ops.push(op);
+    }
+
+    fn binary(&mut self, operator: BinaryOperator) -> Result<(), &'static str>
+    {
+        let rhs = self.pop()?;
+        let lhs = self.pop()?;
+        let dest = self.push_result();
+        self.ops.push(RegisterOp::Binary { operator, dest, lhs, rhs });
+        return Ok(());
+    }
+
+    /// Pops the condition and emits JumpTrue or JumpFalse to the given stack op index.
+    fn conditional_jump(&mut self, jump_if: bool, target: usize, op_count: usize) -> Result<(), &'static str>
+    {
+        let condition = self.pop()?;
+        self.flush();
+        let depth = self.slots.len();
+        self.record_label(target.min(op_count), depth)?;
+
+        if jump_if
+        {
+            self.emit_jump(RegisterOp::JumpTrue { condition, target: 0 }, target);
+        }
+        else
+        {
+            self.emit_jump(RegisterOp::JumpFalse { condition, target: 0 }, target);
+        }
+        return Ok(());
+    }
+
+    /// Emits an assignment, first copying out every local read that it may overwrite while still on the stack.
+    fn assign(&mut self, target: Operand<State>, value: Operand<State>)
+    {
+        let overwrites = |slot: LocalSlot| -> bool {
+            return match &target {
+                Operand::Variable(VariableReference::LocalSlot { slot: written, phantom: _ }) => *written == slot,
+                Operand::Variable(VariableReference::Global { value: _, name: _, phantom: _ }) => false,
+
+                // Named locals and variables only known at runtime may land in any slot
+                _ => true
+            };
+        };
+
+        for index in 0 .. self.slots.len()
+        {
+            if let Operand::Local(slot) = self.slots[index]
+            {
+                if overwrites(slot)
+                {
+                    self.slots[index] = Operand::Register(index);
+                    self.ops.push(RegisterOp::Load { dest: index, source: Operand::Local(slot) });
+                }
+            }
+        }
+        self.ops.push(RegisterOp::Assign { target: target, value: value });
+    }
+
+    fn unary(&mut self, operator: UnaryOperator) -> Result<(), &'static str>
+    {
+        let source = self.pop()?;
+        let dest = self.push_result();
+        self.ops.push(RegisterOp::Unary { operator, dest, source });
+        return Ok(());
+    }
+}
+
+impl<State> RegisterSequence<State> where State: Clone
+{
+    /// Translates a stack sequence to register form. Fails if the stack depth at a jump target
+    /// differs between the paths reaching it.
+    pub fn translate(instructions: &InstructionSequence<State>) -> Result<Self, &'static str>
+    {
+        let op_count = instructions.ops.len();
+
+        let mut targets: HashSet<usize> = HashSet::new();
+        for (index, op) in instructions.ops.iter().enumerate()
+        {
+            if let Some(target) = op.jump_address()
+            {
+                targets.insert(jump_target(index, target).min(op_count));
+            }
+        }
+
+        let mut translator = Translator {
+            ops: Vec::with_capacity(op_count),
+            slots: Vec::new(),
+            register_count: 0,
+            label_depths: HashMap::new(),
+            fixups: Vec::new()
+        };
+
+        // Register op index each stack op starts at, with one extra entry for the end of the sequence
+        let mut op_starts: Vec<usize> = Vec::with_capacity(op_count + 1);
+        let mut reachable = true;
+
+        for index in 0 ..= op_count
+        {
+            if targets.contains(&index)
+            {
+                if reachable
+                {
+                    translator.flush();
+                    let depth = translator.slots.len();
+                    translator.record_label(index, depth)?;
+                }
+                else
+                {
+                    let depth = *translator.label_depths.get(&index).ok_or("Unable to Determine Stack Depth at Jump Target")?;
+                    translator.slots = (0 .. depth).map(Operand::Register).collect();
+                    reachable = true;
+                }
+            }
+            op_starts.push(translator.ops.len());
+
+            // Skip dead code following an unconditional jump
+            if !reachable || index == op_count
+            {
+                continue;
+            }
+
+            match &instructions.ops[index] {
+                OpCode::PushFloat (value) => {
+                    translator.push_operand(Operand::Constant(RawValue::Float { 0: FloatValue { value: value.value }}));
+                },
+                OpCode::PushInteger { value } => {
+                    translator.push_operand(Operand::Constant(RawValue::Integer { 0: IntegerValue { value: *value }}));
+                },
+                OpCode::PushString { value } => {
+                    translator.push_operand(Operand::Constant(RawValue::String { 0: StringValue { value: Arc::new(value.clone()) }}));
+                },
+                OpCode::PushConstant { value } => {
+                    translator.push_operand(Operand::Constant(value.clone()));
+                },
+                OpCode::PushSharedString { value } => {
+                    translator.push_operand(Operand::Constant(RawValue::String { 0: StringValue { value: value.clone() }}));
+                },
+                OpCode::PushVariable { variable } => {
+                    translator.push_operand(Operand::Variable(variable.clone()));
+                },
+                OpCode::LoadLocal { slot } => {
+                    translator.push_operand(Operand::Local(*slot));
+                },
+                OpCode::StoreLocal { slot } => {
+                    let value = translator.pop()?;
+                    let target = Operand::Variable(VariableReference::LocalSlot { slot: *slot, phantom: PhantomData });
+                    translator.assign(target.clone(), value);
+                    translator.push_operand(target);
+                },
+                OpCode::PushTaggedString { value } => {
+                    let dest = translator.push_result();
+                    translator.ops.push(RegisterOp::LoadTaggedString { dest, value: value.clone() });
+                },
+                OpCode::Pop {  } => {
+                    translator.pop()?;
+                },
+                OpCode::NOP {  } => {
+
+                },
+                OpCode::Swap {  } => {
+                    let depth = translator.slots.len();
+                    if depth < 2
+                    {
+                        return Err("Stack Underflow during Register Translation");
+                    }
+
+                    // Registers have to stay at their slot index, so swap the contents at runtime
+                    translator.flush();
+                    translator.ops.push(RegisterOp::Swap { first: depth - 2, second: depth - 1 });
+                },
+                OpCode::Jump { target } => {
+                    let target = jump_target(index, target);
+                    translator.flush();
+                    let depth = translator.slots.len();
+                    translator.record_label(target.min(op_count), depth)?;
+                    translator.emit_jump(RegisterOp::Jump { target: 0 }, target);
+                    reachable = false;
+                },
+                OpCode::JumpTrue { target } => {
+                    translator.conditional_jump(true, jump_target(index, target), op_count)?;
+                },
+                OpCode::JumpFalse { target } => {
+                    translator.conditional_jump(false, jump_target(index, target), op_count)?;
+                },
+                OpCode::CompareAndJump { comparison, jump_if, target } => {
+                    let operator = match comparison {
+                        Comparison::LessThan => BinaryOperator::LessThan,
+                        Comparison::LessThanOrEqual => BinaryOperator::LessThanOrEqual,
+                        Comparison::GreaterThan => BinaryOperator::GreaterThan,
+                        Comparison::GreaterThanOrEqual => BinaryOperator::GreaterThanOrEqual,
+                        Comparison::Equals => BinaryOperator::Equals,
+                        Comparison::NotEquals => BinaryOperator::NotEquals
+                    };
+                    translator.binary(operator)?;
+                    translator.conditional_jump(*jump_if, jump_target(index, target), op_count)?;
+                },
+                OpCode::JumpTrueOrPop { target } => {
+                    let target = jump_target(index, target);
+                    translator.flush();
+                    let depth = translator.slots.len();
+                    if depth == 0
+                    {
+                        return Err("Stack Underflow during Register Translation");
+                    }
+                    translator.record_label(target.min(op_count), depth)?;
+                    translator.emit_jump(RegisterOp::JumpTrueOrPop { condition: depth - 1, target: 0 }, target);
+                    translator.pop()?;
+                },
+                OpCode::JumpFalseOrPop { target } => {
+                    let target = jump_target(index, target);
+                    translator.flush();
+                    let depth = translator.slots.len();
+                    if depth == 0
+                    {
+                        return Err("Stack Underflow during Register Translation");
+                    }
+                    translator.record_label(target.min(op_count), depth)?;
+                    translator.emit_jump(RegisterOp::JumpFalseOrPop { condition: depth - 1, target: 0 }, target);
+                    translator.pop()?;
+                },
+                OpCode::Assignment {  } => {
+                    let value = translator.pop()?;
+                    let target = translator.pop()?;
+
+                    if let Operand::Constant(_) | Operand::Local(_) = target
+                    {
+                        return Err("Assignment Target is not a Variable");
+                    }
+
+                    // The variable stays on the stack as the result of the assignment
+                    translator.assign(target.clone(), value);
+                    translator.push_operand(target);
+                },
+                OpCode::AssignAndPop {  } => {
+                    let value = translator.pop()?;
+                    let target = translator.pop()?;
+
+                    if let Operand::Constant(_) | Operand::Local(_) = target
+                    {
+                        return Err("Assignment Target is not a Variable");
+                    }
+                    translator.assign(target, value);
+                },
+                OpCode::AssignLocalAndPop { slot } => {
+                    let value = translator.pop()?;
+                    translator.assign(Operand::Variable(VariableReference::LocalSlot { slot: *slot, phantom: PhantomData }), value);
+                },
+                OpCode::IncrementLocal { slot, amount } => {
+                    translator.push_operand(Operand::Local(*slot));
+                    translator.push_operand(Operand::Constant(RawValue::Float { 0: FloatValue { value: *amount }}));
+                    translator.binary(BinaryOperator::Add)?;
+
+                    let value = translator.pop()?;
+                    translator.assign(Operand::Variable(VariableReference::LocalSlot { slot: *slot, phantom: PhantomData }), value);
+                },
+                OpCode::CallFunction { target } => {
+                    // Arguments have to be in their registers for the callee to read them as a stack
+                    translator.flush();
+                    translator.ops.push(RegisterOp::CallFunction { target: target.clone(), depth: translator.slots.len() });
+                },
+                OpCode::CallCached { site } => {
+                    translator.flush();
+                    translator.ops.push(RegisterOp::CallCached { site: site.clone(), depth: translator.slots.len() });
+                },
+                OpCode::Return {  } => {
+                    let value = translator.pop().ok();
+                    translator.ops.push(RegisterOp::Return { value: value });
+                    reachable = false;
+                },
+                OpCode::Concat {  } => translator.binary(BinaryOperator::Concat)?,
+                OpCode::ConcatSeparator { separator } => translator.binary(BinaryOperator::ConcatSeparator(*separator))?,
+                OpCode::LogicalAnd {  } => translator.binary(BinaryOperator::LogicalAnd)?,
+                OpCode::LogicalOr {  } => translator.binary(BinaryOperator::LogicalOr)?,
+                OpCode::BitwiseAnd {  } => translator.binary(BinaryOperator::BitwiseAnd)?,
+                OpCode::BitwiseOr {  } => translator.binary(BinaryOperator::BitwiseOr)?,
+                OpCode::BitwiseXor {  } => translator.binary(BinaryOperator::BitwiseXor)?,
+                OpCode::ShiftLeft {  } => translator.binary(BinaryOperator::ShiftLeft)?,
+                OpCode::ShiftRight {  } => translator.binary(BinaryOperator::ShiftRight)?,
+                OpCode::Add {  } => translator.binary(BinaryOperator::Add)?,
+                OpCode::Minus {  } => translator.binary(BinaryOperator::Minus)?,
+                OpCode::Modulus {  } => translator.binary(BinaryOperator::Modulus)?,
+                OpCode::Multiply {  } => translator.binary(BinaryOperator::Multiply)?,
+                OpCode::Divide {  } => translator.binary(BinaryOperator::Divide)?,
+                OpCode::IntegerDivide {  } => translator.binary(BinaryOperator::IntegerDivide)?,
+                OpCode::LessThan {  } => translator.binary(BinaryOperator::LessThan)?,
+                OpCode::LessThanOrEqual {  } => translator.binary(BinaryOperator::LessThanOrEqual)?,
+                OpCode::GreaterThan {  } => translator.binary(BinaryOperator::GreaterThan)?,
+                OpCode::GreaterThanOrEqual {  } => translator.binary(BinaryOperator::GreaterThanOrEqual)?,
+                OpCode::Equals {  } => translator.binary(BinaryOperator::Equals)?,
+                OpCode::NotEquals {  } => translator.binary(BinaryOperator::NotEquals)?,
+                OpCode::StringEquals {  } => translator.binary(BinaryOperator::StringEquals)?,
+                OpCode::StringNotEqual {  } => translator.binary(BinaryOperator::StringNotEqual)?,
+                OpCode::Negate {  } => translator.unary(UnaryOperator::Negate)?,
+                OpCode::Not {  } => translator.unary(UnaryOperator::Not)?,
+                OpCode::ToBoolean {  } => translator.unary(UnaryOperator::ToBoolean)?,
+                OpCode::BitwiseNot {  } | OpCode::OnesComplement {  } => translator.unary(UnaryOperator::BitwiseNot)?,
+                OpCode::Detag {  } => translator.unary(UnaryOperator::Detag)?,
+                OpCode::GetTaggedString {  } => translator.unary(UnaryOperator::GetTaggedString)?
+            }
+        }
+
+        for (op_index, target) in translator.fixups.iter()
+        {
+            let resolved = op_starts[(*target).min(op_count)];
+            match &mut translator.ops[*op_index] {
+                RegisterOp::Jump { target } | RegisterOp::JumpTrue { condition: _, target } | RegisterOp::JumpFalse { condition: _, target } |
+                RegisterOp::JumpTrueOrPop { condition: _, target } | RegisterOp::JumpFalseOrPop { condition: _, target } => {
+                    *target = resolved;
+                },
+                _ => { }
+            }
+        }
+
+        #[cfg(feature="fixed-registers")]
+        if translator.register_count > FIXED_REGISTER_COUNT
+        {
+            return Err("Sequence Exceeds the Fixed Register File");
+        }
+
+        return Ok(Self {
+            ops: translator.ops,
+            register_count: translator.register_count,
+            local_slots: instructions.local_slots.clone()
+        });
+    }
+}
+
+#[inline(always)]
+fn resolve<State>(operand: &Operand<State>, registers: &[SystemValue<State>], vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> RawValue<State> where State: Clone
+{
+    return match operand {
+        Operand::Register(register) => registers[*register].as_raw(vm, frame),
+        Operand::Constant(value) => value.clone(),
+        Operand::Variable(variable) => {
+            // Same as a variable on the stack, invalid lookups produce ""
+            variable.deref(vm, frame).unwrap_or(RawValue::String { 0: StringValue { value: SharedString::default() }})
+        },
+        Operand::Local(slot) => frame.slots[*slot].clone()
+    };
+}
+
+/// Produces the stack representation of an operand, keeping variables as references
+#[inline(always)]
+fn as_system_value<State>(operand: &Operand<State>, registers: &[SystemValue<State>], frame: &StackFrame<State>) -> SystemValue<State> where State: Clone
+{
+    return match operand {
+        Operand::Register(register) => registers[*register].clone(),
+        Operand::Constant(value) => SystemValue::Raw { value: value.clone() },
+        Operand::Variable(variable) => SystemValue::Variable { value: variable.clone() },
+        Operand::Local(slot) => SystemValue::Raw { value: frame.slots[*slot].clone() }
+    };
+}
+
+impl BinaryOperator
+{
+    #[inline(always)]
+    fn apply<State>(&self, lhs: &RawValue<State>, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> RawValue<State> where State: Clone
+    {
+        return match self {
+            BinaryOperator::Add => RawValue::Float { 0: FloatValue { value: lhs.add(rhs, vm, frame) }},
+            BinaryOperator::Minus => RawValue::Float { 0: FloatValue { value: lhs.subtract(rhs, vm, frame) }},
+            BinaryOperator::Multiply => RawValue::Float { 0: FloatValue { value: lhs.multiply(rhs, vm, frame) }},
+            BinaryOperator::Divide => RawValue::Float { 0: FloatValue { value: lhs.divide(rhs, vm, frame) }},
+            BinaryOperator::IntegerDivide => RawValue::Integer { 0: IntegerValue { value: lhs.integer_divide(rhs, vm, frame) }},
+            BinaryOperator::Modulus => RawValue::Integer { 0: IntegerValue { value: lhs.modulus(rhs, vm, frame) }},
+            BinaryOperator::BitwiseAnd => RawValue::Integer { 0: IntegerValue { value: lhs.as_integer(vm, frame) & rhs.as_integer(vm, frame) }},
+            BinaryOperator::BitwiseOr => RawValue::Integer { 0: IntegerValue { value: lhs.as_integer(vm, frame) | rhs.as_integer(vm, frame) }},
+            BinaryOperator::BitwiseXor => RawValue::Integer { 0: IntegerValue { value: lhs.as_integer(vm, frame) ^ rhs.as_integer(vm, frame) }},
+            BinaryOperator::ShiftLeft => RawValue::Integer { 0: IntegerValue { value: lhs.as_integer(vm, frame).wrapping_shl(rhs.as_integer(vm, frame) as u32) }},
+            BinaryOperator::ShiftRight => RawValue::Integer { 0: IntegerValue { value: (lhs.as_integer(vm, frame) as u32).wrapping_shr(rhs.as_integer(vm, frame) as u32) as i32 }},
+            BinaryOperator::LogicalAnd => RawValue::Boolean { 0: BooleanValue { value: lhs.as_boolean(vm, frame) && rhs.as_boolean(vm, frame) }},
+            BinaryOperator::LogicalOr => RawValue::Boolean { 0: BooleanValue { value: lhs.as_boolean(vm, frame) || rhs.as_boolean(vm, frame) }},
+            BinaryOperator::LessThan => RawValue::Boolean { 0: BooleanValue { value: lhs.as_float(vm, frame) < rhs.as_float(vm, frame) }},
+            BinaryOperator::LessThanOrEqual => RawValue::Boolean { 0: BooleanValue { value: lhs.as_float(vm, frame) <= rhs.as_float(vm, frame) }},
+            BinaryOperator::GreaterThan => RawValue::Boolean { 0: BooleanValue { value: lhs.as_float(vm, frame) > rhs.as_float(vm, frame) }},
+            BinaryOperator::GreaterThanOrEqual => RawValue::Boolean { 0: BooleanValue { value: lhs.as_float(vm, frame) >= rhs.as_float(vm, frame) }},
+            BinaryOperator::Equals => RawValue::Boolean { 0: BooleanValue { value: lhs.equals(vm, frame, rhs) }},
+            BinaryOperator::NotEquals => RawValue::Boolean { 0: BooleanValue { value: !lhs.equals(vm, frame, rhs) }},
+            BinaryOperator::StringEquals => RawValue::Boolean { 0: BooleanValue { value: lhs.as_str(vm, frame) == rhs.as_str(vm, frame) }},
+            BinaryOperator::StringNotEqual => RawValue::Boolean { 0: BooleanValue { value: lhs.as_str(vm, frame) != rhs.as_str(vm, frame) }},
+            BinaryOperator::Concat => {
+                let mut result = lhs.as_string(vm, frame);
+                result.push_str(&rhs.as_str(vm, frame));
+                RawValue::String { 0: StringValue { value: Arc::new(result) }}
+            },
+            BinaryOperator::ConcatSeparator(separator) => {
+                let mut result = lhs.as_string(vm, frame);
+                result.push(*separator);
+                result.push_str(&rhs.as_str(vm, frame));
+                RawValue::String { 0: StringValue { value: Arc::new(result) }}
+            }
+        };
+    }
+}
+
+impl UnaryOperator
+{
+    #[inline(always)]
+    fn apply<State>(&self, value: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> RawValue<State> where State: Clone
+    {
+        return match self {
+            UnaryOperator::Negate => RawValue::Float { 0: FloatValue { value: value.negate(vm, frame) }},
+            UnaryOperator::Not => RawValue::Boolean { 0: BooleanValue { value: !value.as_boolean(vm, frame) }},
+            UnaryOperator::ToBoolean => RawValue::Boolean { 0: BooleanValue { value: value.as_boolean(vm, frame) }},
+            UnaryOperator::BitwiseNot => RawValue::Integer { 0: IntegerValue { value: !value.as_integer(vm, frame) }},
+            UnaryOperator::Detag => RawValue::String { 0: StringValue { value: Arc::new(vm.detag(value, frame)) }},
+            UnaryOperator::GetTaggedString => RawValue::String { 0: StringValue { value: Arc::new(vm.get_tagged_string(value, frame)) }}
+        };
+    }
+}
+
+impl<State> VirtualMachine<'_, State> where State: Clone
+{
+    /// Executes a translated register sequence, producing whatever it returned.
+    pub fn interpret_registers(&self, sequence: &RegisterSequence<State>) -> Result<Option<RawValue<State>>, &'static str>
+    {
+        let coalescing = self.watchers.enter();
+        let result = self.run_registers(sequence);
+
+        if coalescing
+        {
+            self.leave_watch_scope();
+        }
+        return result;
+    }
+
+    fn run_registers(&self, sequence: &RegisterSequence<State>) -> Result<Option<RawValue<State>>, &'static str>
+    {
+        let mut frame = StackFrame::new();
+        frame.slots = vec![RawValue::String { 0: StringValue { value: SharedString::default() }}; sequence.local_slots.len()];
+        frame.slot_identifiers = sequence.local_slots.clone();
+
+        #[cfg(feature="fixed-registers")]
+        let mut registers: [SystemValue<State>; FIXED_REGISTER_COUNT] = std::array::from_fn(|_| SystemValue::Raw { value: RawValue::String { 0: StringValue { value: SharedString::default() }}});
+
+        #[cfg(not(feature="fixed-registers"))]
+        let mut registers: Vec<SystemValue<State>> = vec![SystemValue::Raw { value: RawValue::String { 0: StringValue { value: SharedString::default() }}}; sequence.register_count];
+
+        let mut current_index: usize = 0;
+        let op_count = sequence.ops.len();
+
+        while current_index < op_count
+        {
+            let current_instruction = &sequence.ops[current_index];
+            current_index += 1;
+
+            match current_instruction {
+                RegisterOp::Load { dest, source } => {
+                    registers[*dest] = as_system_value(source, &registers, &frame);
+                },
+                RegisterOp::LoadTaggedString { dest, value } => {
+                    let id = self.tag_string(value);
+                    registers[*dest] = SystemValue::Raw { value: RawValue::Tagged { 0: TaggedValue { id }}};
+                },
+                RegisterOp::Swap { first, second } => {
+                    registers.swap(*first, *second);
+                },
+                RegisterOp::Binary { operator, dest, lhs, rhs } => {
+                    let lhs = resolve(lhs, &registers, self, &frame);
+                    let rhs = resolve(rhs, &registers, self, &frame);
+                    registers[*dest] = SystemValue::Raw { value: operator.apply(&lhs, &rhs, self, &frame) };
+                },
+                RegisterOp::Unary { operator, dest, source } => {
+                    let value = resolve(source, &registers, self, &frame);
+                    registers[*dest] = SystemValue::Raw { value: operator.apply(&value, self, &frame) };
+                },
+                RegisterOp::Assign { target, value } => {
+                    let variable = match target {
+                        Operand::Variable(variable) => variable.clone(),
+                        Operand::Register(register) => registers[*register].as_variable(self, &frame)?,
+                        Operand::Constant(_) | Operand::Local(_) => {
+                            return Err("Not a Variable");
+                        }
+                    };
+
+                    let value = as_system_value(value, &registers, &frame);
+                    variable.perform_assignment(self, &mut frame, &value)?;
+                },
+                RegisterOp::Jump { target } => {
+                    current_index = *target;
+                },
+                RegisterOp::JumpTrue { condition, target } => {
+                    if resolve(condition, &registers, self, &frame).as_boolean(self, &frame) {
+                        current_index = *target;
+                    }
+                },
+                RegisterOp::JumpFalse { condition, target } => {
+                    if !resolve(condition, &registers, self, &frame).as_boolean(self, &frame) {
+                        current_index = *target;
+                    }
+                },
+                RegisterOp::JumpTrueOrPop { condition, target } => {
+                    if registers[*condition].as_raw(self, &frame).as_boolean(self, &frame) {
+                        registers[*condition] = SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: true }}};
+                        current_index = *target;
+                    }
+                },
+                RegisterOp::JumpFalseOrPop { condition, target } => {
+                    if !registers[*condition].as_raw(self, &frame).as_boolean(self, &frame) {
+                        registers[*condition] = SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: false }}};
+                        current_index = *target;
+                    }
+                },
+                RegisterOp::CallFunction { target, depth } => {
+                    let function_lookup = self.namespace().lookup_function_cached(target)?;
+                    self.call_with_registers(&function_lookup, &mut frame, &mut registers, *depth)?;
+                },
+                RegisterOp::CallCached { site, depth } => {
+                    let function = site.resolve(self)?;
+                    self.call_with_registers(&function, &mut frame, &mut registers, *depth)?;
+                },
+                RegisterOp::Return { value } => {
+                    return Ok(value.as_ref().map(|value| resolve(value, &registers, self, &frame)));
+                }
+            }
+        }
+
+        return Ok(None);
+    }
+
+    /// Makes a call the way the stack backend does, with the registers below depth standing in for the stack.
+    fn call_with_registers(&self, function: &Function<State>, frame: &mut StackFrame<State>, registers: &mut [SystemValue<State>], depth: usize) -> Result<(), &'static str>
+    {
+        frame.stack.extend(registers[.. depth].iter().cloned());
+        let result = function.call(self, frame);
+
+        if let Ok(value) = result.as_ref()
+        {
+            frame.hand_back(function.parameters(), value.clone());
+            if let Some(argument) = frame.stack.pop()
+            {
+                registers[depth - 1] = argument;
+            }
+        }
+        frame.stack.clear();
+        return result.map(|_| ());
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::SystemTime;
+
+use crate::compiler::CompiledScript;
+use crate::dso::hash_bytes;
+use crate::filesystem::ExecError;
+use crate::vm::{VirtualMachine, Function};
+
+/// A function as a script file declared it.
+#[derive(Debug, Clone)]
+struct DeclaredFunction
+{
+    path: Vec<String>,
+
+    /// Hash of the compiled function, None if it can't be serialized and so has to be assumed changed
+    fingerprint: Option<u64>
+}
+
+impl DeclaredFunction
+{
+    /// Paths compare case insensitively, as lookups do.
+    fn key(&self) -> String
+    {
+        return self.path.join("::").to_lowercase();
+    }
+}
+
+/// What a file run through exec looked like, so reload_scripts can tell what changed.
+#[derive(Debug, Clone)]
+pub(crate) struct LoadedScript
+{
+    modified: Option<SystemTime>,
+
+    /// Functions the file declared that no file run since has declared again
+    functions: Vec<DeclaredFunction>
+}
+
+impl LoadedScript
+{
+    pub(crate) fn new<State>(modified: Option<SystemTime>, script: &CompiledScript<State>) -> Self where State: Clone
+    {
+        let functions = script.functions.iter().map(|(path, function)| DeclaredFunction { path: path.clone(), fingerprint: fingerprint(function) }).collect();
+        return Self { modified: modified, functions: functions };
+    }
+
+    /// Gives up the functions a later file declared, so reloading this one never removes them.
+    pub(crate) fn disown(&mut self, later: &LoadedScript)
+    {
+        self.functions.retain(|function| !later.functions.iter().any(|declared| declared.key() == function.key()));
+    }
+}
+
+fn fingerprint<State>(function: &Function<State>) -> Option<u64> where State: Clone
+{
+    let Function::VirtualFunction { parameters, instructions } = function else { return None };
+
+    let mut bytes = instructions.serialize().ok()?;
+    for parameter in parameters
+    {
+        bytes.extend_from_slice(parameter.to_lowercase().as_bytes());
+        bytes.push(0);
+    }
+    return Some(hash_bytes(&bytes));
+}
+
+/// The outcome of reload_scripts. Functions are named by their full path, e.g. "Game::onTick".
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReloadReport
+{
+    /// Files that had changed and were run again
+    pub files: Vec<String>,
+
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+
+    /// Functions no longer declared by the file that declared them
+    pub removed: Vec<String>,
+
+    /// Files that failed to compile, install their functions or run, which leave the rest of the reload to go ahead
+    pub errors: Vec<ExecError>
+}
+
+impl<State> VirtualMachine<'_, State> where State: Clone
+{
+    /// Runs every file exec has run before whose modification time has since changed. Each file's function
+    /// declarations replace the ones it made last time all at once, dropping any it no longer declares, before its
+    /// top level statements run again. Globals and objects are left as they are, other than what those statements do.
+    /// Files that have gone missing are skipped, keeping their functions. A file that fails is listed in errors and
+    /// the rest are still reloaded.
+    pub fn reload_scripts(&self) -> Result<ReloadReport, ExecError>
+    {
+        let file_system = self.files.file_system().ok_or_else(|| ExecError { file: String::new(), message: "No File System Registered" })?;
+
+        let mut report = ReloadReport::default();
+        for path in self.files.loaded_paths()
+        {
+            let fail = |message| ExecError { file: path.clone(), message: message };
+
+            // Read as each file comes up, an earlier file in this pass may have taken over some of its functions
+            let Some(previous) = self.files.loaded(&path) else { continue };
+            let Some(modified) = file_system.modified(&path) else { continue };
+            if previous.modified == Some(modified)
+            {
+                continue;
+            }
+
+            // A file that no longer compiles keeps its current functions
+            let script = match self.load_file(&file_system, &path, Some(modified)) {
+                Ok(script) => script,
+                Err(message) => {
+                    report.errors.push(fail(message));
+                    continue;
+                }
+            };
+            let loaded = LoadedScript::new(Some(modified), &script);
+
+            let mut added = Vec::new();
+            let mut changed = Vec::new();
+            for function in loaded.functions.iter()
+            {
+                match previous.functions.iter().find(|old| old.key() == function.key()) {
+                    None => added.push(function.path.join("::")),
+                    Some(old) if old.fingerprint.is_none() || old.fingerprint != function.fingerprint => changed.push(function.path.join("::")),
+                    Some(_) => { }
+                }
+            }
+
+            let removed: Vec<Vec<String>> = previous.functions.iter()
+                .filter(|old| !loaded.functions.iter().any(|function| function.key() == old.key()))
+                .map(|old| old.path.clone())
+                .collect();
+
+            if let Err(message) = self.install_functions(script.functions, &removed)
+            {
+                report.errors.push(fail(message));
+                continue;
+            }
+            report.added.extend(added);
+            report.changed.extend(changed);
+            report.removed.extend(removed.iter().map(|path| path.join("::")));
+
+            self.files.record_loaded(path.clone(), loaded);
+            if let Err(message) = self.run_body(script.body)
+            {
+                report.errors.push(fail(message));
+            }
+
+            report.files.push(path);
+        }
+
+        return Ok(report);
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+/// Tagged strings travel through scripts as this byte followed by the decimal tag ID
+pub const STRING_TAG_PREFIX: char = '\x01';
+
+/// Identifier of an interned tagged string. Zero is never assigned.
+pub type TagIdentifier = u32;
+
+/// The network string table; interns 'tagged' strings so only their IDs need to be replicated.
+pub struct TaggedStringTable
+{
+    /// Interned strings, indexed by tag ID - 1
+    strings: Vec<String>,
+
+    /// Reverse lookup so identical strings share one tag
+    lookup: HashMap<String, TagIdentifier>
+}
+
+impl Default for TaggedStringTable
+{
+    fn default() -> Self
+    {
+        return Self::new();
+    }
+}
+
+impl TaggedStringTable
+{
+    pub fn new() -> Self
+    {
+        return Self
+        {
+            strings: Vec::new(),
+            lookup: HashMap::new()
+        };
+    }
+
+    /// Interns a string, returning its tag. IDs are handed out sequentially from 1 in order of first use,
+    /// so a client replaying the same additions ends up with the same table.
+    pub fn add(&mut self, value: &str) -> TagIdentifier
+    {
+        if let Some(existing) = self.lookup.get(value)
+        {
+            return *existing;
+        }
+
+        self.strings.push(value.to_owned());
+        let id = self.strings.len() as TagIdentifier;
+        self.lookup.insert(value.to_owned(), id);
+        return id;
+    }
+
+    /// Resolves a tag back to the string it was created from.
+    pub fn lookup(&self, id: TagIdentifier) -> Option<&str>
+    {
+        if id == 0
+        {
+            return None;
+        }
+
+        return self.strings.get((id - 1) as usize).map(|value| value.as_str());
+    }
+
+    /// Number of interned strings; also the highest assigned tag.
+    pub fn len(&self) -> usize
+    {
+        return self.strings.len();
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        return self.strings.is_empty();
+    }
+
+    /// Enumerates all entries in ID order, used to mirror the table to clients.
+    pub fn entries(&self) -> impl Iterator<Item = (TagIdentifier, &str)>
+    {
+        return self.strings.iter().enumerate().map(|(index, value)| ((index + 1) as TagIdentifier, value.as_str()));
+    }
+}
+
+/// Produces the script-visible token for a tag, e.g. "\x015".
+pub fn tag_to_token(id: TagIdentifier) -> String
+{
+    return format!("{}{}", STRING_TAG_PREFIX, id);
+}
+
+/// Parses a "\x01<id>" token. Returns None for strings that are not tags.
+pub fn token_to_tag(value: &str) -> Option<TagIdentifier>
+{
+    return match value.strip_prefix(STRING_TAG_PREFIX) {
+        Some(digits) => digits.parse::<TagIdentifier>().ok(),
+        None => None
+    };
+}
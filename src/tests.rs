@@ -1,13 +1,23 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
-    use std::{time::{Instant}, collections::{HashMap}, hash::{SipHasher}, cell::RefCell};
-    use std::sync::{RwLock, Arc};
-    use std::thread;
-    use std::sync;
+    use std::{cell::RefCell, marker::PhantomData, sync::Arc};
 
-    use crate::util::variable_name_to_identifier;
-    use crate::vm::{InstructionSequence, OpCode, VariableReference, Function, RawValue, VariableIdentifier, PushFloat, AddressValue, VirtualMachine};
+    use crate::util::{variable_name_to_identifier, collapse_escapes, expand_escapes, glob_match};
+    use crate::tagged_strings::TaggedStringTable;
+
+    #[cfg(feature="register-vm")]
+    use crate::register_vm::RegisterSequence;
+    use crate::vm::{InstructionSequence, OpCode, VariableReference, Function, NativeResult, VirtualMachine, StackFrame, PushFloat, AddressValue, Comparison};
+    use crate::vm::{RawValue, FloatValue, IntegerValue, BooleanValue, StringValue, TaggedValue, GlobalStorage, ClassEntry, DEFAULT_MAX_CALL_DEPTH};
+    use crate::threaded::DispatchMode;
+    use crate::binding::{BoundType, VariableGetter, VariableSetter};
+    use crate::watch::GlobalWatcher;
+    use crate::compiler::{CompiledScript, ScriptCompiler};
+    use crate::filesystem::{FileSystem, DiskFileSystem, MemoryFileSystem, normalize_path};
+    use crate::dso::{DsoHeader, DSO_FORMAT_VERSION, hash_source, read_dso, read_dso_header, write_dso};
+    use crate::reload::ReloadReport;
 
     #[derive(Clone)]
     struct ApplicationState
@@ -15,33 +25,167 @@ mod tests {
         pub running: bool
     }
 
+    fn global<State: Clone>(name: &str) -> OpCode<State>
+    {
+        return OpCode::PushVariable { variable: VariableReference::global(name) };
+    }
+
+    /// Creates a VM with a native quit() that clears the running flag, so tests can observe whether it ran
+    fn create_quit_vm<'a>() -> VirtualMachine<'a, RefCell<ApplicationState>>
+    {
+        let vm = VirtualMachine::new(RefCell::new(ApplicationState {
+            running: true
+        }));
+
+        vm.add_function(Function::NativeFunction {
+            parameters: Vec::new(),
+            binding: Box::new(|binding_vm, _frame| -> NativeResult<RefCell<ApplicationState>> {
+                binding_vm.state.borrow_mut().running = false;
+                Ok(None)
+            })
+        }, &["quit".to_owned()]).unwrap();
+
+        return vm;
+    }
+
+    /// Evaluates `$result = lhs <op> quit()` with short circuiting, returning the result and whether quit() ran
+    fn evaluate_short_circuit(lhs: i32, op: OpCode<RefCell<ApplicationState>>) -> (String, bool)
+    {
+        let opcodes = InstructionSequence::new(vec![
+            global("result"),
+            OpCode::PushInteger { value: lhs },
+            op,
+            OpCode::CallFunction { target: vec!["quit".to_owned()] },
+            // Calls don't produce a value, so stand in a truthy result
+            OpCode::PushInteger { value: 1 },
+            OpCode::ToBoolean { },
+            // 6th index is the end of the expression
+            OpCode::Assignment { },
+        ]);
+
+        let vm = create_quit_vm();
+        vm.interpret(&opcodes).unwrap();
+
+        let called = !vm.state.borrow().running;
+        return (read_global_string(&vm, "result"), called);
+    }
+
+    /// Evaluates `$result = lhs <op> rhs`
+    fn evaluate_binary(lhs: OpCode<ApplicationState>, rhs: OpCode<ApplicationState>, op: OpCode<ApplicationState>) -> String
+    {
+        let opcodes = InstructionSequence::new(vec![global("result"), lhs, rhs, op, OpCode::Assignment { }]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.interpret(&opcodes).unwrap();
+        return read_global_string(&vm, "result");
+    }
+
+    fn evaluate_unary(value: OpCode<ApplicationState>, op: OpCode<ApplicationState>) -> String
+    {
+        let opcodes = InstructionSequence::new(vec![global("result"), value, op, OpCode::Assignment { }]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.interpret(&opcodes).unwrap();
+        return read_global_string(&vm, "result");
+    }
+
+    fn local<State: Clone>(name: &str) -> OpCode<State>
+    {
+        return OpCode::PushVariable { variable: VariableReference::Local { value: variable_name_to_identifier(name.to_owned()), phantom: PhantomData } };
+    }
+
+    /// Counts %counter to 10, appending each value to %result, then copies both to globals
+    fn build_local_loop() -> InstructionSequence<ApplicationState>
+    {
+        return InstructionSequence::new(vec![
+            // %counter = 0
+            local("counter"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // %result = ""
+            local("result"),
+            OpCode::PushString { value: "".to_owned() },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // 8th index is the loop body: %result = %result @ %counter; %counter = %counter + 1
+            local("result"),
+            local("result"),
+            local("counter"),
+            OpCode::Concat { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("counter"),
+            local("counter"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Add { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // while (%counter < 10)
+            local("counter"),
+            OpCode::PushInteger { value: 10 },
+            OpCode::LessThan { },
+            OpCode::JumpTrue { target: AddressValue::AbsoluteTarget { index: 8 } },
+
+            // $result = %result; $counter = %counter
+            global("result"),
+            local("result"),
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            global("counter"),
+            local("counter"),
+            OpCode::Assignment { },
+        ]);
+    }
+
+    /// Runs a sequence on the stack interpreter and, translated, on the register interpreter and compares the named globals
+    #[cfg(feature="register-vm")]
+    fn assert_register_equivalent(build: fn() -> InstructionSequence<ApplicationState>, globals: &[&str])
+    {
+        let stack_vm = VirtualMachine::new(ApplicationState { running: true });
+        stack_vm.interpret(&build()).unwrap();
+
+        let register_vm = VirtualMachine::new(ApplicationState { running: true });
+        let sequence = RegisterSequence::translate(&build()).unwrap();
+        register_vm.interpret_registers(&sequence).unwrap();
+
+        for name in globals
+        {
+            assert_eq!(read_global_string(&stack_vm, name), read_global_string(&register_vm, name), "${} differs between stack and register execution", name);
+        }
+    }
+
+    fn read_global_string<State: Clone>(vm: &VirtualMachine<State>, name: &str) -> String
+    {
+        return vm.get_global(name).unwrap().string_value().unwrap();
+    }
+
     #[test]
     fn test_function_binding_simple()
     {  
-        let opcodes = InstructionSequence { 
-            ops: vec![
-                // FIXME: Encode this ahead of time to avoid the CPU
-                OpCode::CallFunction { target: vec!["quit".to_owned()] },
-            ]
-        };
+        let opcodes = InstructionSequence::new(vec![
+            // FIXME: Encode this ahead of time to avoid the CPU
+            OpCode::CallFunction { target: vec!["quit".to_owned()] },
+        ]);
 
         let vm = VirtualMachine::new(RefCell::new(ApplicationState {
             running: true
         }));
 
         // Add a native binding
-        let mut namespace_write = vm.root_namespace.borrow_mut();
-        namespace_write.add_function_entry(Function::NativeFunction { 
+        vm.add_function(Function::NativeFunction { 
             parameters: Vec::new(), 
-            binding: Box::new(|binding_vm, _frame| -> Result<(), &'static str> {
+            binding: Box::new(|binding_vm, _frame| -> NativeResult<RefCell<ApplicationState>> {
                 let mut state_write = binding_vm.state.borrow_mut();
                 state_write.running = false;
                 drop(state_write);
 
-                Ok(())
+                Ok(None)
             })
-        }, &vec!["quit".to_owned()]).unwrap();
-        drop(namespace_write);
+        }, &["quit".to_owned()]).unwrap();
 
         // Perform execution
         vm.interpret(&opcodes).unwrap();   
@@ -49,4 +193,2730 @@ mod tests {
         let state_read = vm.state.borrow();
         assert!(!state_read.running);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_concat_separators()
+    {
+        let separators = [(' ', "SPC"), ('\t', "TAB"), ('\n', "NL")];
+
+        for (separator, name) in separators
+        {
+            let opcodes = InstructionSequence::new(vec![
+                // $result = "abc" <op> 12
+                global("result"),
+                OpCode::PushString { value: "abc".to_owned() },
+                OpCode::PushInteger { value: 12 },
+                OpCode::ConcatSeparator { separator },
+                OpCode::Assignment { },
+            ]);
+
+            let vm = VirtualMachine::new(ApplicationState { running: true });
+            vm.interpret(&opcodes).unwrap();
+
+            let expected = format!("abc{}12", separator);
+            assert_eq!(read_global_string(&vm, "result").as_bytes(), expected.as_bytes(), "{} produced the wrong bytes", name);
+        }
+    }
+
+    #[test]
+    fn test_collapse_escapes()
+    {
+        assert_eq!(collapse_escapes("a\\tb\\nc\\rd").unwrap().as_bytes(), b"a\tb\nc\rd");
+        assert_eq!(collapse_escapes("\\\"quoted\\\" \\'tag\\' \\\\").unwrap().as_bytes(), b"\"quoted\" 'tag' \\");
+        assert_eq!(collapse_escapes("\\x41\\x7e\\x01").unwrap().as_bytes(), b"A~\x01");
+        assert_eq!(collapse_escapes("\\c0\\c1\\c2\\c3\\c4\\c5\\c6\\c7\\c8\\c9").unwrap().as_bytes(), b"\x02\x03\x04\x05\x06\x07\x08\x0B\x0C\x0E");
+        assert_eq!(collapse_escapes("\\crRed\\cpPush\\coPop").unwrap().as_bytes(), b"\x0FRed\x10Push\x11Pop");
+
+        // Bytes above ASCII become Latin-1 characters
+        assert_eq!(collapse_escapes("\\xE9").unwrap(), "\u{E9}");
+
+        assert!(collapse_escapes("\\q").is_err());
+        assert!(collapse_escapes("\\x4").is_err());
+        assert!(collapse_escapes("\\cx").is_err());
+        assert!(collapse_escapes("trailing\\").is_err());
+    }
+
+    #[test]
+    fn test_escaped_string_concat()
+    {
+        let opcodes = InstructionSequence::new(vec![
+            // $result = "\c2Name" TAB "\x41"
+            global("result"),
+            OpCode::PushString { value: collapse_escapes("\\c2Name").unwrap() },
+            OpCode::PushString { value: collapse_escapes("\\x41").unwrap() },
+            OpCode::ConcatSeparator { separator: '\t' },
+            OpCode::Assignment { },
+        ]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.interpret(&opcodes).unwrap();
+
+        assert_eq!(read_global_string(&vm, "result").as_bytes(), b"\x04Name\tA");
+    }
+
+    #[test]
+    fn test_tagged_string_table_ids()
+    {
+        let mut table = TaggedStringTable::new();
+
+        assert_eq!(table.add("Hello"), 1);
+        assert_eq!(table.add("World"), 2);
+        assert_eq!(table.add("Hello"), 1);
+
+        // Tagged strings are case sensitive
+        assert_eq!(table.add("hello"), 3);
+
+        assert_eq!(table.lookup(0), None);
+        assert_eq!(table.lookup(2), Some("World"));
+        assert_eq!(table.lookup(4), None);
+        assert_eq!(table.entries().collect::<Vec<_>>(), vec![(1, "Hello"), (2, "World"), (3, "hello")]);
+    }
+
+    #[test]
+    fn test_tagged_string_ops()
+    {
+        let opcodes = InstructionSequence::new(vec![
+            // $tag = 'Welcome'
+            global("tag"),
+            OpCode::PushTaggedString { value: "Welcome".to_owned() },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $again = 'Welcome'
+            global("again"),
+            OpCode::PushTaggedString { value: "Welcome".to_owned() },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $detagged = detag($tag)
+            global("detagged"),
+            global("tag"),
+            OpCode::Detag { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $plain = detag("untagged")
+            global("plain"),
+            OpCode::PushString { value: "untagged".to_owned() },
+            OpCode::Detag { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $byid = getTaggedString(1)
+            global("byid"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::GetTaggedString { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $missing = getTaggedString(7)
+            global("missing"),
+            OpCode::PushInteger { value: 7 },
+            OpCode::GetTaggedString { },
+            OpCode::Assignment { },
+        ]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.interpret(&opcodes).unwrap();
+
+        assert_eq!(read_global_string(&vm, "tag").as_bytes(), b"\x011");
+        assert_eq!(read_global_string(&vm, "again").as_bytes(), b"\x011");
+        assert_eq!(read_global_string(&vm, "detagged"), "Welcome");
+        assert_eq!(read_global_string(&vm, "plain"), "untagged");
+        assert_eq!(read_global_string(&vm, "byid"), "Welcome");
+        assert_eq!(read_global_string(&vm, "missing"), "");
+        assert_eq!(vm.lookup_tagged_string(1), Some("Welcome".to_owned()));
+    }
+
+    #[test]
+    fn test_tagged_string_token_survives_concat()
+    {
+        let opcodes = InstructionSequence::new(vec![
+            // $result = detag("" @ 'Name')
+            global("result"),
+            OpCode::PushString { value: "".to_owned() },
+            OpCode::PushTaggedString { value: "Name".to_owned() },
+            OpCode::Concat { },
+            OpCode::Detag { },
+            OpCode::Assignment { },
+        ]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.interpret(&opcodes).unwrap();
+
+        assert_eq!(read_global_string(&vm, "result"), "Name");
+    }
+
+    #[test]
+    fn test_tagged_string_builtins()
+    {
+        let opcodes = InstructionSequence::new(vec![
+            // $tag = 'Welcome'
+            global("tag"),
+            OpCode::PushTaggedString { value: "Welcome".to_owned() },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $detagged = detag($tag)
+            global("detagged"),
+            global("tag"),
+            OpCode::CallFunction { target: vec!["detag".to_owned()] },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $byid = getTaggedString(1)
+            global("byid"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::CallFunction { target: vec!["getTaggedString".to_owned()] },
+            OpCode::Assignment { },
+        ]);
+
+        for dispatch in [DispatchMode::Match, DispatchMode::Threaded]
+        {
+            let vm = VirtualMachine::new(ApplicationState { running: true });
+            vm.set_dispatch(dispatch);
+            vm.interpret(&opcodes).unwrap();
+
+            assert_eq!(read_global_string(&vm, "detagged"), "Welcome");
+            assert_eq!(read_global_string(&vm, "byid"), "Welcome");
+        }
+    }
+
+    #[test]
+    fn test_less_than_or_equal()
+    {
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 3 }, OpCode::PushInteger { value: 3 }, OpCode::LessThanOrEqual { }), "true");
+        assert_eq!(evaluate_binary(OpCode::PushFloat(PushFloat { value: 2.5 }), OpCode::PushInteger { value: 3 }, OpCode::LessThanOrEqual { }), "true");
+        assert_eq!(evaluate_binary(OpCode::PushString { value: "4".to_owned() }, OpCode::PushInteger { value: 3 }, OpCode::LessThanOrEqual { }), "false");
+    }
+
+    #[test]
+    fn test_bitwise_xor()
+    {
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 0b1100 }, OpCode::PushInteger { value: 0b1010 }, OpCode::BitwiseXor { }), "6");
+
+        // Floats and strings are truncated to integers first
+        assert_eq!(evaluate_binary(OpCode::PushFloat(PushFloat { value: 7.9 }), OpCode::PushString { value: "2".to_owned() }, OpCode::BitwiseXor { }), "5");
+    }
+
+    #[test]
+    fn test_shift_left()
+    {
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 1 }, OpCode::PushInteger { value: 4 }, OpCode::ShiftLeft { }), "16");
+        assert_eq!(evaluate_binary(OpCode::PushString { value: "3".to_owned() }, OpCode::PushFloat(PushFloat { value: 1.5 }), OpCode::ShiftLeft { }), "6");
+    }
+
+    #[test]
+    fn test_shift_right()
+    {
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 64 }, OpCode::PushInteger { value: 3 }, OpCode::ShiftRight { }), "8");
+
+        // The shift is logical, so the sign bit is not carried
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: -1 }, OpCode::PushInteger { value: 28 }, OpCode::ShiftRight { }), "15");
+    }
+
+    #[test]
+    fn test_bitwise_not()
+    {
+        assert_eq!(evaluate_unary(OpCode::PushInteger { value: 0 }, OpCode::BitwiseNot { }), "-1");
+        assert_eq!(evaluate_unary(OpCode::PushString { value: "5".to_owned() }, OpCode::BitwiseNot { }), "-6");
+    }
+
+    #[test]
+    fn test_ones_complement()
+    {
+        assert_eq!(evaluate_unary(OpCode::PushInteger { value: 255 }, OpCode::OnesComplement { }), "-256");
+        assert_eq!(evaluate_unary(OpCode::PushFloat(PushFloat { value: -1.7 }), OpCode::OnesComplement { }), "0");
+    }
+
+    #[test]
+    fn test_integer_divide()
+    {
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 7 }, OpCode::PushInteger { value: 2 }, OpCode::IntegerDivide { }), "3");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: -7 }, OpCode::PushInteger { value: 2 }, OpCode::IntegerDivide { }), "-3");
+        assert_eq!(evaluate_binary(OpCode::PushFloat(PushFloat { value: 9.9 }), OpCode::PushString { value: "2".to_owned() }, OpCode::IntegerDivide { }), "4");
+
+        // Dividing by zero produces zero rather than faulting
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 7 }, OpCode::PushInteger { value: 0 }, OpCode::IntegerDivide { }), "0");
+    }
+
+    #[test]
+    fn test_negate()
+    {
+        assert_eq!(evaluate_unary(OpCode::PushInteger { value: 4 }, OpCode::Negate { }), "-4");
+        assert_eq!(evaluate_unary(OpCode::PushString { value: "-2.5".to_owned() }, OpCode::Negate { }), "2.5");
+    }
+
+    /// $zero = 7 % 0; $overflow = i32::MIN % -1; $negative = -7 % 3
+    fn build_modulus_edge_cases() -> InstructionSequence<ApplicationState>
+    {
+        return InstructionSequence::new(vec![
+            global("zero"),
+            OpCode::PushInteger { value: 7 },
+            OpCode::PushInteger { value: 0 },
+            OpCode::Modulus { },
+            OpCode::AssignAndPop { },
+
+            global("overflow"),
+            OpCode::PushInteger { value: i32::MIN },
+            OpCode::PushInteger { value: -1 },
+            OpCode::Modulus { },
+            OpCode::AssignAndPop { },
+
+            global("negative"),
+            OpCode::PushInteger { value: -7 },
+            OpCode::PushInteger { value: 3 },
+            OpCode::Modulus { },
+            OpCode::AssignAndPop { },
+        ]);
+    }
+
+    #[test]
+    fn test_modulus_edge_cases()
+    {
+        let expected = [("zero", "0"), ("overflow", "0"), ("negative", "-1")];
+
+        for dispatch in [DispatchMode::Match, DispatchMode::Threaded]
+        {
+            let vm = VirtualMachine::new(ApplicationState { running: true });
+            vm.set_dispatch(dispatch);
+            vm.interpret(&build_modulus_edge_cases()).unwrap();
+
+            for (name, value) in expected
+            {
+                assert_eq!(read_global_string(&vm, name), value, "${} under {:?}", name, dispatch);
+            }
+        }
+
+        // Folded at load time
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        let mut opcodes = build_modulus_edge_cases();
+        assert_eq!(opcodes.fold_constants(&vm), 3);
+        vm.interpret(&opcodes).unwrap();
+        for (name, value) in expected
+        {
+            assert_eq!(read_global_string(&vm, name), value, "${} once folded", name);
+        }
+
+        #[cfg(feature="register-vm")]
+        assert_register_equivalent(build_modulus_edge_cases, &["zero", "overflow", "negative"]);
+    }
+
+    #[test]
+    fn test_logical_and_short_circuits()
+    {
+        let end = AddressValue::AbsoluteTarget { index: 6 };
+
+        assert_eq!(evaluate_short_circuit(0, OpCode::JumpFalseOrPop { target: end.clone() }), ("false".to_owned(), false));
+        assert_eq!(evaluate_short_circuit(1, OpCode::JumpFalseOrPop { target: end }), ("true".to_owned(), true));
+    }
+
+    #[test]
+    fn test_logical_or_short_circuits()
+    {
+        let end = AddressValue::AbsoluteTarget { index: 6 };
+
+        assert_eq!(evaluate_short_circuit(1, OpCode::JumpTrueOrPop { target: end.clone() }), ("true".to_owned(), false));
+        assert_eq!(evaluate_short_circuit(0, OpCode::JumpTrueOrPop { target: end }), ("true".to_owned(), true));
+    }
+
+    #[test]
+    fn test_short_circuit_leaves_stack_balanced()
+    {
+        // if (%a && %b) with both sides falsy, then $after = 5
+        let opcodes = InstructionSequence::new(vec![
+            OpCode::PushInteger { value: 0 },
+            OpCode::JumpFalseOrPop { target: AddressValue::AbsoluteTarget { index: 4 } },
+            OpCode::PushInteger { value: 0 },
+            OpCode::ToBoolean { },
+            OpCode::JumpFalse { target: AddressValue::AbsoluteTarget { index: 6 } },
+            OpCode::CallFunction { target: vec!["quit".to_owned()] },
+            global("after"),
+            OpCode::PushInteger { value: 5 },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+        ]);
+
+        let vm = create_quit_vm();
+        vm.interpret(&opcodes).unwrap();
+
+        assert!(vm.state.borrow().running);
+        assert_eq!(read_global_string(&vm, "after"), "5");
+    }
+
+    #[test]
+    fn test_operand_order_arithmetic()
+    {
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 10 }, OpCode::PushInteger { value: 4 }, OpCode::Minus { }), "6");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 10 }, OpCode::PushInteger { value: 4 }, OpCode::Divide { }), "2.5");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 10 }, OpCode::PushInteger { value: 4 }, OpCode::Modulus { }), "2");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 10 }, OpCode::PushInteger { value: 4 }, OpCode::IntegerDivide { }), "2");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 10 }, OpCode::PushInteger { value: 2 }, OpCode::ShiftLeft { }), "40");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 10 }, OpCode::PushInteger { value: 2 }, OpCode::ShiftRight { }), "2");
+    }
+
+    #[test]
+    fn test_operand_order_relational()
+    {
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 1 }, OpCode::PushInteger { value: 2 }, OpCode::LessThan { }), "true");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 2 }, OpCode::PushInteger { value: 1 }, OpCode::LessThan { }), "false");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 2 }, OpCode::PushInteger { value: 1 }, OpCode::GreaterThan { }), "true");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 1 }, OpCode::PushInteger { value: 2 }, OpCode::GreaterThanOrEqual { }), "false");
+        assert_eq!(evaluate_binary(OpCode::PushInteger { value: 2 }, OpCode::PushInteger { value: 1 }, OpCode::LessThanOrEqual { }), "false");
+    }
+
+    #[test]
+    fn test_operand_order_concat()
+    {
+        assert_eq!(evaluate_binary(OpCode::PushString { value: "left".to_owned() }, OpCode::PushString { value: "right".to_owned() }, OpCode::Concat { }), "leftright");
+        assert_eq!(evaluate_binary(OpCode::PushString { value: "left".to_owned() }, OpCode::PushString { value: "right".to_owned() }, OpCode::ConcatSeparator { separator: ' ' }), "left right");
+    }
+
+    #[test]
+    fn test_operand_order_assignment()
+    {
+        let opcodes = InstructionSequence::new(vec![
+            // $first = $second = 3
+            global("first"),
+            global("second"),
+            OpCode::PushInteger { value: 3 },
+            OpCode::Assignment { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $third = $first - 1
+            global("third"),
+            global("first"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Minus { },
+            OpCode::Assignment { },
+        ]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.interpret(&opcodes).unwrap();
+
+        assert_eq!(read_global_string(&vm, "first"), "3");
+        assert_eq!(read_global_string(&vm, "second"), "3");
+        assert_eq!(read_global_string(&vm, "third"), "2");
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_loop_equivalence()
+    {
+        assert_register_equivalent(build_local_loop, &["result", "counter"]);
+    }
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_operator_equivalence()
+    {
+        assert_register_equivalent(|| InstructionSequence::new(vec![
+            // $a = (10 - 4) / 4 % 3
+            global("a"),
+            OpCode::PushInteger { value: 10 },
+            OpCode::PushInteger { value: 4 },
+            OpCode::Minus { },
+            OpCode::PushInteger { value: 4 },
+            OpCode::Divide { },
+            OpCode::PushInteger { value: 3 },
+            OpCode::Modulus { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $b = ~(1 << 4 ^ 3) >> 2 SPC -$a
+            global("b"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::PushInteger { value: 4 },
+            OpCode::ShiftLeft { },
+            OpCode::PushInteger { value: 3 },
+            OpCode::BitwiseXor { },
+            OpCode::OnesComplement { },
+            OpCode::PushInteger { value: 2 },
+            OpCode::ShiftRight { },
+            global("a"),
+            OpCode::Negate { },
+            OpCode::ConcatSeparator { separator: ' ' },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $c = "x" $= "X" TAB 3 <= 2 NL !0
+            global("c"),
+            OpCode::PushString { value: "x".to_owned() },
+            OpCode::PushString { value: "X".to_owned() },
+            OpCode::StringEquals { },
+            OpCode::PushInteger { value: 3 },
+            OpCode::PushInteger { value: 2 },
+            OpCode::LessThanOrEqual { },
+            OpCode::ConcatSeparator { separator: '\t' },
+            OpCode::PushInteger { value: 0 },
+            OpCode::Not { },
+            OpCode::ConcatSeparator { separator: '\n' },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $d = detag('Tag') @ 'Tag'
+            global("d"),
+            OpCode::PushTaggedString { value: "Tag".to_owned() },
+            OpCode::Detag { },
+            OpCode::PushTaggedString { value: "Tag".to_owned() },
+            OpCode::Concat { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $e = 7 swapped with 2 then divided
+            global("e"),
+            OpCode::PushInteger { value: 7 },
+            OpCode::PushInteger { value: 2 },
+            OpCode::Swap { },
+            OpCode::IntegerDivide { },
+            OpCode::Assignment { },
+        ]), &["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_branch_equivalence()
+    {
+        assert_register_equivalent(|| InstructionSequence::new(vec![
+            // $first = $second = 0 && 1
+            global("first"),
+            global("second"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::JumpFalseOrPop { target: AddressValue::RelativeOffset { offset: 2 } },
+            OpCode::PushInteger { value: 1 },
+            OpCode::ToBoolean { },
+            OpCode::Assignment { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $ternary = $first ? "yes" : "no"
+            global("ternary"),
+            global("first"),
+            OpCode::JumpFalse { target: AddressValue::AbsoluteTarget { index: 14 } },
+            OpCode::PushString { value: "yes".to_owned() },
+            OpCode::Jump { target: AddressValue::AbsoluteTarget { index: 15 } },
+            OpCode::PushString { value: "no".to_owned() },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $either = "" || 2
+            global("either"),
+            OpCode::PushString { value: "".to_owned() },
+            OpCode::JumpTrueOrPop { target: AddressValue::AbsoluteTarget { index: 22 } },
+            OpCode::PushInteger { value: 2 },
+            OpCode::ToBoolean { },
+            OpCode::Assignment { },
+        ]), &["first", "second", "ternary", "either"]);
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_translation_rejects_inconsistent_depth()
+    {
+        let opcodes: InstructionSequence<ApplicationState> = InstructionSequence::new(vec![
+            OpCode::PushInteger { value: 1 },
+            OpCode::JumpFalse { target: AddressValue::AbsoluteTarget { index: 3 } },
+            OpCode::PushInteger { value: 2 },
+            OpCode::NOP { },
+        ]);
+
+        assert!(RegisterSequence::translate(&opcodes).is_err());
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_function_call()
+    {
+        let opcodes = InstructionSequence::new(vec![
+            OpCode::CallFunction { target: vec!["quit".to_owned()] },
+        ]);
+
+        let vm = create_quit_vm();
+        vm.interpret_registers(&RegisterSequence::translate(&opcodes).unwrap()).unwrap();
+        assert!(!vm.state.borrow().running);
+    }
+
+    #[test]
+    fn test_local_slot_resolution()
+    {
+        let mut opcodes = build_local_loop();
+        assert_eq!(opcodes.resolve_local_slots(), 2);
+
+        // Resolving again keeps the existing layout
+        assert_eq!(opcodes.resolve_local_slots(), 2);
+
+        assert!(!opcodes.ops.iter().any(|op| matches!(op, OpCode::PushVariable { variable: VariableReference::Local { value: _, phantom: _ } })));
+        assert!(opcodes.ops.iter().any(|op| matches!(op, OpCode::LoadLocal { slot: _ })));
+        assert!(opcodes.ops.iter().any(|op| matches!(op, OpCode::StoreLocal { slot: _ })));
+
+        let unresolved_vm = VirtualMachine::new(ApplicationState { running: true });
+        unresolved_vm.interpret(&build_local_loop()).unwrap();
+
+        let resolved_vm = VirtualMachine::new(ApplicationState { running: true });
+        resolved_vm.interpret(&opcodes).unwrap();
+
+        assert_eq!(read_global_string(&resolved_vm, "result"), "0123456789");
+        assert_eq!(read_global_string(&resolved_vm, "result"), read_global_string(&unresolved_vm, "result"));
+        assert_eq!(read_global_string(&resolved_vm, "counter"), read_global_string(&unresolved_vm, "counter"));
+    }
+
+    #[test]
+    fn test_local_slot_across_jump()
+    {
+        // %value = true && 5, with the variable still on the stack at the short circuit
+        let mut opcodes = InstructionSequence::new(vec![
+            local("value"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::JumpFalseOrPop { target: AddressValue::AbsoluteTarget { index: 5 } },
+            OpCode::PushInteger { value: 5 },
+            OpCode::ToBoolean { },
+            // 5th index is the end of the expression
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            global("result"),
+            local("value"),
+            OpCode::Assignment { },
+        ]);
+        opcodes.resolve_local_slots();
+
+        assert!(matches!(opcodes.ops[0], OpCode::PushVariable { variable: VariableReference::LocalSlot { slot: 0, phantom: _ } }));
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "result"), "true");
+    }
+
+    #[test]
+    fn test_local_slot_dynamic_lookup()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+
+        // Looks %counter up by name, as a runtime generated name would be, and copies it to $peeked
+        vm.add_function(Function::NativeFunction {
+            parameters: Vec::new(),
+            binding: Box::new(|binding_vm, frame| -> NativeResult<ApplicationState> {
+                let counter = VariableReference::Local { value: variable_name_to_identifier("counter".to_owned()), phantom: PhantomData };
+                let value = counter.deref(binding_vm, frame)?;
+                binding_vm.set_global("peeked", value)?;
+                Ok(None)
+            })
+        }, &["peek".to_owned()]).unwrap();
+
+        // Locals are resolved as the function is registered
+        vm.add_function(Function::VirtualFunction {
+            parameters: Vec::new(),
+            instructions: InstructionSequence::new(vec![
+                local("counter"),
+                OpCode::PushInteger { value: 7 },
+                OpCode::Assignment { },
+                OpCode::Pop { },
+                OpCode::CallFunction { target: vec!["peek".to_owned()] },
+            ])
+        }, &["body".to_owned()]).unwrap();
+
+        vm.interpret(&InstructionSequence::new(vec![
+            OpCode::CallFunction { target: vec!["body".to_owned()] },
+        ])).unwrap();
+
+        assert_eq!(read_global_string(&vm, "peeked"), "7");
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_local_slot_equivalence()
+    {
+        assert_register_equivalent(|| {
+            let mut opcodes = build_local_loop();
+            opcodes.resolve_local_slots();
+            return opcodes;
+        }, &["result", "counter"]);
+
+        // %x = 1; $sum = %x + (%x = 3); the left operand is read before the assignment
+        assert_register_equivalent(|| {
+            let mut opcodes = InstructionSequence::new(vec![
+                local("x"),
+                OpCode::PushInteger { value: 1 },
+                OpCode::Assignment { },
+                OpCode::Pop { },
+                global("sum"),
+                local("x"),
+                local("x"),
+                OpCode::PushInteger { value: 3 },
+                OpCode::Assignment { },
+                OpCode::Add { },
+                OpCode::Assignment { },
+                OpCode::Pop { },
+            ]);
+            opcodes.resolve_local_slots();
+            return opcodes;
+        }, &["sum"]);
+    }
+
+    /// A VM with a native double(value) that hands back twice its argument
+    fn create_double_vm<'a>() -> VirtualMachine<'a, ApplicationState>
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+
+        vm.add_function(Function::NativeFunction {
+            parameters: vec!["value".to_owned()],
+            binding: Box::new(|vm, frame| -> NativeResult<ApplicationState> {
+                let value = frame.stack.last().ok_or("double() expects a Value")?.as_raw(vm, frame);
+                Ok(Some(RawValue::from(value.as_integer(vm, frame) * 2)))
+            })
+        }, &["double".to_owned()]).unwrap();
+
+        return vm;
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_call_arguments()
+    {
+        // $doubled = 1 + double(4); the result lands where the argument was, as on the stack backend
+        let opcodes = InstructionSequence::new(vec![
+            global("doubled"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::PushInteger { value: 4 },
+            OpCode::CallFunction { target: vec!["double".to_owned()] },
+            OpCode::Add { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+        ]);
+
+        let vm = create_double_vm();
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "doubled"), "9");
+
+        let vm = create_double_vm();
+        vm.interpret_registers(&RegisterSequence::translate(&opcodes).unwrap()).unwrap();
+        assert_eq!(read_global_string(&vm, "doubled"), "9");
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_script_call_arguments()
+    {
+        // $summed = sum(4); a script callee binds the argument from the registers and returns into them
+        let opcodes = InstructionSequence::new(vec![
+            global("summed"),
+            OpCode::PushInteger { value: 4 },
+            OpCode::CallFunction { target: vec!["sum".to_owned()] },
+            OpCode::AssignAndPop { },
+        ]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.add_function(create_sum_function(), &["sum".to_owned()]).unwrap();
+        vm.interpret_registers(&RegisterSequence::translate(&opcodes).unwrap()).unwrap();
+        assert_eq!(read_global_string(&vm, "summed"), "10");
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_return_value()
+    {
+        let opcodes: InstructionSequence<ApplicationState> = InstructionSequence::new(vec![
+            OpCode::PushInteger { value: 6 },
+            OpCode::PushInteger { value: 7 },
+            OpCode::Multiply { },
+            OpCode::Return { },
+        ]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        let result = vm.interpret_registers(&RegisterSequence::translate(&opcodes).unwrap()).unwrap();
+        assert_eq!(result.and_then(|value| value.integer_value()), Some(42));
+    }
+
+    #[test]
+    fn test_global_storage_kinds()
+    {
+        let storage: GlobalStorage<ApplicationState> = GlobalStorage::new("value");
+        let frame = StackFrame::new();
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        assert!(storage.get().is_none());
+
+        let values = [
+            RawValue::Float { 0: FloatValue { value: -2.5 }},
+            RawValue::Integer { 0: IntegerValue { value: -7 }},
+            RawValue::Boolean { 0: BooleanValue { value: true }},
+            RawValue::Tagged { 0: TaggedValue { id: 9 }},
+            RawValue::String { 0: StringValue { value: Arc::new("text".to_owned()) }},
+            RawValue::Integer { 0: IntegerValue { value: 0 }}
+        ];
+        for value in values
+        {
+            storage.set(value.clone()).unwrap();
+            assert_eq!(storage.get().unwrap().as_string(&vm, &frame), value.as_string(&vm, &frame));
+        }
+
+        // Switching from a boxed string back to a number drops the string from view
+        storage.set(RawValue::String { 0: StringValue { value: Arc::new("boxed".to_owned()) }}).unwrap();
+        storage.set(RawValue::Integer { 0: IntegerValue { value: 4 }}).unwrap();
+        assert!(matches!(storage.get(), Some(RawValue::Integer { 0: IntegerValue { value: 4 }})));
+
+        assert_eq!(storage.take().unwrap().integer_value(), Some(4));
+        assert!(storage.get().is_none());
+
+        storage.set(RawValue::from("last")).unwrap();
+        assert_eq!(storage.take().unwrap().string_value().as_deref(), Some("last"));
+        assert!(storage.take().is_none());
+    }
+
+    #[test]
+    fn test_global_handle_resolution()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+
+        // $total = $total + 2, resolved before $total has ever been assigned
+        let mut opcodes = InstructionSequence::new(vec![
+            global("total"),
+            global("total"),
+            OpCode::PushInteger { value: 2 },
+            OpCode::Add { },
+            OpCode::Assignment { },
+        ]);
+        assert_eq!(opcodes.resolve_global_handles(&vm), 2);
+        assert!(opcodes.ops.iter().all(|op| !matches!(op, OpCode::PushVariable { variable: VariableReference::Global { value: _, name: _, phantom: _ } })));
+
+        // Unresolved writes land in the same storage the handles point at
+        vm.interpret(&InstructionSequence::new(vec![
+            global("total"),
+            OpCode::PushInteger { value: 5 },
+            OpCode::Assignment { },
+        ])).unwrap();
+
+        vm.interpret(&opcodes).unwrap();
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "total"), "9");
+    }
+
+    #[test]
+    fn test_add_function_resolves_globals()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.add_function(Function::VirtualFunction {
+            parameters: Vec::new(),
+            instructions: InstructionSequence::new(vec![
+                global("result"),
+                OpCode::PushString { value: "set".to_owned() },
+                OpCode::Assignment { },
+            ])
+        }, &["setter".to_owned()]).unwrap();
+
+        // Registering binds the global up front, though it stays unassigned until the function runs
+        assert!(vm.get_global("result").is_none());
+
+        vm.interpret(&InstructionSequence::new(vec![
+            OpCode::CallFunction { target: vec!["setter".to_owned()] },
+        ])).unwrap();
+        assert_eq!(read_global_string(&vm, "result"), "set");
+    }
+
+    /// A virtual function that writes the given string to $result
+    fn create_setter(value: &str) -> Function<ApplicationState>
+    {
+        return Function::VirtualFunction {
+            parameters: Vec::new(),
+            instructions: InstructionSequence::new(vec![
+                global("result"),
+                OpCode::PushString { value: value.to_owned() },
+                OpCode::Assignment { },
+            ])
+        };
+    }
+
+    #[test]
+    fn test_call_site_cache_invalidation()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.add_function(create_setter("first"), &["Target".to_owned()]).unwrap();
+
+        let mut opcodes = InstructionSequence::new(vec![
+            OpCode::CallFunction { target: vec!["target".to_owned()] },
+        ]);
+        assert_eq!(opcodes.resolve_call_sites(), 1);
+
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "result"), "first");
+
+        // Redefining the function has to reach call sites that already cached the old one
+        vm.add_function(create_setter("second"), &["target".to_owned()]).unwrap();
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "result"), "second");
+    }
+
+    #[test]
+    fn test_call_site_missing_function()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+
+        let mut opcodes = InstructionSequence::new(vec![
+            OpCode::CallFunction { target: vec!["missing".to_owned()] },
+        ]);
+        opcodes.resolve_call_sites();
+
+        assert!(vm.interpret(&opcodes).is_err());
+    }
+
+    #[test]
+    fn test_intern_string_constants()
+    {
+        let mut opcodes: InstructionSequence<ApplicationState> = InstructionSequence::new(vec![
+            OpCode::PushString { value: "shared".to_owned() },
+            OpCode::PushString { value: "shared".to_owned() },
+            OpCode::PushString { value: "other".to_owned() },
+        ]);
+        assert_eq!(opcodes.intern_string_constants(), 3);
+
+        match (&opcodes.ops[0], &opcodes.ops[1], &opcodes.ops[2]) {
+            (OpCode::PushSharedString { value: first }, OpCode::PushSharedString { value: second }, OpCode::PushSharedString { value: third }) => {
+                assert!(Arc::ptr_eq(first, second));
+                assert!(!Arc::ptr_eq(first, third));
+            },
+            _ => panic!("String constants were not interned")
+        }
+    }
+
+    #[test]
+    fn test_concat_in_place_keeps_copies_intact()
+    {
+        let mut opcodes = InstructionSequence::new(vec![
+            // %a = "ab"; %b = %a
+            local("a"),
+            OpCode::PushString { value: "ab".to_owned() },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("b"),
+            local("a"),
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // %a = %a @ "c"; %a = %a @ %a
+            local("a"),
+            local("a"),
+            OpCode::PushString { value: "c".to_owned() },
+            OpCode::Concat { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("a"),
+            local("a"),
+            local("a"),
+            OpCode::ConcatSeparator { separator: ' ' },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // $a = %a; $b = %b
+            global("a"),
+            local("a"),
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            global("b"),
+            local("b"),
+            OpCode::Assignment { },
+        ]);
+        opcodes.resolve_local_slots();
+        opcodes.intern_string_constants();
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.interpret(&opcodes).unwrap();
+        vm.interpret(&opcodes).unwrap();
+
+        assert_eq!(read_global_string(&vm, "a"), "abc abc");
+        assert_eq!(read_global_string(&vm, "b"), "ab");
+
+        // Running again must not have appended to the interned constant
+        match &opcodes.ops[1] {
+            OpCode::PushSharedString { value } => assert_eq!(value.as_str(), "ab"),
+            _ => panic!("String constant was not interned")
+        }
+    }
+
+    /// Runs a sequence as built, with only the peephole pass, and with locals resolved then optimized, comparing the named globals
+    fn assert_optimized_equivalent(build: fn() -> InstructionSequence<ApplicationState>, globals: &[&str])
+    {
+        let reference_vm = VirtualMachine::new(ApplicationState { running: true });
+        reference_vm.interpret(&build()).unwrap();
+
+        let mut optimized = build();
+        optimized.optimize();
+        let optimized_vm = VirtualMachine::new(ApplicationState { running: true });
+        optimized_vm.interpret(&optimized).unwrap();
+
+        let mut resolved = build();
+        resolved.resolve_local_slots();
+        resolved.optimize();
+        let resolved_vm = VirtualMachine::new(ApplicationState { running: true });
+        resolved_vm.interpret(&resolved).unwrap();
+
+        let folded_vm = VirtualMachine::new(ApplicationState { running: true });
+        let mut folded = build();
+        folded.fold_constants(&folded_vm);
+        folded.eliminate_dead_code();
+        folded.resolve_local_slots();
+        folded.optimize();
+        folded_vm.interpret(&folded).unwrap();
+
+        for name in globals
+        {
+            assert_eq!(read_global_string(&reference_vm, name), read_global_string(&optimized_vm, name), "${} differs after optimizing", name);
+            assert_eq!(read_global_string(&reference_vm, name), read_global_string(&resolved_vm, name), "${} differs after resolving and optimizing", name);
+            assert_eq!(read_global_string(&reference_vm, name), read_global_string(&folded_vm, name), "${} differs after folding", name);
+        }
+    }
+
+    #[test]
+    fn test_optimize_fuses_loop()
+    {
+        let mut opcodes = build_local_loop();
+        let original_count = opcodes.ops.len();
+        opcodes.resolve_local_slots();
+        let removed = opcodes.optimize();
+
+        assert_eq!(opcodes.ops.len(), original_count - removed);
+        assert!(!opcodes.ops.iter().any(|op| matches!(op, OpCode::NOP { })));
+        assert!(opcodes.ops.iter().any(|op| matches!(op, OpCode::IncrementLocal { slot: _, amount } if *amount == 1.0)));
+        assert!(opcodes.ops.iter().any(|op| matches!(op, OpCode::AssignLocalAndPop { slot: _ })));
+        assert!(opcodes.ops.iter().any(|op| matches!(op, OpCode::CompareAndJump { comparison: Comparison::LessThan, jump_if: true, target: _ })));
+
+        assert_optimized_equivalent(build_local_loop, &["result", "counter"]);
+    }
+
+    #[test]
+    #[cfg(feature="peephole")]
+    fn test_peephole_toggle()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        let count_nops = |name: &str| -> usize {
+            let function = vm.namespace().lookup_function_cached(&vec![name.to_owned()]).unwrap();
+            let Function::VirtualFunction { parameters: _, instructions } = function.as_ref() else { panic!("Expected a Script Function") };
+            return instructions.ops.iter().filter(|op| matches!(op, OpCode::NOP { })).count();
+        };
+        let build = || Function::VirtualFunction { parameters: vec![], instructions: build_local_loop() };
+
+        // Turning the pass off at runtime loads code as the other passes leave it
+        vm.set_peephole(false);
+        assert!(!vm.peephole());
+        vm.add_function(build(), &["plain".to_owned()]).unwrap();
+
+        vm.set_peephole(true);
+        vm.add_function(build(), &["optimized".to_owned()]).unwrap();
+
+        assert!(count_nops("plain") > 0);
+        assert_eq!(count_nops("optimized"), 0);
+    }
+
+    #[test]
+    fn test_optimize_relative_jumps()
+    {
+        // $total = 0; %n = 5; do { $total = $total + %n; %n = %n - 1; } while (%n > 0)
+        assert_optimized_equivalent(|| InstructionSequence::new(vec![
+            global("total"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("n"),
+            OpCode::PushInteger { value: 5 },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // 8th index is the loop body
+            OpCode::NOP { },
+            global("total"),
+            global("total"),
+            local("n"),
+            OpCode::Add { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("n"),
+            local("n"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Minus { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("n"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::GreaterThan { },
+            OpCode::JumpTrue { target: AddressValue::RelativeOffset { offset: -17 } },
+
+            // Skip over a dead write with a forward relative jump
+            OpCode::Jump { target: AddressValue::RelativeOffset { offset: 4 } },
+            global("total"),
+            OpCode::PushInteger { value: -1 },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            OpCode::NOP { },
+        ]), &["total"]);
+    }
+
+    #[test]
+    fn test_optimize_short_circuit()
+    {
+        // $result = 0 && quit-less stand in, then $other = 1 || 0
+        assert_optimized_equivalent(|| InstructionSequence::new(vec![
+            global("result"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::JumpFalseOrPop { target: AddressValue::AbsoluteTarget { index: 6 } },
+            OpCode::NOP { },
+            OpCode::PushInteger { value: 1 },
+            OpCode::ToBoolean { },
+            // 6th index is the end of the expression
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            global("other"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::JumpTrueOrPop { target: AddressValue::RelativeOffset { offset: 3 } },
+            OpCode::NOP { },
+            OpCode::PushInteger { value: 0 },
+            OpCode::ToBoolean { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+        ]), &["result", "other"]);
+    }
+
+    #[test]
+    fn test_optimize_does_not_fuse_across_jump_target()
+    {
+        let mut opcodes: InstructionSequence<ApplicationState> = InstructionSequence::new(vec![
+            global("result"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::JumpTrue { target: AddressValue::AbsoluteTarget { index: 4 } },
+            OpCode::PushInteger { value: 2 },
+            // 4th index is a jump target, so this Assignment can't be fused with the Pop before it is reached
+            OpCode::Assignment { },
+            OpCode::Pop { },
+        ]);
+        opcodes.optimize();
+        assert!(matches!(opcodes.ops[opcodes.ops.len() - 1], OpCode::AssignAndPop { }));
+
+        let mut split: InstructionSequence<ApplicationState> = InstructionSequence::new(vec![
+            OpCode::PushInteger { value: 1 },
+            OpCode::PushInteger { value: 2 },
+            OpCode::LessThan { },
+            // The jump is itself a target, so the comparison has to stay separate
+            OpCode::JumpFalse { target: AddressValue::AbsoluteTarget { index: 3 } },
+        ]);
+        split.optimize();
+        assert!(matches!(split.ops[2], OpCode::LessThan { }));
+    }
+
+    #[test]
+    fn test_fold_constants()
+    {
+        // $result = 3 * 4 @ "px"; $ratio = 7 / 2; $mask = (1 << 4) | 3
+        let build = || InstructionSequence::new(vec![
+            global("result"),
+            OpCode::PushInteger { value: 3 },
+            OpCode::PushInteger { value: 4 },
+            OpCode::Multiply { },
+            OpCode::PushString { value: "px".to_owned() },
+            OpCode::Concat { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            global("ratio"),
+            OpCode::PushInteger { value: 7 },
+            OpCode::PushFloat { 0: PushFloat { value: 2.0 }},
+            OpCode::Divide { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            global("mask"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::PushInteger { value: 4 },
+            OpCode::ShiftLeft { },
+            OpCode::PushInteger { value: 3 },
+            OpCode::BitwiseOr { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+        ]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        let mut opcodes = build();
+        assert_eq!(opcodes.fold_constants(&vm), 5);
+        opcodes.eliminate_dead_code();
+
+        assert_eq!(opcodes.ops.len(), 12);
+        assert_eq!(opcodes.ops.iter().filter(|op| matches!(op, OpCode::PushConstant { value: _ })).count(), 3);
+
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "result"), "12px");
+        assert_eq!(read_global_string(&vm, "ratio"), "3.5");
+        assert_eq!(read_global_string(&vm, "mask"), "19");
+
+        assert_optimized_equivalent(build, &["result", "ratio", "mask"]);
+    }
+
+    #[test]
+    fn test_fold_constants_modulus_by_zero()
+    {
+        // 5 % 0 yields 0 when run, so it folds to the same
+        let mut opcodes: InstructionSequence<ApplicationState> = InstructionSequence::new(vec![
+            OpCode::PushInteger { value: 5 },
+            OpCode::PushInteger { value: 0 },
+            OpCode::Modulus { },
+        ]);
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        assert_eq!(opcodes.fold_constants(&vm), 1);
+        assert!(!opcodes.ops.iter().any(|op| matches!(op, OpCode::Modulus { })));
+    }
+
+    #[test]
+    fn test_fold_constant_branch()
+    {
+        // if (2 < 1) $result = "dead"; else $result = "live"; $after = $result
+        let build = || InstructionSequence::new(vec![
+            OpCode::PushInteger { value: 2 },
+            OpCode::PushInteger { value: 1 },
+            OpCode::LessThan { },
+            OpCode::JumpFalse { target: AddressValue::AbsoluteTarget { index: 9 } },
+            global("result"),
+            OpCode::PushString { value: "dead".to_owned() },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            OpCode::Jump { target: AddressValue::AbsoluteTarget { index: 13 } },
+            // 9th index is the else branch
+            global("result"),
+            OpCode::PushString { value: "live".to_owned() },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            // 13th index joins both branches
+            global("after"),
+            global("result"),
+            OpCode::Assignment { },
+            OpCode::Pop { },
+        ]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        let mut opcodes = build();
+        opcodes.fold_constants(&vm);
+        opcodes.eliminate_dead_code();
+
+        // Only the live branch and the join remain, with no jumps left to take
+        assert_eq!(opcodes.ops.len(), 8);
+        assert!(opcodes.ops.iter().all(|op| op.jump_address().is_none()));
+        assert!(!opcodes.ops.iter().any(|op| matches!(op, OpCode::PushString { value } if value == "dead")));
+
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "after"), "live");
+
+        assert_optimized_equivalent(build, &["result", "after"]);
+    }
+
+    #[test]
+    fn test_eliminate_jump_chain()
+    {
+        // A loop whose exit goes through a chain of jumps, with dead code between the links
+        let build = || InstructionSequence::new(vec![
+            global("counter"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            // 4th index is the loop body
+            global("counter"),
+            global("counter"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Add { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            global("counter"),
+            OpCode::PushInteger { value: 3 },
+            OpCode::GreaterThanOrEqual { },
+            OpCode::JumpTrue { target: AddressValue::RelativeOffset { offset: 1 } },
+            OpCode::Jump { target: AddressValue::AbsoluteTarget { index: 4 } },
+            // 15th index starts the chain
+            OpCode::Jump { target: AddressValue::RelativeOffset { offset: 2 } },
+            global("counter"),
+            OpCode::PushInteger { value: -1 },
+            // 18th index
+            OpCode::Jump { target: AddressValue::AbsoluteTarget { index: 21 } },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            // 21st index
+            global("done"),
+            global("counter"),
+            OpCode::Assignment { },
+            OpCode::Pop { },
+        ]);
+
+        let mut opcodes: InstructionSequence<ApplicationState> = build();
+        let removed = opcodes.eliminate_dead_code();
+
+        // The chain collapses into the conditional jump, which now exits the loop directly
+        assert_eq!(removed, 6);
+        assert_eq!(opcodes.ops.iter().filter(|op| op.jump_address().is_some()).count(), 2);
+        assert!(matches!(opcodes.ops[13], OpCode::JumpTrue { target: AddressValue::RelativeOffset { offset: 1 } }));
+
+        assert_optimized_equivalent(build, &["counter", "done"]);
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_optimized_equivalence()
+    {
+        assert_register_equivalent(|| {
+            let mut opcodes = build_local_loop();
+            opcodes.resolve_local_slots();
+            opcodes.optimize();
+            return opcodes;
+        }, &["result", "counter"]);
+    }
+
+    #[test]
+    #[cfg(feature="register-vm")]
+    fn test_register_folded_equivalence()
+    {
+        assert_register_equivalent(|| {
+            let mut opcodes = InstructionSequence::new(vec![
+                global("result"),
+                OpCode::PushInteger { value: 3 },
+                OpCode::PushInteger { value: 4 },
+                OpCode::Multiply { },
+                OpCode::PushString { value: "px".to_owned() },
+                OpCode::Concat { },
+                OpCode::Assignment { },
+                OpCode::Pop { },
+            ]);
+            opcodes.fold_constants(&VirtualMachine::new(ApplicationState { running: true }));
+            opcodes.eliminate_dead_code();
+            return opcodes;
+        }, &["result"]);
+    }
+
+    /// Runs a sequence with both dispatch modes and checks the given globals come out the same
+    fn assert_threaded_equivalent(build: fn() -> InstructionSequence<ApplicationState>, globals: &[&str])
+    {
+        let match_vm = VirtualMachine::new(ApplicationState { running: true });
+        match_vm.interpret(&build()).unwrap();
+
+        let threaded_vm = VirtualMachine::new(ApplicationState { running: true });
+        threaded_vm.set_dispatch(DispatchMode::Threaded);
+        threaded_vm.interpret(&build()).unwrap();
+
+        for name in globals
+        {
+            assert_eq!(read_global_string(&match_vm, name), read_global_string(&threaded_vm, name), "${} differs between dispatch modes", name);
+        }
+    }
+
+    #[test]
+    fn test_threaded_equivalence()
+    {
+        assert_threaded_equivalent(build_local_loop, &["result", "counter"]);
+        assert_threaded_equivalent(|| {
+            let mut opcodes = build_local_loop();
+            opcodes.resolve_local_slots();
+            opcodes.optimize();
+            return opcodes;
+        }, &["result", "counter"]);
+
+        // Relative jumps, short circuiting, separators and tags
+        assert_threaded_equivalent(|| InstructionSequence::new(vec![
+            global("result"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::JumpFalseOrPop { target: AddressValue::RelativeOffset { offset: 2 } },
+            OpCode::PushInteger { value: 1 },
+            OpCode::ToBoolean { },
+            OpCode::PushTaggedString { value: "tag".to_owned() },
+            OpCode::Detag { },
+            OpCode::ConcatSeparator { separator: ' ' },
+            OpCode::PushInteger { value: 7 },
+            OpCode::PushInteger { value: 3 },
+            OpCode::Modulus { },
+            OpCode::Concat { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+        ]), &["result"]);
+    }
+
+    #[test]
+    fn test_threaded_dispatch_calls()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.set_dispatch(DispatchMode::Threaded);
+        vm.add_function(create_setter("threaded"), &["setter".to_owned()]).unwrap();
+
+        // Called functions run threaded as well
+        vm.interpret(&InstructionSequence::new(vec![
+            OpCode::CallFunction { target: vec!["setter".to_owned()] },
+        ])).unwrap();
+        assert_eq!(read_global_string(&vm, "result"), "threaded");
+
+        // Errors surface instead of panicking
+        let underflow: InstructionSequence<ApplicationState> = InstructionSequence::new(vec![
+            OpCode::Add { },
+        ]);
+        assert!(vm.interpret(&underflow).is_err());
+    }
+
+    #[test]
+    fn test_threaded_form_follows_passes()
+    {
+        let mut opcodes = build_local_loop();
+        let original_count = opcodes.threaded().instructions.len();
+
+        opcodes.resolve_local_slots();
+        opcodes.optimize();
+        assert_eq!(opcodes.threaded().instructions.len(), opcodes.ops.len());
+        assert!(opcodes.ops.len() < original_count);
+        assert_eq!(opcodes.threaded().local_slots.len(), opcodes.local_slots.len());
+    }
+
+    /// Adds an empty child namespace under the root, so functions can be registered beneath it
+    fn add_namespace<State: Clone>(vm: &VirtualMachine<State>, name: &str)
+    {
+        vm.namespace().add_namespace_path(&[name.to_owned()]);
+    }
+
+    #[test]
+    fn test_call_returns_value()
+    {
+        for dispatch in [DispatchMode::Match, DispatchMode::Threaded]
+        {
+            let vm = VirtualMachine::new(ApplicationState { running: true });
+            vm.set_dispatch(dispatch);
+            add_namespace(&vm, "Game");
+
+            // function Game::onTick(%delta, %scale) { return %delta * %scale; $result = "unreachable"; }
+            vm.add_function(Function::VirtualFunction {
+                parameters: vec!["delta".to_owned(), "scale".to_owned()],
+                instructions: InstructionSequence::new(vec![
+                    local("delta"),
+                    local("scale"),
+                    OpCode::Multiply { },
+                    OpCode::Return { },
+                    global("result"),
+                    OpCode::PushString { value: "unreachable".to_owned() },
+                    OpCode::AssignAndPop { },
+                ])
+            }, &["Game".to_owned(), "onTick".to_owned()]).unwrap();
+
+            let arguments = [RawValue::from(0.5), RawValue::from(6.0)];
+            let result = vm.call(&["Game", "onTick"], &arguments).unwrap();
+            assert_eq!(result.as_string(&vm, &StackFrame::new()), "3");
+            assert!(vm.get_global("result").is_none());
+
+            // Missing arguments are left empty
+            let result = vm.call(&["game", "ontick"], &[RawValue::from("4")]).unwrap();
+            assert_eq!(result.as_string(&vm, &StackFrame::new()), "0");
+
+            assert!(vm.call(&["Game", "missing"], &[]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_host_globals()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        assert!(vm.get_global("$pref::Video::res").is_none());
+
+        // The sigil is optional and names are case insensitive, as in script
+        vm.set_global("$pref::Video::res", RawValue::from("1920 1080")).unwrap();
+        assert_eq!(read_global_string(&vm, "pref::video::RES"), "1920 1080");
+
+        // Scripts see host writes, and the host sees script writes
+        vm.set_global("counter", RawValue::from(41)).unwrap();
+        let mut opcodes = InstructionSequence::new(vec![
+            global("counter"),
+            global("counter"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Add { },
+            OpCode::AssignAndPop { },
+        ]);
+        opcodes.resolve_global_handles(&vm);
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(vm.get_global("$counter").unwrap().integer_value(), Some(42));
+
+        // Deleting keeps handles already resolved by sequences working
+        assert_eq!(vm.delete_global("counter").unwrap().float_value(), Some(42.0));
+        assert!(vm.get_global("counter").is_none());
+        assert!(vm.delete_global("counter").is_none());
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(vm.get_global("counter").unwrap().integer_value(), Some(1));
+    }
+
+    #[test]
+    fn test_glob_match()
+    {
+        assert!(glob_match("pref::net::*", "Pref::Net::Port"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("pref::*::port", "pref::Net::Port"));
+        assert!(glob_match("pref::?et::*", "pref::net::a::b"));
+        assert!(glob_match("*::port", "a::port::port"));
+        assert!(!glob_match("pref::net::*", "pref::video::res"));
+        assert!(!glob_match("pref::?", "pref::"));
+        assert!(!glob_match("pref", "pref::net"));
+    }
+
+    #[test]
+    fn test_expand_escapes_round_trip()
+    {
+        for value in ["plain", "quote \" and \\ slash", "lines\n\r\t", "\x01tag", "\x02color\x0Freset", "caf\u{e9}"]
+        {
+            assert_eq!(collapse_escapes(&expand_escapes(value)).unwrap(), value);
+        }
+    }
+
+    /// Stands in for the compiler when re-executing an exported file: runs each `$name = "value";` line
+    fn execute_export<State: Clone>(vm: &VirtualMachine<State>, source: &str)
+    {
+        for line in source.lines()
+        {
+            let (name, value) = line.split_once(" = ").unwrap();
+            let value = value.strip_prefix('"').unwrap().strip_suffix("\";").unwrap();
+
+            vm.interpret(&InstructionSequence::new(vec![
+                OpCode::PushVariable { variable: VariableReference::global(name) },
+                OpCode::PushString { value: collapse_escapes(value).unwrap() },
+                OpCode::AssignAndPop { },
+            ])).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_enumerate_and_delete_globals()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+
+        // Globals assigned by script keep their names
+        vm.interpret(&InstructionSequence::new(vec![
+            global("Pref::Net::Port"),
+            OpCode::PushInteger { value: 28000 },
+            OpCode::AssignAndPop { },
+            global("Pref::Net::Host"),
+            OpCode::PushString { value: "localhost".to_owned() },
+            OpCode::AssignAndPop { },
+        ])).unwrap();
+        vm.set_global("$Pref::Video::Res", RawValue::from("800 600")).unwrap();
+        vm.set_global("$Server::Name", RawValue::from("test")).unwrap();
+
+        let names: Vec<String> = vm.matching_globals("$Pref::*").into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["Pref::Net::Host", "Pref::Net::Port", "Pref::Video::Res"]);
+
+        assert_eq!(vm.delete_globals("$pref::net::*"), 2);
+        assert!(vm.get_global("$Pref::Net::Port").is_none());
+        assert_eq!(read_global_string(&vm, "Pref::Video::Res"), "800 600");
+        assert_eq!(vm.matching_globals("*").len(), 2);
+
+        // Unassigned entries don't count
+        assert_eq!(vm.delete_globals("$pref::net::*"), 0);
+    }
+
+    #[test]
+    fn test_export_globals()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.set_global("$Pref::Name", RawValue::from("say \"hi\"\n\\o/")).unwrap();
+        vm.set_global("$Pref::Volume", RawValue::from(0.5)).unwrap();
+        vm.set_global("$Pref::Tag", RawValue::Tagged { 0: TaggedValue { id: 3 }}).unwrap();
+        vm.set_global("$Other", RawValue::from(true)).unwrap();
+
+        let mut exported: Vec<u8> = Vec::new();
+        assert_eq!(vm.export_globals("$Pref::*", &mut exported).unwrap(), 3);
+        assert_eq!(vm.export_globals("$Other", &mut exported).unwrap(), 1);
+        let source = String::from_utf8(exported).unwrap();
+
+        assert_eq!(source.lines().next(), Some("$Pref::Name = \"say \\\"hi\\\"\\n\\\\o/\";"));
+        assert_eq!(source.lines().count(), 4);
+
+        // Re-executing the file restores the same state
+        let restored = VirtualMachine::new(ApplicationState { running: true });
+        execute_export(&restored, &source);
+        for (name, value) in vm.matching_globals("*")
+        {
+            assert_eq!(read_global_string(&restored, &name), value.string_value().unwrap(), "${} differs after export", name);
+        }
+        assert_eq!(restored.format_globals("*"), vm.format_globals("*"));
+    }
+
+    #[test]
+    fn test_bound_global_cell()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+
+        // $Server::MaxPlayers = $Server::MaxPlayers + 1;
+        let mut increment = InstructionSequence::new(vec![
+            global("Server::MaxPlayers"),
+            global("Server::MaxPlayers"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Add { },
+            OpCode::AssignAndPop { },
+        ]);
+        increment.resolve_global_handles(&vm);
+        vm.set_global("$Server::MaxPlayers", RawValue::from(4)).unwrap();
+
+        // Binding after the sequence resolved its handle still routes it to the host
+        let max_players = Arc::new(std::sync::RwLock::new(16));
+        vm.bind_global_cell("$Server::MaxPlayers", max_players.clone()).unwrap();
+        assert_eq!(vm.get_global("$server::maxplayers").unwrap().integer_value(), Some(16));
+
+        vm.interpret(&increment).unwrap();
+        assert_eq!(*max_players.read().unwrap(), 17);
+
+        *max_players.write().unwrap() = 40;
+        assert_eq!(read_global_string(&vm, "Server::MaxPlayers"), "40");
+
+        // Values are converted on the way in, and rejected if they don't convert
+        vm.set_global("$Server::MaxPlayers", RawValue::from("12")).unwrap();
+        assert_eq!(*max_players.read().unwrap(), 12);
+
+        let invalid = InstructionSequence::new(vec![
+            global("Server::MaxPlayers"),
+            OpCode::PushString { value: "lots".to_owned() },
+            OpCode::AssignAndPop { },
+        ]);
+        assert!(vm.interpret(&invalid).is_err());
+        assert_eq!(*max_players.read().unwrap(), 12);
+
+        // The host owns the value, so it can't be deleted or bound again
+        assert!(vm.delete_global("$Server::MaxPlayers").is_none());
+        assert_eq!(vm.delete_globals("$Server::*"), 0);
+        assert!(vm.bind_global_cell("$Server::MaxPlayers", Arc::new(std::sync::RwLock::new(0))).is_err());
+        assert_eq!(vm.format_globals("$Server::*"), "$Server::MaxPlayers = \"12\";\n");
+    }
+
+    #[test]
+    fn test_bound_global_accessors()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        let volume = Arc::new(std::sync::RwLock::new(0.5_f32));
+
+        // Clamps writes into range rather than rejecting them
+        let getter_volume = volume.clone();
+        let setter_volume = volume.clone();
+        let getter: VariableGetter<ApplicationState> = Box::new(move || getter_volume.read().unwrap().to_raw());
+        let setter: VariableSetter<ApplicationState> = Box::new(move |value| {
+            *setter_volume.write().unwrap() = f32::from_raw(value)?.clamp(0.0, 1.0);
+            return Ok(());
+        });
+        vm.bind_global("$Pref::Audio::Volume", getter, setter).unwrap();
+
+        let mut opcodes = InstructionSequence::new(vec![
+            global("Pref::Audio::Volume"),
+            global("Pref::Audio::Volume"),
+            OpCode::PushInteger { value: 4 },
+            OpCode::Multiply { },
+            OpCode::AssignAndPop { },
+            global("doubled"),
+            global("Pref::Audio::Volume"),
+            OpCode::PushInteger { value: 2 },
+            OpCode::Multiply { },
+            OpCode::AssignAndPop { },
+        ]);
+        opcodes.resolve_global_handles(&vm);
+        vm.interpret(&opcodes).unwrap();
+
+        assert_eq!(*volume.read().unwrap(), 1.0);
+        assert_eq!(read_global_string(&vm, "doubled"), "2");
+    }
+
+    type ChangeLog = Arc<std::sync::Mutex<Vec<(String, Option<String>, String)>>>;
+
+    /// A watcher that records each change it hears about as strings
+    fn create_recorder(log: &ChangeLog) -> GlobalWatcher<ApplicationState>
+    {
+        let log = log.clone();
+        return Box::new(move |name, old, new| {
+            let old = old.map(|value| value.string_value().unwrap());
+            log.lock().unwrap().push((name.to_owned(), old, new.string_value().unwrap()));
+        });
+    }
+
+    fn change(name: &str, old: Option<&str>, new: &str) -> (String, Option<String>, String)
+    {
+        return (name.to_owned(), old.map(|value| value.to_owned()), new.to_owned());
+    }
+
+    #[test]
+    fn test_global_watchers()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        let mut opcodes = InstructionSequence::new(vec![
+            global("UI::Title"),
+            OpCode::PushString { value: "first".to_owned() },
+            OpCode::AssignAndPop { },
+            global("UI::Title"),
+            OpCode::PushString { value: "second".to_owned() },
+            OpCode::AssignAndPop { },
+            global("Score"),
+            OpCode::PushInteger { value: 3 },
+            OpCode::AssignAndPop { },
+            global("Unwatched"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::AssignAndPop { },
+        ]);
+
+        // Watchers apply to handles resolved before they were registered as well as globals created after
+        opcodes.resolve_global_handles(&vm);
+
+        let pattern_log: ChangeLog = Default::default();
+        let exact_log: ChangeLog = Default::default();
+        let pattern_watch = vm.watch_global("$ui::*", create_recorder(&pattern_log));
+        vm.watch_global("$Score", create_recorder(&exact_log));
+
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(*pattern_log.lock().unwrap(), [change("UI::Title", None, "first"), change("UI::Title", Some("first"), "second")]);
+        assert_eq!(*exact_log.lock().unwrap(), [change("Score", None, "3")]);
+
+        // Host writes don't notify, removed watchers stop hearing about changes
+        vm.set_global("$Score", RawValue::from(5)).unwrap();
+        assert_eq!(exact_log.lock().unwrap().len(), 1);
+
+        assert!(vm.unwatch_global(pattern_watch));
+        assert!(!vm.unwatch_global(pattern_watch));
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(pattern_log.lock().unwrap().len(), 2);
+        assert_eq!(exact_log.lock().unwrap()[1], change("Score", Some("5"), "3"));
+    }
+
+    #[test]
+    fn test_coalesced_watchers()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.set_coalesce_watchers(true);
+
+        let log: ChangeLog = Default::default();
+        vm.watch_global("$Counter", create_recorder(&log));
+
+        // A called function's writes are held back until its caller returns too
+        let observed = log.clone();
+        vm.add_function(Function::NativeFunction {
+            parameters: Vec::new(),
+            binding: Box::new(move |_vm, _frame| -> NativeResult<ApplicationState> {
+                assert!(observed.lock().unwrap().is_empty());
+                Ok(None)
+            })
+        }, &["check".to_owned()]).unwrap();
+        vm.add_function(Function::VirtualFunction {
+            parameters: Vec::new(),
+            instructions: InstructionSequence::new(vec![
+                global("Counter"),
+                OpCode::PushInteger { value: 100 },
+                OpCode::AssignAndPop { },
+            ])
+        }, &["reset".to_owned()]).unwrap();
+
+        // for (%i = 0; %i < 10; %i++) $Counter = %i + 1; reset(); check();
+        let opcodes = InstructionSequence::new(vec![
+            local("i"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::AssignAndPop { },
+            global("Counter"),
+            local("i"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Add { },
+            OpCode::AssignAndPop { },
+            local("i"),
+            local("i"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Add { },
+            OpCode::AssignAndPop { },
+            local("i"),
+            OpCode::PushInteger { value: 10 },
+            OpCode::LessThan { },
+            OpCode::JumpTrue { target: AddressValue::AbsoluteTarget { index: 3 } },
+            OpCode::CallFunction { target: vec!["reset".to_owned()] },
+            OpCode::CallFunction { target: vec!["check".to_owned()] },
+        ]);
+
+        for dispatch in [DispatchMode::Match, DispatchMode::Threaded]
+        {
+            vm.set_dispatch(dispatch);
+            log.lock().unwrap().clear();
+            vm.delete_global("Counter");
+
+            vm.interpret(&opcodes).unwrap();
+            assert_eq!(*log.lock().unwrap(), [change("Counter", None, "100")]);
+        }
+
+        #[cfg(feature="register-vm")]
+        {
+            log.lock().unwrap().clear();
+            vm.delete_global("Counter");
+
+            vm.interpret_registers(&RegisterSequence::translate(&opcodes).unwrap()).unwrap();
+            assert_eq!(*log.lock().unwrap(), [change("Counter", None, "100")]);
+        }
+
+        // Without coalescing each write is delivered as it happens
+        vm.set_coalesce_watchers(false);
+        log.lock().unwrap().clear();
+        vm.call(&["reset"], &[]).unwrap();
+        assert_eq!(*log.lock().unwrap(), [change("Counter", Some("100"), "100")]);
+    }
+
+    #[test]
+    fn test_frame_free_conversions()
+    {
+        let value: RawValue<ApplicationState> = RawValue::from("2.5");
+        assert_eq!(value.float_value(), Some(2.5));
+        assert_eq!(value.integer_value(), Some(0));
+        assert_eq!(value.boolean_value(), Some(true));
+
+        let value: RawValue<ApplicationState> = RawValue::from(true);
+        assert_eq!(value.string_value().as_deref(), Some("true"));
+        assert_eq!(value.integer_value(), Some(1));
+
+        let value: RawValue<ApplicationState> = RawValue::from(-3);
+        assert_eq!(value.float_value(), Some(-3.0));
+        assert_eq!(value.string_value().as_deref(), Some("-3"));
+    }
+
+    #[test]
+    fn test_call_without_return()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.add_function(create_setter("called"), &["setter".to_owned()]).unwrap();
+
+        let result = vm.call(&["setter"], &[]).unwrap();
+        assert_eq!(result.as_string(&vm, &StackFrame::new()), "");
+        assert_eq!(read_global_string(&vm, "result"), "called");
+    }
+
+    #[test]
+    fn test_native_call_returns_value()
+    {
+        for dispatch in [DispatchMode::Match, DispatchMode::Threaded]
+        {
+            let vm = create_double_vm();
+            vm.set_dispatch(dispatch);
+
+            let result = vm.call(&["double"], &[RawValue::from(21)]).unwrap();
+            assert_eq!(result.integer_value(), Some(42));
+
+            // $result = double(4); the result takes the argument's place on the stack
+            vm.interpret(&InstructionSequence::new(vec![
+                global("result"),
+                OpCode::PushInteger { value: 4 },
+                OpCode::CallFunction { target: vec!["double".to_owned()] },
+                OpCode::AssignAndPop { },
+            ])).unwrap();
+            assert_eq!(read_global_string(&vm, "result"), "8");
+        }
+    }
+
+    /// function sum(%n) { if (%n > 0) return %n + sum(%n - 1); return 0; }
+    fn create_sum_function() -> Function<ApplicationState>
+    {
+        return Function::VirtualFunction {
+            parameters: vec!["n".to_owned()],
+            instructions: InstructionSequence::new(vec![
+                local("n"),
+                OpCode::PushInteger { value: 0 },
+                OpCode::GreaterThan { },
+                OpCode::JumpTrue { target: AddressValue::AbsoluteTarget { index: 6 } },
+                OpCode::PushInteger { value: 0 },
+                OpCode::Return { },
+                local("n"),
+                local("n"),
+                OpCode::PushInteger { value: 1 },
+                OpCode::Minus { },
+                OpCode::CallFunction { target: vec!["sum".to_owned()] },
+                OpCode::Add { },
+                OpCode::Return { },
+            ])
+        };
+    }
+
+    #[test]
+    fn test_recursive_arguments()
+    {
+        for dispatch in [DispatchMode::Match, DispatchMode::Threaded]
+        {
+            let vm = VirtualMachine::new(ApplicationState { running: true });
+            vm.set_dispatch(dispatch);
+            vm.set_max_call_depth(100_000);
+            vm.add_function(create_sum_function(), &["sum".to_owned()]).unwrap();
+
+            // Each level reads its own argument and adds the result of the call below it
+            assert_eq!(vm.call(&["sum"], &[RawValue::from(1000)]).unwrap().string_value().unwrap(), "500500");
+
+            // $result = sum(4); from script the result takes the argument's place, as it does for natives
+            vm.interpret(&InstructionSequence::new(vec![
+                global("result"),
+                OpCode::PushInteger { value: 4 },
+                OpCode::CallFunction { target: vec!["sum".to_owned()] },
+                OpCode::AssignAndPop { },
+            ])).unwrap();
+            assert_eq!(read_global_string(&vm, "result"), "10");
+
+            // Deeper than the host stack would allow if every call recursed, adding up in the same order as the script
+            let expected = (1 ..= 50_000).fold(0.0f32, |total, n| n as f32 + total);
+            assert_eq!(vm.call(&["sum"], &[RawValue::from(50_000)]).unwrap().float_value(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_call_method()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        add_namespace(&vm, "ShapeBase");
+        add_namespace(&vm, "LocalPlayer");
+
+        vm.namespace().classes.write().unwrap().insert("localplayer".to_owned(), ClassEntry {
+            name: "LocalPlayer".to_owned(),
+            namespaces: vec!["Player".to_owned(), "ShapeBase".to_owned()],
+            functions: Default::default()
+        });
+
+        // function ShapeBase::describe(%this, %suffix) { return %this @ %suffix; }
+        vm.add_function(Function::VirtualFunction {
+            parameters: vec!["this".to_owned(), "suffix".to_owned()],
+            instructions: InstructionSequence::new(vec![
+                local("this"),
+                local("suffix"),
+                OpCode::Concat { },
+                OpCode::Return { },
+            ])
+        }, &["ShapeBase".to_owned(), "describe".to_owned()]).unwrap();
+
+        let object = RawValue::from("LocalPlayer");
+        let result = vm.call_method(&object, "describe", &[RawValue::from("!")]).unwrap();
+        assert_eq!(result.as_string(&vm, &StackFrame::new()), "LocalPlayer!");
+
+        // The object's own namespace comes before its class chain
+        vm.add_function(Function::VirtualFunction {
+            parameters: vec!["this".to_owned()],
+            instructions: InstructionSequence::new(vec![
+                OpCode::PushString { value: "overridden".to_owned() },
+                OpCode::Return { },
+            ])
+        }, &["LocalPlayer".to_owned(), "describe".to_owned()]).unwrap();
+
+        let result = vm.call_method(&object, "describe", &[]).unwrap();
+        assert_eq!(result.as_string(&vm, &StackFrame::new()), "overridden");
+
+        assert!(vm.call_method(&object, "missing", &[]).is_err());
+        assert!(vm.call_method(&RawValue::from("Unknown"), "describe", &[]).is_err());
+    }
+
+    /// Sums 0.5 into %total 100 times, scaling and negating %wave along the way, then copies both to globals.
+    /// The initial value of %total is given so the JIT's type guard can be exercised.
+    #[cfg(feature="jit")]
+    fn build_numeric_loop(initial_total: OpCode<ApplicationState>) -> InstructionSequence<ApplicationState>
+    {
+        return InstructionSequence::new(vec![
+            local("total"),
+            initial_total,
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("wave"),
+            OpCode::PushInteger { value: 3 },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("counter"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // 12th index is the loop body: %total = %total + 0.5; %wave = -(%wave * 0.5) / 1.25 - 1
+            local("total"),
+            local("total"),
+            OpCode::PushFloat { 0: PushFloat { value: 0.5 }},
+            OpCode::Add { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("wave"),
+            local("wave"),
+            OpCode::PushFloat { 0: PushFloat { value: 0.5 }},
+            OpCode::Multiply { },
+            OpCode::Negate { },
+            OpCode::PushFloat { 0: PushFloat { value: 1.25 }},
+            OpCode::Divide { },
+            OpCode::PushInteger { value: 1 },
+            OpCode::Minus { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+
+            // %counter = %counter + 1 while (%counter < 100)
+            local("counter"),
+            local("counter"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Add { },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("counter"),
+            OpCode::PushInteger { value: 100 },
+            OpCode::LessThan { },
+            OpCode::JumpTrue { target: AddressValue::AbsoluteTarget { index: 12 } },
+
+            global("total"),
+            local("total"),
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            global("wave"),
+            local("wave"),
+            OpCode::Assignment { },
+        ]);
+    }
+
+    /// Runs a sequence through interpret with the JIT available and through the threaded backend, which
+    /// never compiles, and compares the named globals. Returns the sequence so its regions can be inspected.
+    #[cfg(feature="jit")]
+    fn assert_jit_equivalent(sequence: InstructionSequence<ApplicationState>, globals: &[&str]) -> InstructionSequence<ApplicationState>
+    {
+        let jit_vm = VirtualMachine::new(ApplicationState { running: true });
+        let threaded_vm = VirtualMachine::new(ApplicationState { running: true });
+        threaded_vm.set_dispatch(DispatchMode::Threaded);
+
+        // Run a few times so the loop gets hot partway through
+        for _ in 0 .. 3
+        {
+            jit_vm.interpret(&sequence).unwrap();
+            threaded_vm.interpret(&sequence).unwrap();
+
+            for name in globals
+            {
+                assert_eq!(read_global_string(&jit_vm, name), read_global_string(&threaded_vm, name), "${} differs with the JIT", name);
+            }
+        }
+
+        return sequence;
+    }
+
+    #[test]
+    #[cfg(feature="jit")]
+    fn test_jit_numeric_loop()
+    {
+        // Slots only, so the loop is still Assignment/Pop pairs and a separate comparison and jump
+        let mut resolved = build_numeric_loop(OpCode::PushInteger { value: 0 });
+        resolved.resolve_local_slots();
+        let resolved = assert_jit_equivalent(resolved, &["total", "wave"]);
+        assert!(resolved.jit_regions().regions[0].is_compiled());
+
+        // Fused into superinstructions
+        let mut optimized = build_numeric_loop(OpCode::PushInteger { value: 0 });
+        optimized.resolve_local_slots();
+        optimized.optimize();
+        let optimized = assert_jit_equivalent(optimized, &["total", "wave"]);
+        assert!(optimized.jit_regions().regions[0].is_compiled());
+    }
+
+    #[test]
+    #[cfg(feature="jit")]
+    fn test_jit_deoptimizes_on_type_guard()
+    {
+        // %total starts as a string, so the loop has to stay interpreted
+        let mut opcodes = build_numeric_loop(OpCode::PushString { value: "1.5".to_owned() });
+        opcodes.resolve_local_slots();
+        opcodes.optimize();
+        assert_jit_equivalent(opcodes, &["total", "wave"]);
+
+        // Locals that are never stored as floats would change type if the loop wrote them, so it is not compiled at all
+        let mut copies: InstructionSequence<ApplicationState> = InstructionSequence::new(vec![
+            local("a"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("b"),
+            local("a"),
+            OpCode::Assignment { },
+            OpCode::Pop { },
+            local("a"),
+            OpCode::PushInteger { value: 1 },
+            OpCode::LessThan { },
+            OpCode::JumpTrue { target: AddressValue::AbsoluteTarget { index: 4 } },
+        ]);
+        copies.resolve_local_slots();
+        assert!(copies.jit_regions().regions.is_empty());
+    }
+
+    /// A virtual function that calls the native tick()
+    #[cfg(feature="async")]
+    fn create_worker() -> Function<ApplicationState>
+    {
+        return Function::VirtualFunction {
+            parameters: Vec::new(),
+            instructions: InstructionSequence::new(vec![
+                OpCode::CallFunction { target: vec!["tick".to_owned()] },
+            ])
+        };
+    }
+
+    #[test]
+    #[cfg(feature="async")]
+    fn test_concurrent_interpret()
+    {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 25;
+        const ITERATIONS: i32 = 20;
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let binding_ticks = ticks.clone();
+        vm.add_function(Function::NativeFunction {
+            parameters: Vec::new(),
+            binding: Box::new(move |_vm, _frame| -> NativeResult<ApplicationState> {
+                binding_ticks.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            })
+        }, &["tick".to_owned()]).unwrap();
+        vm.add_function(create_worker(), &["work".to_owned()]).unwrap();
+
+        std::thread::scope(|scope| {
+            for thread in 0 .. THREADS
+            {
+                let vm = &vm;
+                scope.spawn(move || {
+                    // %counter = 0; do { work(); $last = %counter = %counter + 1; } while (%counter < 20); $thread_N = %counter
+                    let mut opcodes = InstructionSequence::new(vec![
+                        local("counter"),
+                        OpCode::PushInteger { value: 0 },
+                        OpCode::Assignment { },
+                        OpCode::Pop { },
+                        // 4th index is the loop body
+                        OpCode::CallFunction { target: vec!["work".to_owned()] },
+                        global("last"),
+                        local("counter"),
+                        local("counter"),
+                        OpCode::PushInteger { value: 1 },
+                        OpCode::Add { },
+                        OpCode::Assignment { },
+                        OpCode::Assignment { },
+                        OpCode::Pop { },
+                        local("counter"),
+                        OpCode::PushInteger { value: ITERATIONS },
+                        OpCode::LessThan { },
+                        OpCode::JumpTrue { target: AddressValue::AbsoluteTarget { index: 4 } },
+                        global(&format!("thread_{}", thread)),
+                        local("counter"),
+                        OpCode::Assignment { },
+                    ]);
+
+                    // Half the threads run resolved sequences, going through call site caches and global handles
+                    if thread % 2 == 0
+                    {
+                        opcodes.resolve_local_slots();
+                        opcodes.resolve_call_sites();
+                        opcodes.resolve_global_handles(vm);
+                    }
+
+                    for _ in 0 .. ROUNDS
+                    {
+                        vm.interpret(&opcodes).unwrap();
+                    }
+                });
+            }
+
+            // Keep replacing the worker while the others run, invalidating every call site cache
+            scope.spawn(|| {
+                for _ in 0 .. ROUNDS
+                {
+                    vm.add_function(create_worker(), &["work".to_owned()]).unwrap();
+                    std::thread::yield_now();
+                }
+            });
+        });
+
+        assert_eq!(ticks.load(Ordering::Relaxed), THREADS * ROUNDS * ITERATIONS as usize);
+        for thread in 0 .. THREADS
+        {
+            assert_eq!(read_global_string(&vm, &format!("thread_{}", thread)), ITERATIONS.to_string());
+        }
+
+        let last: i32 = read_global_string(&vm, "last").parse().unwrap();
+        assert!((1 ..= ITERATIONS).contains(&last));
+    }
+
+    /// Stands in for the TorqueScript frontend: "name=value" sets a global, "fn Namespace::name=value" declares a
+    /// function setting $name, and "return value" ends the script with a value. Statements are separated by ';'.
+    fn create_test_compiler() -> ScriptCompiler<ApplicationState>
+    {
+        return Arc::new(|source: &str| -> Result<CompiledScript<ApplicationState>, &'static str> {
+            let mut functions = Vec::new();
+            let mut body = Vec::new();
+
+            for statement in source.split(';').map(|statement| statement.trim()).filter(|statement| !statement.is_empty())
+            {
+                if let Some(value) = statement.strip_prefix("return ")
+                {
+                    body.push(OpCode::PushString { value: value.to_owned() });
+                    body.push(OpCode::Return { });
+                    continue;
+                }
+
+                let (target, value) = statement.split_once('=').ok_or("Syntax Error")?;
+                match target.strip_prefix("fn ") {
+                    Some(path) => {
+                        let path: Vec<String> = path.split("::").map(|name| name.to_owned()).collect();
+                        let name = path.last().unwrap().clone();
+                        functions.push((path, Function::VirtualFunction {
+                            parameters: vec![],
+                            instructions: InstructionSequence::new(vec![
+                                global(&name),
+                                OpCode::PushString { value: value.to_owned() },
+                                OpCode::AssignAndPop { },
+                            ])
+                        }));
+                    },
+                    None => {
+                        body.push(global(target));
+                        body.push(OpCode::PushString { value: value.to_owned() });
+                        body.push(OpCode::AssignAndPop { });
+                    }
+                }
+            }
+
+            return Ok(CompiledScript { functions: functions, body: InstructionSequence::new(body) });
+        });
+    }
+
+    #[test]
+    fn test_eval()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        assert_eq!(vm.eval("level=1").err(), Some("No Compiler Registered"));
+
+        vm.set_compiler(create_test_compiler()).unwrap();
+
+        // Functions land in namespaces created on demand, globals are shared with the host
+        let result = vm.eval("fn Game::Rules::onStart=started; level=2; return done").unwrap();
+        assert_eq!(result.string_value().unwrap(), "done");
+        assert_eq!(read_global_string(&vm, "level"), "2");
+
+        vm.call(&["Game", "Rules", "onStart"], &[]).unwrap();
+        assert_eq!(read_global_string(&vm, "onStart"), "started");
+
+        // Declared functions go through the same load passes as the body, e.g. their strings are interned
+        let function = vm.namespace().lookup_function_cached(&vec!["Game".to_owned(), "Rules".to_owned(), "onStart".to_owned()]).unwrap();
+        let Function::VirtualFunction { parameters: _, instructions } = function.as_ref() else { panic!("Expected a Script Function") };
+        assert!(instructions.ops.iter().any(|op| matches!(op, OpCode::PushSharedString { value: _ })));
+        assert!(!instructions.ops.iter().any(|op| matches!(op, OpCode::PushString { value: _ })));
+
+        // Scripts without a return produce an empty string
+        assert_eq!(vm.eval("level=3").unwrap().string_value().unwrap(), "");
+        assert_eq!(read_global_string(&vm, "level"), "3");
+
+        // Compile errors come back out without running anything
+        assert_eq!(vm.eval("level=4; garbage").err(), Some("Syntax Error"));
+        assert_eq!(read_global_string(&vm, "level"), "3");
+    }
+
+    #[test]
+    fn test_eval_builtin()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.set_compiler(create_test_compiler()).unwrap();
+
+        // $result = eval("fn announce=hello; level=5; return loaded"); announce();
+        let opcodes = InstructionSequence::new(vec![
+            global("result"),
+            OpCode::PushString { value: "fn announce=hello; level=5; return loaded".to_owned() },
+            OpCode::CallFunction { target: vec!["eval".to_owned()] },
+            OpCode::AssignAndPop { },
+            OpCode::CallFunction { target: vec!["announce".to_owned()] },
+        ]);
+        vm.interpret(&opcodes).unwrap();
+
+        assert_eq!(read_global_string(&vm, "level"), "5");
+        assert_eq!(read_global_string(&vm, "announce"), "hello");
+        assert_eq!(read_global_string(&vm, "result"), "loaded");
+    }
+
+    #[test]
+    fn test_normalize_path()
+    {
+        assert_eq!(normalize_path("scripts/main.cs").unwrap(), "scripts/main.cs");
+        assert_eq!(normalize_path("./scripts\\client//../main.cs").unwrap(), "scripts/main.cs");
+        assert_eq!(normalize_path("/scripts/./main.cs").unwrap(), "scripts/main.cs");
+        assert_eq!(normalize_path("scripts/..").unwrap(), "");
+        assert!(normalize_path("../main.cs").is_err());
+        assert!(normalize_path("scripts/../../main.cs").is_err());
+    }
+
+    #[test]
+    fn test_memory_file_system()
+    {
+        let files = MemoryFileSystem::new();
+        files.write("scripts/main.cs", b"level=1").unwrap();
+        files.write("scripts/client/ui.cs", b"").unwrap();
+        files.write("readme.txt", b"").unwrap();
+        assert!(files.write("../outside.cs", b"").is_err());
+
+        assert!(files.exists("scripts"));
+        assert!(files.exists("scripts/client/ui.cs"));
+        assert!(!files.exists("script"));
+        assert_eq!(files.list("").unwrap(), vec!["readme.txt", "scripts"]);
+        assert_eq!(files.list("scripts").unwrap(), vec!["client", "main.cs"]);
+        assert!(files.list("missing").is_err());
+
+        // Every write is newer than the last
+        let modified = files.modified("scripts/main.cs").unwrap();
+        files.write("scripts/main.cs", b"level=2").unwrap();
+        assert!(files.modified("scripts/main.cs").unwrap() > modified);
+        assert_eq!(files.read("scripts/main.cs").unwrap(), "level=2");
+
+        assert!(files.remove("readme.txt"));
+        assert!(!files.exists("readme.txt"));
+    }
+
+    #[test]
+    fn test_disk_file_system()
+    {
+        let root = std::env::temp_dir().join(format!("exec_test_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("scripts")).unwrap();
+        std::fs::write(root.join("scripts/main.cs"), "level=7").unwrap();
+
+        let files = DiskFileSystem::new(&root);
+        assert!(files.exists("scripts/main.cs"));
+        assert!(files.modified("scripts/main.cs").is_some());
+        assert_eq!(files.list("scripts").unwrap(), vec!["main.cs"]);
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.set_compiler(create_test_compiler()).unwrap();
+        vm.set_file_system(Arc::new(files)).unwrap();
+        vm.exec("scripts/../scripts/main.cs").unwrap();
+        assert_eq!(read_global_string(&vm, "level"), "7");
+
+        // Hosts calling the file system directly stay inside the root too
+        let files = DiskFileSystem::new(root.join("scripts"));
+        std::fs::write(root.join("outside.txt"), "secret").unwrap();
+        assert_eq!(files.read_bytes("../outside.txt").err(), Some("Path Escapes Root"));
+        assert_eq!(files.write("../written.txt", b"x").err(), Some("Path Escapes Root"));
+        assert!(!files.exists("../outside.txt"));
+        assert!(files.modified("../outside.txt").is_none());
+        assert!(!root.join("written.txt").exists());
+
+        // Absolute paths are taken as relative to the root
+        assert_eq!(files.read_bytes("/main.cs").unwrap(), b"level=7");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_exec()
+    {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        assert_eq!(vm.exec("main.cs").err().unwrap().message, "No File System Registered");
+
+        // Count how often scripts actually get compiled
+        let compiles = Arc::new(AtomicUsize::new(0));
+        let compiler = create_test_compiler();
+        let counter = compiles.clone();
+        vm.set_compiler(Arc::new(move |source: &str| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            return compiler(source);
+        })).unwrap();
+
+        let files = Arc::new(MemoryFileSystem::new());
+        files.write("scripts/main.cs", b"fn Game::start=yes; level=1; return loaded").unwrap();
+        files.write("scripts/broken.cs", b"level=2; garbage").unwrap();
+        vm.set_file_system(files.clone()).unwrap();
+
+        let result = vm.exec("scripts/main.cs").unwrap();
+        assert_eq!(result.string_value().unwrap(), "loaded");
+        assert_eq!(read_global_string(&vm, "level"), "1");
+        vm.call(&["Game", "start"], &[]).unwrap();
+        assert_eq!(read_global_string(&vm, "start"), "yes");
+
+        // Running it again reuses the compiled script, until the file changes
+        vm.set_global("level", RawValue::from(0)).unwrap();
+        vm.exec("./scripts/main.cs").unwrap();
+        assert_eq!(read_global_string(&vm, "level"), "1");
+        assert_eq!(compiles.load(Ordering::Relaxed), 1);
+
+        files.write("scripts/main.cs", b"level=3").unwrap();
+        vm.exec("scripts/main.cs").unwrap();
+        assert_eq!(read_global_string(&vm, "level"), "3");
+        assert_eq!(compiles.load(Ordering::Relaxed), 2);
+
+        // Errors name the file they came from
+        let error = vm.exec("scripts/broken.cs").err().unwrap();
+        assert_eq!(error.to_string(), "scripts/broken.cs: Syntax Error");
+        assert_eq!(vm.exec("scripts/missing.cs").err().unwrap().message, "File Not Found");
+        assert_eq!(vm.exec("../main.cs").err().unwrap().message, "Path Escapes Root");
+
+        // exec("scripts/main.cs") from script
+        files.write("scripts/main.cs", b"level=4").unwrap();
+        let opcodes = InstructionSequence::new(vec![
+            OpCode::PushString { value: "scripts/main.cs".to_owned() },
+            OpCode::CallFunction { target: vec!["exec".to_owned()] },
+            OpCode::Pop { },
+        ]);
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "level"), "4");
+        assert!(vm.exec_failure().is_none());
+
+        // Script errors only carry the message, the file is kept on the VM
+        let opcodes = InstructionSequence::new(vec![
+            OpCode::PushString { value: "scripts/broken.cs".to_owned() },
+            OpCode::CallFunction { target: vec!["exec".to_owned()] },
+            OpCode::Pop { },
+        ]);
+        assert_eq!(vm.interpret(&opcodes).err(), Some("Syntax Error"));
+        assert_eq!(vm.exec_failure().unwrap().to_string(), "scripts/broken.cs: Syntax Error");
+    }
+
+    /// %i = 0; $sum = 0.5; do { $sum = $sum + %i; %i = %i + 1; } while (%i < 5); $label = "a" SPC "b";
+    fn create_dso_sequence() -> InstructionSequence<ApplicationState>
+    {
+        return InstructionSequence::new(vec![
+            local("i"),
+            OpCode::PushInteger { value: 0 },
+            OpCode::AssignAndPop { },
+            global("sum"),
+            OpCode::PushConstant { value: RawValue::from(0.5) },
+            OpCode::AssignAndPop { },
+            // 6th index is the loop body
+            global("sum"),
+            global("sum"),
+            local("i"),
+            OpCode::Add { },
+            OpCode::AssignAndPop { },
+            local("i"),
+            local("i"),
+            OpCode::PushFloat(PushFloat { value: 1.0 }),
+            OpCode::Add { },
+            OpCode::AssignAndPop { },
+            local("i"),
+            OpCode::PushInteger { value: 5 },
+            OpCode::CompareAndJump { comparison: Comparison::LessThan, jump_if: true, target: AddressValue::AbsoluteTarget { index: 6 } },
+            global("label"),
+            OpCode::PushString { value: "a".to_owned() },
+            OpCode::PushString { value: "b".to_owned() },
+            OpCode::ConcatSeparator { separator: ' ' },
+            OpCode::AssignAndPop { },
+        ]);
+    }
+
+    #[test]
+    fn test_dso_round_trip()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+
+        // Sequences survive both as compiled and after the load time passes have resolved them
+        let mut resolved = create_dso_sequence();
+        resolved.resolve_global_handles(&vm);
+        resolved.resolve_local_slots();
+        resolved.resolve_call_sites();
+        resolved.intern_string_constants();
+
+        for sequence in [create_dso_sequence(), resolved]
+        {
+            let bytes = sequence.serialize().unwrap();
+            let mut loaded = InstructionSequence::<ApplicationState>::deserialize(&bytes).unwrap();
+            assert_eq!(loaded.ops.len(), sequence.ops.len());
+            assert_eq!(loaded.serialize().unwrap(), bytes);
+
+            vm.set_global("sum", RawValue::from(0)).unwrap();
+            loaded.resolve_global_handles(&vm);
+            vm.interpret(&loaded).unwrap();
+            assert_eq!(read_global_string(&vm, "sum"), "10.5");
+            assert_eq!(read_global_string(&vm, "label"), "a b");
+        }
+
+        // Whole scripts carry what they were built from
+        let script = CompiledScript {
+            functions: vec![(vec!["Game".to_owned(), "run".to_owned()], Function::VirtualFunction { parameters: vec!["speed".to_owned()], instructions: create_dso_sequence() })],
+            body: InstructionSequence::new(vec![OpCode::CallFunction { target: vec!["Game".to_owned(), "run".to_owned()] }])
+        };
+        let source_hash = hash_source("source");
+        let bytes = write_dso(&script, 3, source_hash).unwrap();
+
+        // Hashes are part of the file format, so they must never change between builds
+        assert_eq!(hash_source(""), 0xcbf29ce484222325);
+        assert_eq!(hash_source("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(read_dso_header(&bytes).unwrap(), DsoHeader { format_version: DSO_FORMAT_VERSION, compiler_version: 3, source_hash: source_hash });
+
+        let loaded = read_dso::<ApplicationState>(&bytes, 3, source_hash).unwrap();
+        assert_eq!(loaded.functions[0].0, vec!["Game", "run"]);
+        assert!(matches!(&loaded.functions[0].1, Function::VirtualFunction { parameters, instructions } if parameters == &["speed"] && instructions.ops.len() == 24));
+
+        assert_eq!(read_dso::<ApplicationState>(&bytes, 4, source_hash).err(), Some("Compiled Script is Out of Date"));
+        assert_eq!(read_dso::<ApplicationState>(&bytes, 3, hash_source("changed")).err(), Some("Compiled Script is Out of Date"));
+        assert_eq!(read_dso::<ApplicationState>(&bytes[.. bytes.len() - 1], 3, source_hash).err(), Some("Compiled Script is Truncated"));
+        assert_eq!(read_dso::<ApplicationState>(b"not a script", 3, source_hash).err(), Some("Not a Compiled Script"));
+
+        // Tags only mean something to the VM that created them
+        let tagged = InstructionSequence::<ApplicationState>::new(vec![OpCode::PushConstant { value: RawValue::Tagged(TaggedValue { id: 1 }) }]);
+        assert_eq!(tagged.serialize().err(), Some("Value Cannot Be Serialized"));
+    }
+
+    #[test]
+    fn test_dso_rejects_bad_indices()
+    {
+        // The slot and the jump target are the last four bytes written, flipping one puts them out of range
+        let mut slots = InstructionSequence::<ApplicationState>::new(vec![OpCode::LoadLocal { slot: 0 }]);
+        slots.local_slots = Arc::new(vec![variable_name_to_identifier("x".to_owned())]);
+        let mut bytes = slots.serialize().unwrap();
+        assert!(InstructionSequence::<ApplicationState>::deserialize(&bytes).is_ok());
+        *bytes.last_mut().unwrap() ^= 0xFF;
+        assert_eq!(InstructionSequence::<ApplicationState>::deserialize(&bytes).err(), Some("Compiled Script is Corrupt"));
+
+        // Jumping to the end of the sequence is how scripts finish, one past that is not
+        let jumps = InstructionSequence::<ApplicationState>::new(vec![OpCode::Jump { target: AddressValue::AbsoluteTarget { index: 1 } }]);
+        let mut bytes = jumps.serialize().unwrap();
+        assert!(InstructionSequence::<ApplicationState>::deserialize(&bytes).is_ok());
+        *bytes.last_mut().unwrap() ^= 0xFF;
+        assert_eq!(InstructionSequence::<ApplicationState>::deserialize(&bytes).err(), Some("Compiled Script is Corrupt"));
+
+        let backwards = InstructionSequence::<ApplicationState>::new(vec![OpCode::Jump { target: AddressValue::RelativeOffset { offset: -2 } }]);
+        assert_eq!(InstructionSequence::<ApplicationState>::deserialize(&backwards.serialize().unwrap()).err(), Some("Compiled Script is Corrupt"));
+    }
+
+    #[test]
+    fn test_dso_cache()
+    {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let compiles = Arc::new(AtomicUsize::new(0));
+        let files = Arc::new(MemoryFileSystem::new());
+        files.write("scripts/main.cs", b"fn Game::start=yes; level=1").unwrap();
+        files.write("scripts/client/ui.cs", b"ui=1").unwrap();
+        files.write("scripts/readme.txt", b"not a script").unwrap();
+
+        let create_vm = |compiler_version: u32| {
+            let vm = VirtualMachine::new(ApplicationState { running: true });
+            let compiler = create_test_compiler();
+            let counter = compiles.clone();
+            vm.set_compiler(Arc::new(move |source: &str| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                return compiler(source);
+            })).unwrap();
+            vm.set_file_system(files.clone()).unwrap();
+            vm.enable_dso_cache(compiler_version);
+            return vm;
+        };
+
+        // The first run compiles and writes the .dso, later VMs load it instead
+        let vm = create_vm(1);
+        vm.exec("scripts/main.cs").unwrap();
+        assert!(files.exists("scripts/main.cs.dso"));
+        assert_eq!(compiles.load(Ordering::Relaxed), 1);
+
+        let vm = create_vm(1);
+        vm.exec("scripts/main.cs").unwrap();
+        vm.call(&["Game", "start"], &[]).unwrap();
+        assert_eq!(read_global_string(&vm, "level"), "1");
+        assert_eq!(read_global_string(&vm, "start"), "yes");
+        assert_eq!(compiles.load(Ordering::Relaxed), 1);
+
+        // Changed sources and compilers both recompile
+        files.write("scripts/main.cs", b"level=2").unwrap();
+        let vm = create_vm(1);
+        vm.exec("scripts/main.cs").unwrap();
+        assert_eq!(read_global_string(&vm, "level"), "2");
+        assert_eq!(compiles.load(Ordering::Relaxed), 2);
+
+        let vm = create_vm(2);
+        vm.exec("scripts/main.cs").unwrap();
+        assert_eq!(compiles.load(Ordering::Relaxed), 3);
+
+        // Corrupt files are replaced
+        files.write("scripts/main.cs.dso", b"garbage").unwrap();
+        let vm = create_vm(2);
+        vm.exec("scripts/main.cs").unwrap();
+        assert_eq!(read_global_string(&vm, "level"), "2");
+        assert_eq!(compiles.load(Ordering::Relaxed), 4);
+
+        // Precompiling only touches scripts without an up to date .dso
+        let vm = create_vm(2);
+        assert_eq!(vm.precompile_scripts("scripts").unwrap(), 1);
+        assert!(files.exists("scripts/client/ui.cs.dso"));
+        assert!(!files.exists("scripts/readme.txt.dso"));
+        assert_eq!(vm.precompile_scripts("").unwrap(), 0);
+        assert_eq!(compiles.load(Ordering::Relaxed), 5);
+
+        files.write("scripts/client/broken.cs", b"garbage").unwrap();
+        assert_eq!(vm.precompile_scripts("scripts").err().unwrap().to_string(), "scripts/client/broken.cs: Syntax Error");
+
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.set_file_system(files.clone()).unwrap();
+        assert_eq!(vm.precompile_scripts("scripts").err().unwrap().message, "DSO Cache is Disabled");
+    }
+
+    #[test]
+    fn test_reload_scripts()
+    {
+        let vm = VirtualMachine::new(ApplicationState { running: true });
+        vm.set_compiler(create_test_compiler()).unwrap();
+
+        let files = Arc::new(MemoryFileSystem::new());
+        files.write("scripts/main.cs", b"fn Game::start=one; fn Game::stop=one; fn helper=one; level=1").unwrap();
+        vm.set_file_system(files.clone()).unwrap();
+        vm.exec("scripts/main.cs").unwrap();
+        vm.set_global("kept", RawValue::from("host")).unwrap();
+
+        // Game::start(); through a resolved call site, so reloading has to invalidate its cache
+        let mut opcodes = InstructionSequence::new(vec![
+            OpCode::CallFunction { target: vec!["Game".to_owned(), "start".to_owned()] },
+        ]);
+        opcodes.resolve_call_sites();
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "start"), "one");
+
+        assert_eq!(vm.reload_scripts().unwrap(), ReloadReport::default());
+
+        files.write("scripts/main.cs", b"fn GAME::start=two; fn helper=one; fn Game::pause=new; level=2").unwrap();
+        let report = vm.reload_scripts().unwrap();
+        assert_eq!(report.files, vec!["scripts/main.cs"]);
+        assert_eq!(report.added, vec!["Game::pause"]);
+        assert_eq!(report.changed, vec!["GAME::start"]);
+        assert_eq!(report.removed, vec!["Game::stop"]);
+
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "start"), "two");
+        assert!(vm.call(&["Game", "stop"], &[]).is_err());
+        vm.call(&["Game", "pause"], &[]).unwrap();
+        assert_eq!(read_global_string(&vm, "pause"), "new");
+
+        // The file's statements ran again, nothing else was touched
+        assert_eq!(read_global_string(&vm, "level"), "2");
+        assert_eq!(read_global_string(&vm, "kept"), "host");
+
+        // Broken files keep their functions and name themselves in the errors, the other files still reload
+        files.write("scripts/other.cs", b"fn helper=other").unwrap();
+        vm.exec("scripts/other.cs").unwrap();
+        files.write("scripts/main.cs", b"fn Game::start=three; garbage").unwrap();
+        files.write("scripts/other.cs", b"fn helper=changed").unwrap();
+        let report = vm.reload_scripts().unwrap();
+        assert_eq!(report.errors.iter().map(|error| error.to_string()).collect::<Vec<String>>(), vec!["scripts/main.cs: Syntax Error"]);
+        assert_eq!(report.files, vec!["scripts/other.cs"]);
+        assert_eq!(report.changed, vec!["helper"]);
+        vm.interpret(&opcodes).unwrap();
+        assert_eq!(read_global_string(&vm, "start"), "two");
+
+        // helper belongs to the file that declared it last, main.cs no longer declaring it leaves it alone
+        files.write("scripts/main.cs", b"fn Game::start=two; fn Game::pause=new").unwrap();
+        let report = vm.reload_scripts().unwrap();
+        assert_eq!(report.files, vec!["scripts/main.cs"]);
+        assert!(report.removed.is_empty());
+        vm.call(&["helper"], &[]).unwrap();
+        assert_eq!(read_global_string(&vm, "helper"), "changed");
+
+        // Files that went missing keep their functions too
+        files.remove("scripts/main.cs");
+        files.remove("scripts/other.cs");
+        assert_eq!(vm.reload_scripts().unwrap(), ReloadReport::default());
+        vm.call(&["helper"], &[]).unwrap();
+        vm.call(&["Game", "pause"], &[]).unwrap();
+    }
+
+    /// function recurse() { $depth = $depth + 1; if ($depth < limit) callee(); }
+    fn create_recursive_function(limit: i32, callee: &str) -> Function<ApplicationState>
+    {
+        return Function::VirtualFunction {
+            parameters: vec![],
+            instructions: InstructionSequence::new(vec![
+                global("depth"),
+                global("depth"),
+                OpCode::PushInteger { value: 1 },
+                OpCode::Add { },
+                OpCode::AssignAndPop { },
+                global("depth"),
+                OpCode::PushInteger { value: limit },
+                OpCode::LessThan { },
+                OpCode::JumpFalse { target: AddressValue::AbsoluteTarget { index: 10 } },
+                OpCode::CallFunction { target: vec![callee.to_owned()] },
+            ])
+        };
+    }
+
+    #[test]
+    fn test_deep_recursion()
+    {
+        for dispatch in [DispatchMode::Match, DispatchMode::Threaded]
+        {
+            // Far deeper than the host stack would allow if every call recursed
+            let vm = VirtualMachine::new(ApplicationState { running: true });
+            vm.set_dispatch(dispatch);
+            vm.set_max_call_depth(100_000);
+            vm.add_function(create_recursive_function(100_000, "recurse"), &["recurse".to_owned()]).unwrap();
+
+            vm.set_global("depth", RawValue::from(0)).unwrap();
+            vm.call(&["recurse"], &[]).unwrap();
+            assert_eq!(read_global_string(&vm, "depth"), "100000");
+
+            // Runaway recursion stops at the limit with an error
+            vm.set_max_call_depth(100);
+            vm.add_function(create_recursive_function(i32::MAX, "recurse"), &["recurse".to_owned()]).unwrap();
+
+            vm.set_global("depth", RawValue::from(0)).unwrap();
+            let opcodes = InstructionSequence::new(vec![OpCode::CallFunction { target: vec!["recurse".to_owned()] }]);
+            assert_eq!(vm.interpret(&opcodes).err(), Some("Maximum Call Depth Exceeded"));
+            assert_eq!(read_global_string(&vm, "depth"), "100");
+        }
+    }
+
+    #[test]
+    fn test_reentrant_recursion()
+    {
+        for dispatch in [DispatchMode::Match, DispatchMode::Threaded]
+        {
+            let vm = VirtualMachine::new(ApplicationState { running: true });
+            vm.set_dispatch(dispatch);
+            vm.set_max_call_depth(10);
+
+            // Every level goes through a native that runs script again, so each one grows the host stack
+            vm.add_function(Function::NativeFunction {
+                parameters: vec![],
+                binding: Box::new(|vm, _frame| -> NativeResult<ApplicationState> {
+                    vm.call(&["recurse"], &[])?;
+                    Ok(None)
+                })
+            }, &["reenter".to_owned()]).unwrap();
+            let opcodes = InstructionSequence::new(vec![OpCode::CallFunction { target: vec!["recurse".to_owned()] }]);
+
+            vm.add_function(create_recursive_function(i32::MAX, "reenter"), &["recurse".to_owned()]).unwrap();
+            vm.set_global("depth", RawValue::from(0)).unwrap();
+            assert_eq!(vm.interpret(&opcodes).err(), Some("Maximum Call Depth Exceeded"));
+            assert_eq!(read_global_string(&vm, "depth"), "10");
+
+            // The depth unwinds with the error, shallower recursion still runs to completion
+            vm.add_function(create_recursive_function(10, "reenter"), &["recurse".to_owned()]).unwrap();
+            vm.set_global("depth", RawValue::from(0)).unwrap();
+            vm.interpret(&opcodes).unwrap();
+            assert_eq!(read_global_string(&vm, "depth"), "10");
+        }
+    }
+
+    #[test]
+    fn test_calls_resume_caller()
+    {
+        for dispatch in [DispatchMode::Match, DispatchMode::Threaded]
+        {
+            let vm = VirtualMachine::new(ApplicationState { running: true });
+            vm.set_dispatch(dispatch);
+            vm.set_max_call_depth(DEFAULT_MAX_CALL_DEPTH);
+
+            // function inner() { %x = 7; $inner = %x; return 1; }
+            vm.add_function(Function::VirtualFunction {
+                parameters: vec![],
+                instructions: InstructionSequence::new(vec![
+                    local("x"),
+                    OpCode::PushInteger { value: 7 },
+                    OpCode::AssignAndPop { },
+                    global("inner"),
+                    local("x"),
+                    OpCode::AssignAndPop { },
+                    OpCode::PushInteger { value: 1 },
+                    OpCode::Return { },
+                ])
+            }, &["inner".to_owned()]).unwrap();
+
+            // %x = 5; $sum = 2 + (inner(), 3); $after = %x;
+            let mut opcodes = InstructionSequence::new(vec![
+                local("x"),
+                OpCode::PushInteger { value: 5 },
+                OpCode::AssignAndPop { },
+                global("sum"),
+                OpCode::PushInteger { value: 2 },
+                OpCode::CallFunction { target: vec!["inner".to_owned()] },
+                OpCode::PushInteger { value: 3 },
+                OpCode::Add { },
+                OpCode::AssignAndPop { },
+                global("after"),
+                local("x"),
+                OpCode::AssignAndPop { },
+            ]);
+            opcodes.resolve_local_slots();
+            opcodes.resolve_call_sites();
+            vm.interpret(&opcodes).unwrap();
+
+            // The callee's locals and stack never touched the caller's
+            assert_eq!(read_global_string(&vm, "inner"), "7");
+            assert_eq!(read_global_string(&vm, "sum"), "5");
+            assert_eq!(read_global_string(&vm, "after"), "5");
+        }
+    }
+}
@@ -0,0 +1,566 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use crate::vm::{
+    VirtualMachine, InstructionSequence, OpCode, VariableReference, VariableIdentifier, SystemValue, RawValue, StackFrame, FloatValue,
+    IntegerValue, StringValue, BooleanValue, TaggedValue, CallSite, LocalSlot, Comparison, RootSequence, store_slot
+};
+use crate::passes::jump_target;
+
+/// Which loop the VM executes instruction sequences with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DispatchMode
+{
+    /// Matches on each OpCode as it is reached
+    #[default]
+    Match,
+
+    /// Runs the sequence's pre-decoded ThreadedSequence, see InstructionSequence::threaded
+    Threaded
+}
+
+/// Executes one pre-decoded instruction. The program counter already points at the next instruction
+/// when the handler runs, so only jumps need to touch it.
+pub type Handler<State> = fn(&VirtualMachine<State>, &mut StackFrame<State>, &Immediate<State>, &mut usize) -> Result<(), &'static str>;
+
+/// Operands decoded ahead of time. Jump targets are absolute and call targets are pre-hashed, so
+/// handlers never have to look at the original OpCode.
+pub enum Immediate<State> where State: Clone
+{
+    None,
+    Value(RawValue<State>),
+    Variable(VariableReference<State>),
+    Slot(LocalSlot),
+    Increment(LocalSlot, f32),
+    Target(usize),
+    Compare(Comparison, bool, usize),
+
+    /// Separator, and the slot the result is stored to straight after if any
+    Concat(Option<char>, Option<LocalSlot>),
+    Call(CallSite<State>),
+    Tag(String)
+}
+
+pub struct ThreadedInstruction<State> where State: Clone
+{
+    pub handler: Handler<State>,
+    pub immediate: Immediate<State>
+}
+
+/// An InstructionSequence decoded into a handler table, so dispatch is a single indirect call per instruction.
+pub struct ThreadedSequence<State> where State: Clone
+{
+    pub instructions: Vec<ThreadedInstruction<State>>,
+
+    /// Identifier of the local held in each slot, as in the source sequence
+    pub local_slots: Arc<Vec<VariableIdentifier>>
+}
+
+impl<State> ThreadedSequence<State> where State: Clone
+{
+    pub fn compile(sequence: &InstructionSequence<State>) -> Self
+    {
+        let mut instructions: Vec<ThreadedInstruction<State>> = Vec::with_capacity(sequence.ops.len());
+
+        for (index, op) in sequence.ops.iter().enumerate()
+        {
+            let next = sequence.ops.get(index + 1);
+            let (handler, immediate): (Handler<State>, Immediate<State>) = match op {
+                OpCode::NOP {  } => (nop, Immediate::None),
+                OpCode::Swap {  } => (swap, Immediate::None),
+                OpCode::Pop {  } => (pop, Immediate::None),
+                OpCode::PushFloat (value) => (push_value, Immediate::Value(RawValue::Float { 0: FloatValue { value: value.value }})),
+                OpCode::PushInteger { value } => (push_value, Immediate::Value(RawValue::Integer { 0: IntegerValue { value: *value }})),
+                OpCode::PushString { value } => (push_value, Immediate::Value(RawValue::String { 0: StringValue { value: Arc::new(value.clone()) }})),
+                OpCode::PushSharedString { value } => (push_value, Immediate::Value(RawValue::String { 0: StringValue { value: value.clone() }})),
+                OpCode::PushConstant { value } => (push_value, Immediate::Value(value.clone())),
+                OpCode::PushTaggedString { value } => (push_tagged_string, Immediate::Tag(value.clone())),
+                OpCode::PushVariable { variable } => (push_variable, Immediate::Variable(variable.clone())),
+                OpCode::LoadLocal { slot } => (load_local, Immediate::Slot(*slot)),
+                OpCode::StoreLocal { slot } => (store_local, Immediate::Slot(*slot)),
+                OpCode::Assignment {  } => (assignment, Immediate::None),
+                OpCode::AssignAndPop {  } => (assign_and_pop, Immediate::None),
+                OpCode::AssignLocalAndPop { slot } => (assign_local_and_pop, Immediate::Slot(*slot)),
+                OpCode::IncrementLocal { slot, amount } => (increment_local, Immediate::Increment(*slot, *amount)),
+                OpCode::Jump { target } => (jump, Immediate::Target(jump_target(index, target))),
+                OpCode::JumpTrue { target } => (jump_true, Immediate::Target(jump_target(index, target))),
+                OpCode::JumpFalse { target } => (jump_false, Immediate::Target(jump_target(index, target))),
+                OpCode::JumpTrueOrPop { target } => (jump_true_or_pop, Immediate::Target(jump_target(index, target))),
+                OpCode::JumpFalseOrPop { target } => (jump_false_or_pop, Immediate::Target(jump_target(index, target))),
+                OpCode::CompareAndJump { comparison, jump_if, target } => (compare_and_jump, Immediate::Compare(*comparison, *jump_if, jump_target(index, target))),
+                OpCode::CallFunction { target } => (call, Immediate::Call(CallSite::new(target.clone()))),
+                OpCode::CallCached { site } => (call, Immediate::Call(site.clone())),
+                OpCode::Return {  } => (return_value, Immediate::None),
+                OpCode::Concat {  } => (concat, Immediate::Concat(None, store_slot(next))),
+                OpCode::ConcatSeparator { separator } => (concat, Immediate::Concat(Some(*separator), store_slot(next))),
+                OpCode::Negate {  } => (negate, Immediate::None),
+                OpCode::Not {  } => (not, Immediate::None),
+                OpCode::ToBoolean {  } => (to_boolean, Immediate::None),
+                OpCode::BitwiseNot {  } | OpCode::OnesComplement {  } => (bitwise_not, Immediate::None),
+                OpCode::Detag {  } => (detag, Immediate::None),
+                OpCode::GetTaggedString {  } => (get_tagged_string, Immediate::None),
+                OpCode::LogicalAnd {  } => (logical_and, Immediate::None),
+                OpCode::LogicalOr {  } => (logical_or, Immediate::None),
+                OpCode::BitwiseAnd {  } => (bitwise_and, Immediate::None),
+                OpCode::BitwiseOr {  } => (bitwise_or, Immediate::None),
+                OpCode::BitwiseXor {  } => (bitwise_xor, Immediate::None),
+                OpCode::ShiftLeft {  } => (shift_left, Immediate::None),
+                OpCode::ShiftRight {  } => (shift_right, Immediate::None),
+                OpCode::Add {  } => (add, Immediate::None),
+                OpCode::Minus {  } => (minus, Immediate::None),
+                OpCode::Multiply {  } => (multiply, Immediate::None),
+                OpCode::Divide {  } => (divide, Immediate::None),
+                OpCode::IntegerDivide {  } => (integer_divide, Immediate::None),
+                OpCode::Modulus {  } => (modulus, Immediate::None),
+                OpCode::LessThan {  } => (compare, Immediate::Compare(Comparison::LessThan, true, 0)),
+                OpCode::LessThanOrEqual {  } => (compare, Immediate::Compare(Comparison::LessThanOrEqual, true, 0)),
+                OpCode::GreaterThan {  } => (compare, Immediate::Compare(Comparison::GreaterThan, true, 0)),
+                OpCode::GreaterThanOrEqual {  } => (compare, Immediate::Compare(Comparison::GreaterThanOrEqual, true, 0)),
+                OpCode::Equals {  } => (compare, Immediate::Compare(Comparison::Equals, true, 0)),
+                OpCode::NotEquals {  } => (compare, Immediate::Compare(Comparison::NotEquals, true, 0)),
+                OpCode::StringEquals {  } => (string_equals, Immediate::None),
+                OpCode::StringNotEqual {  } => (string_not_equal, Immediate::None)
+            };
+
+            instructions.push(ThreadedInstruction { handler: handler, immediate: immediate });
+        }
+
+        return Self
+        {
+            instructions: instructions,
+            local_slots: sequence.local_slots.clone()
+        };
+    }
+}
+
+impl<State> VirtualMachine<'_, State> where State: Clone
+{
+    /// Executes a threaded sequence.
+    pub fn interpret_threaded(&self, sequence: &ThreadedSequence<State>) -> Result<(), &'static str>
+    {
+        self.execute_threaded(sequence, StackFrame::with_slots(&sequence.local_slots))?;
+        return Ok(());
+    }
+
+    /// Runs a threaded sequence in a frame the caller has already set up, producing whatever it returned.
+    pub fn execute_threaded(&self, sequence: &ThreadedSequence<State>, frame: StackFrame<State>) -> Result<Option<RawValue<State>>, &'static str>
+    {
+        return self.execute_frames(RootSequence::Threaded(sequence), frame);
+    }
+
+    /// Runs a threaded sequence from the given index until it finishes, returns or stops to make a call, see execute_frames.
+    pub(crate) fn run_threaded(&self, sequence: &ThreadedSequence<State>, frame: &mut StackFrame<State>, start: usize) -> Result<(), &'static str>
+    {
+        let mut current_index: usize = start;
+        let op_count = sequence.instructions.len();
+
+        while current_index < op_count
+        {
+            let instruction = &sequence.instructions[current_index];
+            current_index += 1;
+            (instruction.handler)(self, frame, &instruction.immediate, &mut current_index)?;
+        }
+
+        return Ok(());
+    }
+}
+
+#[inline(always)]
+fn pop_value<State>(frame: &mut StackFrame<State>) -> Result<SystemValue<State>, &'static str> where State: Clone
+{
+    return frame.stack.pop().ok_or("Failed to Pop Value from Stack");
+}
+
+/// Pops the operands of a binary op, lhs first.
+#[inline(always)]
+fn pop_operands<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>) -> Result<(RawValue<State>, RawValue<State>), &'static str> where State: Clone
+{
+    let rhs = pop_value(frame)?;
+    let lhs = pop_value(frame)?;
+    return Ok((lhs.into_raw(vm, frame), rhs.into_raw(vm, frame)));
+}
+
+#[inline(always)]
+fn push_raw<State>(frame: &mut StackFrame<State>, value: RawValue<State>) where State: Clone
+{
+    frame.stack.push(SystemValue::Raw { value: value });
+}
+
+#[inline(always)]
+fn push_float<State>(frame: &mut StackFrame<State>, value: f32) where State: Clone
+{
+    push_raw(frame, RawValue::Float { 0: FloatValue { value: value }});
+}
+
+#[inline(always)]
+fn push_integer<State>(frame: &mut StackFrame<State>, value: i32) where State: Clone
+{
+    push_raw(frame, RawValue::Integer { 0: IntegerValue { value: value }});
+}
+
+#[inline(always)]
+fn push_boolean<State>(frame: &mut StackFrame<State>, value: bool) where State: Clone
+{
+    push_raw(frame, RawValue::Boolean { 0: BooleanValue { value: value }});
+}
+
+#[inline(always)]
+fn check_slot<State>(frame: &StackFrame<State>, slot: LocalSlot) -> Result<(), &'static str> where State: Clone
+{
+    #[cfg(feature="fault-checks")]
+    if slot >= frame.slots.len() {
+        return Err("Local Slot out of Range");
+    }
+
+    let _ = (frame, slot);
+    return Ok(());
+}
+
+fn nop<State>(_vm: &VirtualMachine<State>, _frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    return Ok(());
+}
+
+fn swap<State>(_vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let top = pop_value(frame)?;
+    let below = pop_value(frame)?;
+    frame.stack.push(top);
+    frame.stack.push(below);
+    return Ok(());
+}
+
+fn pop<State>(_vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    pop_value(frame)?;
+    return Ok(());
+}
+
+fn push_value<State>(_vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Value(value) = immediate else { return Err("Bad Immediate for Push") };
+    push_raw(frame, value.clone());
+    return Ok(());
+}
+
+fn push_tagged_string<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Tag(value) = immediate else { return Err("Bad Immediate for PushTaggedString") };
+    let id = vm.tag_string(value);
+    push_raw(frame, RawValue::Tagged { 0: TaggedValue { id }});
+    return Ok(());
+}
+
+fn push_variable<State>(_vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Variable(variable) = immediate else { return Err("Bad Immediate for PushVariable") };
+    frame.stack.push(SystemValue::Variable { value: variable.clone() });
+    return Ok(());
+}
+
+fn load_local<State>(_vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Slot(slot) = immediate else { return Err("Bad Immediate for LoadLocal") };
+    check_slot(frame, *slot)?;
+
+    let value = frame.slots[*slot].clone();
+    push_raw(frame, value);
+    return Ok(());
+}
+
+fn store_local<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Slot(slot) = immediate else { return Err("Bad Immediate for StoreLocal") };
+    check_slot(frame, *slot)?;
+
+    let value = pop_value(frame)?;
+    frame.slots[*slot] = value.into_raw(vm, frame);
+    frame.stack.push(SystemValue::Variable { value: VariableReference::LocalSlot { slot: *slot, phantom: PhantomData }});
+    return Ok(());
+}
+
+fn assignment<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let rhs = pop_value(frame)?;
+    let lhs = pop_value(frame)?;
+
+    lhs.as_variable(vm, frame)?.perform_assignment(vm, frame, &rhs)?;
+    frame.stack.push(lhs);
+    return Ok(());
+}
+
+fn assign_and_pop<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let rhs = pop_value(frame)?;
+    let lhs = pop_value(frame)?;
+
+    lhs.as_variable(vm, frame)?.perform_assignment(vm, frame, &rhs)?;
+    return Ok(());
+}
+
+fn assign_local_and_pop<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Slot(slot) = immediate else { return Err("Bad Immediate for AssignLocalAndPop") };
+    check_slot(frame, *slot)?;
+
+    let value = pop_value(frame)?;
+    frame.slots[*slot] = value.into_raw(vm, frame);
+    return Ok(());
+}
+
+fn increment_local<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Increment(slot, amount) = immediate else { return Err("Bad Immediate for IncrementLocal") };
+    check_slot(frame, *slot)?;
+
+    let result = frame.slots[*slot].as_float(vm, frame) + *amount;
+    frame.slots[*slot] = RawValue::Float { 0: FloatValue { value: result }};
+    return Ok(());
+}
+
+fn jump<State>(_vm: &VirtualMachine<State>, _frame: &mut StackFrame<State>, immediate: &Immediate<State>, index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Target(target) = immediate else { return Err("Bad Immediate for Jump") };
+    *index = *target;
+    return Ok(());
+}
+
+fn jump_true<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Target(target) = immediate else { return Err("Bad Immediate for JumpTrue") };
+    if pop_value(frame)?.as_raw(vm, frame).as_boolean(vm, frame) {
+        *index = *target;
+    }
+    return Ok(());
+}
+
+fn jump_false<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Target(target) = immediate else { return Err("Bad Immediate for JumpFalse") };
+    if !pop_value(frame)?.as_raw(vm, frame).as_boolean(vm, frame) {
+        *index = *target;
+    }
+    return Ok(());
+}
+
+fn jump_true_or_pop<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Target(target) = immediate else { return Err("Bad Immediate for JumpTrueOrPop") };
+    if pop_value(frame)?.as_raw(vm, frame).as_boolean(vm, frame) {
+        push_boolean(frame, true);
+        *index = *target;
+    }
+    return Ok(());
+}
+
+fn jump_false_or_pop<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Target(target) = immediate else { return Err("Bad Immediate for JumpFalseOrPop") };
+    if !pop_value(frame)?.as_raw(vm, frame).as_boolean(vm, frame) {
+        push_boolean(frame, false);
+        *index = *target;
+    }
+    return Ok(());
+}
+
+fn compare_and_jump<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Compare(comparison, jump_if, target) = immediate else { return Err("Bad Immediate for CompareAndJump") };
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    if comparison.apply(&lhs, &rhs, vm, frame) == *jump_if {
+        *index = *target;
+    }
+    return Ok(());
+}
+
+fn call<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Call(site) = immediate else { return Err("Bad Immediate for Call") };
+    let function = site.resolve(vm)?;
+
+    // Script functions are run by execute_frames, stop here so it can make the call
+    if vm.begin_call(function, frame, *index)?
+    {
+        *index = usize::MAX;
+    }
+    return Ok(());
+}
+
+fn return_value<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let current_value = frame.stack.pop();
+    frame.return_value = current_value.map(|value| value.into_raw(vm, frame));
+
+    // Past the end of any sequence
+    *index = usize::MAX;
+    return Ok(());
+}
+
+fn concat<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Concat(separator, store_slot) = immediate else { return Err("Bad Immediate for Concat") };
+    let rhs = pop_value(frame)?;
+    let lhs = pop_value(frame)?;
+
+    let result = vm.concat(frame, lhs, rhs, *separator, *store_slot);
+    push_raw(frame, result);
+    return Ok(());
+}
+
+fn negate<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let value = pop_value(frame)?.into_raw(vm, frame).negate(vm, frame);
+    push_float(frame, value);
+    return Ok(());
+}
+
+fn not<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let value = pop_value(frame)?.into_raw(vm, frame).as_boolean(vm, frame);
+    push_boolean(frame, !value);
+    return Ok(());
+}
+
+fn to_boolean<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let value = pop_value(frame)?.into_raw(vm, frame).as_boolean(vm, frame);
+    push_boolean(frame, value);
+    return Ok(());
+}
+
+fn bitwise_not<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let value = pop_value(frame)?.into_raw(vm, frame).as_integer(vm, frame);
+    push_integer(frame, !value);
+    return Ok(());
+}
+
+fn detag<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let value = pop_value(frame)?.into_raw(vm, frame);
+    let result = vm.detag(&value, frame);
+    push_raw(frame, RawValue::String { 0: StringValue { value: Arc::new(result) }});
+    return Ok(());
+}
+
+fn get_tagged_string<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let value = pop_value(frame)?.into_raw(vm, frame);
+    let result = vm.get_tagged_string(&value, frame);
+    push_raw(frame, RawValue::String { 0: StringValue { value: Arc::new(result) }});
+    return Ok(());
+}
+
+fn logical_and<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_boolean(frame, lhs.as_boolean(vm, frame) && rhs.as_boolean(vm, frame));
+    return Ok(());
+}
+
+fn logical_or<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_boolean(frame, lhs.as_boolean(vm, frame) || rhs.as_boolean(vm, frame));
+    return Ok(());
+}
+
+fn bitwise_and<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_integer(frame, lhs.as_integer(vm, frame) & rhs.as_integer(vm, frame));
+    return Ok(());
+}
+
+fn bitwise_or<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_integer(frame, lhs.as_integer(vm, frame) | rhs.as_integer(vm, frame));
+    return Ok(());
+}
+
+fn bitwise_xor<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_integer(frame, lhs.as_integer(vm, frame) ^ rhs.as_integer(vm, frame));
+    return Ok(());
+}
+
+fn shift_left<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_integer(frame, lhs.as_integer(vm, frame).wrapping_shl(rhs.as_integer(vm, frame) as u32));
+    return Ok(());
+}
+
+fn shift_right<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_integer(frame, (lhs.as_integer(vm, frame) as u32).wrapping_shr(rhs.as_integer(vm, frame) as u32) as i32);
+    return Ok(());
+}
+
+fn add<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_float(frame, lhs.add(&rhs, vm, frame));
+    return Ok(());
+}
+
+fn minus<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_float(frame, lhs.subtract(&rhs, vm, frame));
+    return Ok(());
+}
+
+fn multiply<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_float(frame, lhs.multiply(&rhs, vm, frame));
+    return Ok(());
+}
+
+fn divide<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_float(frame, lhs.divide(&rhs, vm, frame));
+    return Ok(());
+}
+
+fn integer_divide<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_integer(frame, lhs.integer_divide(&rhs, vm, frame));
+    return Ok(());
+}
+
+fn modulus<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_integer(frame, lhs.modulus(&rhs, vm, frame));
+    return Ok(());
+}
+
+fn compare<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let Immediate::Compare(comparison, _, _) = immediate else { return Err("Bad Immediate for Comparison") };
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    push_boolean(frame, comparison.apply(&lhs, &rhs, vm, frame));
+    return Ok(());
+}
+
+fn string_equals<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    let result = lhs.as_str(vm, frame) == rhs.as_str(vm, frame);
+    push_boolean(frame, result);
+    return Ok(());
+}
+
+fn string_not_equal<State>(vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, _immediate: &Immediate<State>, _index: &mut usize) -> Result<(), &'static str> where State: Clone
+{
+    let (lhs, rhs) = pop_operands(vm, frame)?;
+    let result = lhs.as_str(vm, frame) != rhs.as_str(vm, frame);
+    push_boolean(frame, result);
+    return Ok(());
+}
@@ -1,8 +1,12 @@
-use std::hash::{SipHasher, Hasher};
+use std::hash::Hasher;
+
+#[allow(deprecated)]
+use std::hash::SipHasher;
 
 use crate::vm::VariableIdentifier;
 
 #[inline(always)]
+#[allow(deprecated)]
 pub fn variable_name_to_identifier(name: String) -> VariableIdentifier
 {
     // Case insensitive
@@ -12,4 +16,148 @@ pub fn variable_name_to_identifier(name: String) -> VariableIdentifier
     let mut hasher = SipHasher::new();
     hasher.write(processed_string.as_bytes());
     return hasher.finish();
-}
\ No newline at end of file
+}
+
+/// Torque remaps the \c0 - \c9 color escapes around tab, newline and carriage return
+const COLOR_ESCAPE_TABLE: [char; 10] = ['\x02', '\x03', '\x04', '\x05', '\x06', '\x07', '\x08', '\x0B', '\x0C', '\x0E'];
+
+/// Processes the escape sequences of a string literal, producing the string the VM sees at runtime.
+/// Hex escapes above 0x7F are mapped to the matching Latin-1 character.
+pub fn collapse_escapes(value: &str) -> Result<String, &'static str>
+{
+    let mut result = String::with_capacity(value.len());
+    let mut characters = value.chars();
+
+    while let Some(current) = characters.next()
+    {
+        if current != '\\'
+        {
+            result.push(current);
+            continue;
+        }
+
+        match characters.next()
+        {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some('\'') => result.push('\''),
+
+            Some('x') => {
+                let high = characters.next().and_then(|digit| digit.to_digit(16));
+                let low = characters.next().and_then(|digit| digit.to_digit(16));
+
+                match (high, low) {
+                    (Some(high), Some(low)) => {
+                        result.push(char::from(((high << 4) | low) as u8));
+                    },
+                    _ => {
+                        return Err("Invalid Hex Escape");
+                    }
+                }
+            },
+
+            Some('c') => {
+                match characters.next() {
+                    // Reset, push and pop color
+                    Some('r') => result.push('\x0F'),
+                    Some('p') => result.push('\x10'),
+                    Some('o') => result.push('\x11'),
+
+                    Some(digit) if digit.is_ascii_digit() => {
+                        result.push(COLOR_ESCAPE_TABLE[digit.to_digit(10).unwrap() as usize]);
+                    },
+                    _ => {
+                        return Err("Invalid Color Escape");
+                    }
+                }
+            },
+
+            _ => {
+                return Err("Invalid Escape Sequence");
+            }
+        }
+    }
+
+    return Ok(result);
+}
+
+/// Inverse of collapse_escapes: produces the body of a string literal that collapses back to the given value.
+pub fn expand_escapes(value: &str) -> String
+{
+    let mut result = String::with_capacity(value.len());
+
+    for current in value.chars()
+    {
+        match current {
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\x0F' => result.push_str("\\cr"),
+            '\x10' => result.push_str("\\cp"),
+            '\x11' => result.push_str("\\co"),
+
+            _ => {
+                if let Some(digit) = COLOR_ESCAPE_TABLE.iter().position(|color| *color == current)
+                {
+                    result.push_str(&format!("\\c{}", digit));
+                }
+                else if current.is_control() && (current as u32) <= 0xFF
+                {
+                    result.push_str(&format!("\\x{:02x}", current as u32));
+                }
+                else
+                {
+                    result.push(current);
+                }
+            }
+        }
+    }
+
+    return result;
+}
+
+/// Case insensitive wildcard match, where * matches any run of characters and ? any single one.
+pub fn glob_match(pattern: &str, name: &str) -> bool
+{
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    let mut pattern_index: usize = 0;
+    let mut name_index: usize = 0;
+
+    // Where to resume if the most recent * has to swallow another character
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while name_index < name.len()
+    {
+        match pattern.get(pattern_index) {
+            Some('*') => {
+                backtrack = Some((pattern_index, name_index));
+                pattern_index += 1;
+            },
+            Some(expected) if *expected == '?' || *expected == name[name_index] => {
+                pattern_index += 1;
+                name_index += 1;
+            },
+            _ => {
+                match backtrack {
+                    Some((star_index, star_name_index)) => {
+                        pattern_index = star_index + 1;
+                        name_index = star_name_index + 1;
+                        backtrack = Some((star_index, star_name_index + 1));
+                    },
+                    None => {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+
+    return pattern[pattern_index ..].iter().all(|remaining| *remaining == '*');
+}
@@ -1,25 +1,67 @@
 use std::
 {
-    collections::{HashMap}, hash::{Hasher, SipHasher}, rc::Rc, borrow::{BorrowMut, Borrow}, marker::PhantomData
+    collections::{HashMap}, hash::{Hasher}, borrow::{BorrowMut, Cow}, marker::PhantomData, io::Write
 };
 
-#[cfg(feature="async")]
+// FIXME: SipHasher is deprecated but we rely on its output staying stable
+#[allow(deprecated)]
+use std::hash::SipHasher;
+
+#[cfg(not(feature="async"))]
+use std::rc::Rc;
+
 use std::sync::{RwLock, Arc};
 
 #[cfg(feature="async")]
-use std::sync;
+use std::sync::{RwLockReadGuard, RwLockWriteGuard, atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering}};
 
-#[cfg(feature="async")]
-use std::thread;
+#[cfg(not(feature="async"))]
+use std::cell::{Cell, RefCell, Ref, RefMut};
 
-use std::cell::RefCell;
+use crate::util::{variable_name_to_identifier, expand_escapes, glob_match};
+use crate::tagged_strings::{TaggedStringTable, TagIdentifier, tag_to_token, token_to_tag};
+use crate::threaded::{DispatchMode, ThreadedSequence};
+use crate::binding::{VariableBinding, VariableGetter, VariableSetter};
+use crate::watch::WatchRegistry;
+use crate::compiler::ScriptCompiler;
+use crate::filesystem::FileRegistry;
 
-use bytestream::{ByteOrder, StreamWriter};
+#[cfg(feature="jit")]
+use crate::jit::JitRegions;
+
+#[cfg(feature="async")]
+use std::sync::OnceLock;
+
+#[cfg(not(feature="async"))]
+use std::cell::OnceCell;
 
 /// Type alias to clarify that this number refers to a variable uniquely
 pub type VariableIdentifier = u64;
 
-pub type NativeFunctionBinding<State> = Box<dyn Fn(&VirtualMachine<State>, &StackFrame<State>) -> Result<(), &'static str>>;
+/// Index of a pre-resolved local within its frame
+pub type LocalSlot = usize;
+
+/// Reference counted string storage, so copying a string value between the stack and variables is cheap
+pub type SharedString = Arc<String>;
+
+/// Storage for a single global, shared by every instruction resolved to it. Entries are never removed
+/// from the globals table so handles stay valid.
+#[cfg(feature="async")]
+pub type GlobalHandle<State> = Arc<GlobalStorage<State>>;
+
+/// Storage for a single global, shared by every instruction resolved to it. Entries are never removed
+/// from the globals table so handles stay valid.
+#[cfg(not(feature="async"))]
+pub type GlobalHandle<State> = Rc<GlobalStorage<State>>;
+
+/// What a native function produces; Some to hand a value back to its caller.
+pub type NativeResult<State> = Result<Option<RawValue<State>>, &'static str>;
+
+#[cfg(feature="async")]
+pub type NativeFunctionBinding<State> = Box<dyn Fn(&VirtualMachine<State>, &StackFrame<State>) -> NativeResult<State> + Send + Sync>;
+
+#[cfg(not(feature="async"))]
+pub type NativeFunctionBinding<State> = Box<dyn Fn(&VirtualMachine<State>, &StackFrame<State>) -> NativeResult<State>>;
 
 pub struct FunctionParameter
 {
@@ -45,20 +87,62 @@ pub enum Function<State> where State: Clone
 
 impl<State> Function<State> where State: Clone
 {
-    pub fn call(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> Result<(), &'static str>
+    /// Calls the function with its arguments on top of the frame's stack, producing its return value if it had one.
+    /// Script functions bind them to their parameters, natives read the stack themselves.
+    pub fn call(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> NativeResult<State>
     {
-        match self
+        return match self
         {
-            Function::NativeFunction { parameters, binding } => {
+            Function::NativeFunction { parameters: _, binding } => {
                 // It's up to the host function to figure out parameters here
-                Ok((binding)(vm, frame)?)
+                (binding)(vm, frame)
             }
 
             // Execute virtual function code
             Function::VirtualFunction { parameters, instructions } => {
-                Ok(vm.interpret(instructions)?)
+                let mut callee = StackFrame::for_sequence(instructions);
+                callee.bind_arguments(parameters, frame, vm);
+                vm.execute(instructions, callee)
             }
-        }
+        };
+    }
+
+    /// Names of the arguments the function takes.
+    pub fn parameters(&self) -> &[String]
+    {
+        return match self
+        {
+            Function::NativeFunction { parameters, binding: _ } => parameters,
+            Function::VirtualFunction { parameters, instructions: _ } => parameters
+        };
+    }
+
+    /// Calls the function from the host, producing its return value or "" if it didn't return one.
+    /// Arguments are bound to the named parameters in order, missing ones are left as "" and extra ones are dropped.
+    /// Native functions find their arguments on the frame's stack instead, in order.
+    pub fn invoke(&self, vm: &VirtualMachine<State>, arguments: &[RawValue<State>]) -> Result<RawValue<State>, &'static str>
+    {
+        let return_value = match self
+        {
+            Function::NativeFunction { parameters: _, binding } => {
+                let mut frame = StackFrame::new();
+                frame.stack.extend(arguments.iter().map(|argument| SystemValue::Raw { value: argument.clone() }));
+
+                (binding)(vm, &frame)?
+            }
+
+            Function::VirtualFunction { parameters, instructions } => {
+                let mut frame = StackFrame::for_sequence(instructions);
+                for (parameter, argument) in parameters.iter().zip(arguments.iter())
+                {
+                    frame.set_local(parameter, argument.clone());
+                }
+
+                vm.execute(instructions, frame)?
+            }
+        };
+
+        return Ok(return_value.unwrap_or(RawValue::String { 0: StringValue { value: SharedString::default() }}));
     }
 }
 
@@ -90,7 +174,28 @@ pub struct Namespace<'a, State> where State: Clone
     pub functions: Arc<RwLock<HashMap<String, Arc<Function<State>>>>>,
 
     /// Lookup cache for function data
-    pub function_cache: RefCell<HashMap<u64, Arc<Function<State>>>>
+    #[cfg(not(feature="async"))]
+    pub function_cache: RefCell<HashMap<u64, Arc<Function<State>>>>,
+
+    /// Lookup cache for function data
+    #[cfg(feature="async")]
+    pub function_cache: RwLock<HashMap<u64, Arc<Function<State>>>>,
+
+    /// Bumped whenever a function is added anywhere beneath this namespace, invalidating call site caches
+    #[cfg(not(feature="async"))]
+    generation: Cell<u64>,
+
+    /// Bumped whenever a function is added anywhere beneath this namespace, invalidating call site caches
+    #[cfg(feature="async")]
+    generation: AtomicU64
+}
+
+impl<State> Default for Namespace<'_, State> where State: Clone
+{
+    fn default() -> Self
+    {
+        return Self::new();
+    }
 }
 
 impl<State> Namespace<'_, State> where State: Clone
@@ -103,7 +208,8 @@ impl<State> Namespace<'_, State> where State: Clone
             children: RefCell::new(HashMap::new()),
             classes: RefCell::new(HashMap::new()),
             functions: RefCell::new(HashMap::new()),
-            function_cache: RefCell::new(HashMap::new())
+            function_cache: RefCell::new(HashMap::new()),
+            generation: Cell::new(0)
         };
     }
 
@@ -115,12 +221,50 @@ impl<State> Namespace<'_, State> where State: Clone
             children: Arc::new(RwLock::new(HashMap::new())),
             classes: Arc::new(RwLock::new(HashMap::new())),
             functions: Arc::new(RwLock::new(HashMap::new())),
-            function_cache: RefCell::new(HashMap::new())
+            function_cache: RwLock::new(HashMap::new()),
+            generation: AtomicU64::new(0)
         };
     }
 
-    pub fn add_function_entry_slice(&mut self, function: Function<State>, path: &[String]) -> Result<(), &'static str>
+    /// Current generation, see CallSite.
+    #[cfg(not(feature="async"))]
+    #[inline(always)]
+    pub fn generation(&self) -> u64
+    {
+        return self.generation.get();
+    }
+
+    /// Current generation, see CallSite.
+    #[cfg(feature="async")]
+    #[inline(always)]
+    pub fn generation(&self) -> u64
+    {
+        return self.generation.load(Ordering::Acquire);
+    }
+
+    /// Invalidates everything resolved through this namespace so far.
+    fn invalidate(&self)
+    {
+        #[cfg(not(feature="async"))]
+        {
+            self.generation.set(self.generation.get() + 1);
+            self.function_cache.borrow_mut().clear();
+        }
+
+        #[cfg(feature="async")]
+        {
+            self.function_cache.write().unwrap().clear();
+            self.generation.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Stores a function as is. Everything else registers through VirtualMachine::add_function, which runs the
+    /// load time passes first, so a function behaves the same however it was added.
+    pub(crate) fn add_function_entry_slice(&mut self, function: Function<State>, path: &[String]) -> Result<(), &'static str>
     {
+        // Anything resolved through this namespace may now resolve differently
+        self.invalidate();
+
         // Need to descend more
         if path.len() > 1
         {
@@ -143,7 +287,7 @@ impl<State> Namespace<'_, State> where State: Clone
 
         // We're at the final stop
         let function_name = &path[0].to_lowercase();
-        let mut functions_write = self.functions.borrow_mut();
+        let functions_write = self.functions.borrow_mut();
 
         #[cfg(not(feature="async"))]
         return match functions_write.insert(function_name.clone(), Rc::new(function))
@@ -170,9 +314,50 @@ impl<State> Namespace<'_, State> where State: Clone
         };
     }
 
-    pub fn add_function_entry(&mut self, function: Function<State>, path: &Vec<String>) -> Result<(), &'static str>
+    /// Removes a function, returning whether it existed.
+    pub fn remove_function_entry_slice(&mut self, path: &[String]) -> bool
+    {
+        // Anything resolved through this namespace may now resolve differently
+        self.invalidate();
+
+        // Need to descend more
+        if path.len() > 1
+        {
+            let next_namespace_name = &path[0].to_lowercase();
+
+            #[cfg(not(feature="async"))]
+            let mut namespace_write = self.children.borrow_mut();
+
+            #[cfg(feature="async")]
+            let mut namespace_write = self.children.write().unwrap();
+
+            return match namespace_write.get_mut(next_namespace_name) {
+                Some(next_namespace) => next_namespace.remove_function_entry_slice(&path[1 ..]),
+                None => false
+            };
+        }
+
+        let function_name = &path[0].to_lowercase();
+
+        #[cfg(not(feature="async"))]
+        return self.functions.borrow_mut().remove(function_name).is_some();
+
+        #[cfg(feature="async")]
+        return self.functions.write().unwrap().remove(function_name).is_some();
+    }
+
+    /// Creates any namespaces along the path that don't exist yet, e.g. ["Game", "Rules"].
+    pub fn add_namespace_path(&self, path: &[String])
     {
-        return self.add_function_entry_slice(function, path.as_slice());
+        let Some(next_namespace_name) = path.first() else { return };
+
+        #[cfg(not(feature="async"))]
+        let mut namespace_write = self.children.borrow_mut();
+
+        #[cfg(feature="async")]
+        let mut namespace_write = self.children.write().unwrap();
+
+        namespace_write.entry(next_namespace_name.to_lowercase()).or_default().add_namespace_path(&path[1 ..]);
     }
 
     /// Performs a recursive search for a given function with no caching.
@@ -224,27 +409,35 @@ impl<State> Namespace<'_, State> where State: Clone
         };
     }
 
-    pub fn lookup_function_cached(&mut self, path: &Vec<String>) -> Result<Arc<Function<State>>, &'static str> //Result<Rc<Function<State>>, &'static str>
+    pub fn lookup_function_cached(&self, path: &Vec<String>) -> Result<Arc<Function<State>>, &'static str> //Result<Rc<Function<State>>, &'static str>
+    {
+        return self.lookup_function_hashed(path.as_slice(), hash_function_path(path.as_slice()));
+    }
+
+    /// Cached lookup for a path already hashed with hash_function_path.
+    pub fn lookup_function_hashed(&self, path: &[String], lookup_id: u64) -> Result<Arc<Function<State>>, &'static str>
     {
-        let mut hasher = SipHasher::new();
-        for path_element in path.iter()
+        #[cfg(not(feature="async"))]
+        let cache_read = self.function_cache.borrow();
+
+        #[cfg(feature="async")]
+        let cache_read = self.function_cache.read().unwrap();
+
+        if let Some(cache_hit) = cache_read.get(&lookup_id)
         {
-            hasher.write(path_element.to_lowercase().as_bytes());
+            return Ok(cache_hit.clone());
         }
-        let lookup_id = hasher.finish();
+        drop(cache_read);
 
-        let cache_write = self.function_cache.borrow_mut();
-        let cache_search = cache_write.get(&lookup_id);
+        let slow_search = self.lookup_function_uncached_slice(path)?;
 
-        return match cache_search {
-            Some(cache_hit) => {
-                Ok(cache_hit.clone())
-            },
-            None => {
-                let slow_search = self.lookup_function_uncached_slice(path.as_slice())?;
-                Ok(slow_search)
-            }
-        };
+        #[cfg(not(feature="async"))]
+        self.function_cache.borrow_mut().insert(lookup_id, slow_search.clone());
+
+        #[cfg(feature="async")]
+        self.function_cache.write().unwrap().insert(lookup_id, slow_search.clone());
+
+        return Ok(slow_search);
     }
 
     pub fn lookup_function_uncached(&self, path: Vec<String>) -> Result<Arc<Function<State>>, &'static str> //Result<Rc<Function<State>>, &'static str>
@@ -253,6 +446,98 @@ impl<State> Namespace<'_, State> where State: Clone
     }
 }
 
+/// Hashes a function path case insensitively, as used to key the function cache.
+#[allow(deprecated)]
+pub fn hash_function_path(path: &[String]) -> u64
+{
+    let mut hasher = SipHasher::new();
+    for path_element in path.iter()
+    {
+        hasher.write(path_element.to_lowercase().as_bytes());
+    }
+    return hasher.finish();
+}
+
+/// A call target hashed at load time, along with an inline cache of the function it last resolved to.
+pub struct CallSite<State> where State: Clone
+{
+    pub path: Vec<String>,
+
+    /// hash_function_path of the path
+    pub hash: u64,
+
+    /// Namespace generation the cached function was resolved at
+    #[cfg(feature="async")]
+    cache: RwLock<Option<(u64, Arc<Function<State>>)>>,
+
+    /// Namespace generation the cached function was resolved at
+    #[cfg(not(feature="async"))]
+    cache: RefCell<Option<(u64, Arc<Function<State>>)>>
+}
+
+impl<State> Clone for CallSite<State> where State: Clone
+{
+    /// Clones start with an empty cache
+    fn clone(&self) -> Self
+    {
+        return Self::new(self.path.clone());
+    }
+}
+
+impl<State> CallSite<State> where State: Clone
+{
+    pub fn new(path: Vec<String>) -> Self
+    {
+        let hash = hash_function_path(path.as_slice());
+
+        return Self
+        {
+            path: path,
+            hash: hash,
+
+            #[cfg(feature="async")]
+            cache: RwLock::new(None),
+
+            #[cfg(not(feature="async"))]
+            cache: RefCell::new(None)
+        };
+    }
+
+    /// Returns the function this site calls, only going to the namespace if it changed since the last call.
+    #[inline(always)]
+    pub fn resolve(&self, vm: &VirtualMachine<State>) -> Result<Arc<Function<State>>, &'static str>
+    {
+        let namespace_read = vm.namespace();
+        let generation = namespace_read.generation();
+
+        #[cfg(feature="async")]
+        let cache_read = self.cache.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let cache_read = self.cache.borrow();
+
+        if let Some((cached_generation, function)) = &*cache_read
+        {
+            if *cached_generation == generation
+            {
+                return Ok(function.clone());
+            }
+        }
+        drop(cache_read);
+
+        let function = namespace_read.lookup_function_hashed(self.path.as_slice(), self.hash)?;
+
+        #[cfg(feature="async")]
+        let mut cache_write = self.cache.write().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let mut cache_write = self.cache.borrow_mut();
+
+        *cache_write = Some((generation, function.clone()));
+        return Ok(function);
+    }
+}
+
 /// A virtual class in memory, used for typedefs
 pub struct ClassEntry<State> where State: Clone
 {
@@ -274,41 +559,74 @@ pub enum AddressValue {
 }
 
 #[derive(Debug, Clone)]
-pub enum VariableReference<State> {
+pub enum VariableReference<State> where State: Clone {
     Global {
         phantom: PhantomData<State>,
-        value: VariableIdentifier
+        value: VariableIdentifier,
+
+        /// Name as written, without the $, so the VM can enumerate and export the global
+        name: SharedString
     },
     Local {
         phantom: PhantomData<State>,
         value: VariableIdentifier
+    },
+
+    /// A local resolved ahead of time by InstructionSequence::resolve_local_slots
+    LocalSlot {
+        phantom: PhantomData<State>,
+        slot: LocalSlot
+    },
+
+    /// A global resolved ahead of time by InstructionSequence::resolve_global_handles
+    GlobalHandle {
+        handle: GlobalHandle<State>
     }
 }
 
 impl<State> VariableReference<State> where State: Clone
 {
+    /// Reference to a global by name, with or without the leading $.
+    pub fn global(name: &str) -> Self
+    {
+        let name = global_name(name);
+        return VariableReference::Global { value: variable_name_to_identifier(name.to_owned()), name: Arc::new(name.to_owned()), phantom: PhantomData };
+    }
+
+    /// Fails if the global is bound to the host and rejects the value.
     #[inline(always)]
-    fn perform_assignment(&self, vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, rhs: &SystemValue<State>)
+    pub(crate) fn perform_assignment(&self, vm: &VirtualMachine<State>, frame: &mut StackFrame<State>, rhs: &SystemValue<State>) -> Result<(), &'static str>
     {
+        // Resolve the value before taking the lock, the rhs may itself be a global
+        let resolved = rhs.as_raw(vm, frame);
+
         match self {
-            VariableReference::Global { value, phantom } => {
-                #[cfg(feature="async")]
-                {
-                    let mut globals_write = vm.globals.write().unwrap();
-                    globals_write.insert((*value).clone(), rhs.as_raw(vm, frame));
-                }
+            VariableReference::Global { value, name, phantom: _ } => {
+                return vm.assign_global(&vm.global_handle(*value, name), resolved);
+            },
 
-                #[cfg(not(feature="async"))]
-                {
-                    let mut globals_write = vm.globals.borrow_mut();
-                    globals_write.insert((*value).clone(), rhs.as_raw(vm, frame));
+            VariableReference::GlobalHandle { handle } => {
+                return vm.assign_global(handle, resolved);
+            },
+
+            VariableReference::Local { value, phantom: _ } => {
+                // Names built at runtime may still refer to a resolved local
+                match frame.find_slot(*value) {
+                    Some(slot) => {
+                        frame.slots[slot] = resolved;
+                    },
+                    None => {
+                        frame.locals.insert(*value, resolved);
+                    }
                 }
             },
 
-            VariableReference::Local { value, phantom } => {
-                frame.locals.insert((*value).clone(), rhs.as_raw(vm, frame));
+            VariableReference::LocalSlot { slot, phantom: _ } => {
+                frame.slots[*slot] = resolved;
             }
         }
+
+        return Ok(());
     }
 
     /// Performs a variable lookup, returning a raw value read from memory
@@ -316,23 +634,31 @@ impl<State> VariableReference<State> where State: Clone
     pub fn deref(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> Result<RawValue<State>, &'static str>
     {
         return match self {
-            VariableReference::Global { value, phantom } => {
+            VariableReference::Global { value, name: _, phantom: _ } => {
                 #[cfg(feature="async")]
                 let globals_read = vm.globals.read().unwrap();
 
                 #[cfg(not(feature="async"))]
-                let globals_read = vm.globals.borrow_mut();
+                let globals_read = vm.globals.borrow();
 
-                match globals_read.get(value) {
+                match globals_read.get(value).and_then(|handle| handle.get()) {
                     Some(value) => {
-                        Ok(value.clone())
+                        Ok(value)
                     },
                     None => {
                         Err("Variable Lookup Failed")
                     }
                 }
             },
-            VariableReference::Local { value, phantom } => {
+            VariableReference::GlobalHandle { handle } => {
+                handle.get().ok_or("Variable Lookup Failed")
+            },
+            VariableReference::Local { value, phantom: _ } => {
+                if let Some(slot) = frame.find_slot(*value)
+                {
+                    return Ok(frame.slots[slot].clone());
+                }
+
                 match frame.locals.get(value) {
                     Some(value) => {
                         Ok(value.clone())
@@ -341,75 +667,348 @@ impl<State> VariableReference<State> where State: Clone
                         Err("Variable Lookup Failed")
                     }
                 }
+            },
+            VariableReference::LocalSlot { slot, phantom: _ } => {
+                match frame.slots.get(*slot) {
+                    Some(value) => {
+                        Ok(value.clone())
+                    },
+                    None => {
+                        Err("Variable Lookup Failed")
+                    }
+                }
             }
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct FloatValue {
-    pub value: f32
-}
+/// Kinds of value a global's packed word can hold, stored in its upper 32 bits.
+#[cfg(feature="async")]
+const GLOBAL_UNASSIGNED: u64 = 0;
 
-#[derive(Debug, Clone)]
-pub struct IntegerValue {
-    pub value: i32
-}
+/// The value lives behind the lock, e.g. a string
+#[cfg(feature="async")]
+const GLOBAL_BOXED: u64 = 1;
 
-#[derive(Debug, Clone)]
-pub struct StringValue {
-    pub value: String
-}
+#[cfg(feature="async")]
+const GLOBAL_FLOAT: u64 = 2;
 
-#[derive(Debug, Clone)]
-pub struct BooleanValue {
-    pub value: bool
+#[cfg(feature="async")]
+const GLOBAL_INTEGER: u64 = 3;
+
+#[cfg(feature="async")]
+const GLOBAL_BOOLEAN: u64 = 4;
+
+#[cfg(feature="async")]
+const GLOBAL_TAGGED: u64 = 5;
+
+/// Packs a value that fits in 32 bits along with its kind, None for values that have to be boxed.
+#[cfg(feature="async")]
+#[inline(always)]
+fn pack_global<State>(value: &RawValue<State>) -> Option<u64> where State: Clone
+{
+    let (kind, bits) = match value {
+        RawValue::Float { 0: FloatValue { value }} => (GLOBAL_FLOAT, value.to_bits()),
+        RawValue::Integer { 0: IntegerValue { value }} => (GLOBAL_INTEGER, *value as u32),
+        RawValue::Boolean { 0: BooleanValue { value }} => (GLOBAL_BOOLEAN, *value as u32),
+        RawValue::Tagged { 0: TaggedValue { id }} => (GLOBAL_TAGGED, *id),
+        RawValue::String { 0: _ } | RawValue::Variable { 0: _ } => return None
+    };
+
+    return Some((kind << 32) | bits as u64);
 }
 
-#[derive(Debug, Clone)]
-pub struct VariableValue<State> {
-    value: VariableReference<State>
+/// Unpacks a value stored by pack_global. Unassigned and boxed globals have nothing to unpack.
+#[cfg(feature="async")]
+#[inline(always)]
+fn unpack_global<State>(packed: u64) -> Option<RawValue<State>> where State: Clone
+{
+    let bits = packed as u32;
+    return match packed >> 32 {
+        GLOBAL_FLOAT => Some(RawValue::Float { 0: FloatValue { value: f32::from_bits(bits) }}),
+        GLOBAL_INTEGER => Some(RawValue::Integer { 0: IntegerValue { value: bits as i32 }}),
+        GLOBAL_BOOLEAN => Some(RawValue::Boolean { 0: BooleanValue { value: bits != 0 }}),
+        GLOBAL_TAGGED => Some(RawValue::Tagged { 0: TaggedValue { id: bits }}),
+        _ => None
+    };
 }
 
-// VariableSoftRef = Unresolved; requires a runtime lookup
-// VariableHardRef = Resolved; memory can be read/write directly
+/// The value of a single global, or the host binding standing in for it.
+///
+/// Under async, numbers, booleans and tags are kept in a single atomic word so reading and writing them never
+/// takes a lock. Strings still go through the RwLock; the word is flipped to boxed while the lock is held, so
+/// readers that see it take the lock and get the latest string.
+pub struct GlobalStorage<State> where State: Clone
+{
+    /// Name the global was first referred to by, without the $
+    name: String,
 
-/// Wrapper value representing a stored value in the virtual machine runtime.
-#[derive(Debug, Clone)]
-pub enum SystemValue<State> where State: Clone {
-    Raw {
-        value: RawValue<State>
-    },
+    /// Set while a watcher's pattern matches the name, so writes to everything else skip the watcher lookup
+    #[cfg(feature="async")]
+    watched: AtomicBool,
 
-    Variable {
-        value: VariableReference<State>
-    }
-}
+    /// Set while a watcher's pattern matches the name, so writes to everything else skip the watcher lookup
+    #[cfg(not(feature="async"))]
+    watched: Cell<bool>,
 
-impl<State> SystemValue<State> where State: Clone {
-    #[inline(always)]
-    pub fn as_raw(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> RawValue<State> {
-        return match self {
-            SystemValue::Raw { value } => {
-                value.clone()
-            },
+    /// Kind of the current value in the upper 32 bits, and the value itself in the lower 32 if it fits
+    #[cfg(feature="async")]
+    packed: AtomicU64,
 
-            SystemValue::Variable { value } => {
-                match value.deref(vm, frame) {
-                    Ok(dereferenced) => {
-                        dereferenced
-                    },
+    /// Boxed value, only current while the packed kind is GLOBAL_BOXED
+    #[cfg(feature="async")]
+    value: RwLock<Option<RawValue<State>>>,
+
+    /// None marks a global that has not been assigned
+    #[cfg(not(feature="async"))]
+    value: RefCell<Option<RawValue<State>>>,
+
+    /// Once bound, every read and write goes to the host instead
+    #[cfg(feature="async")]
+    binding: OnceLock<VariableBinding<State>>,
+
+    /// Once bound, every read and write goes to the host instead
+    #[cfg(not(feature="async"))]
+    binding: OnceCell<VariableBinding<State>>
+}
+
+impl<State> std::fmt::Debug for GlobalStorage<State> where State: Clone + std::fmt::Debug
+{
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        if self.is_bound()
+        {
+            return formatter.write_str("GlobalStorage(bound)");
+        }
+
+        return formatter.debug_tuple("GlobalStorage").field(&self.get()).finish();
+    }
+}
+
+impl<State> GlobalStorage<State> where State: Clone
+{
+    pub fn new(name: &str) -> Self
+    {
+        return Self
+        {
+            name: name.to_owned(),
+
+            #[cfg(feature="async")]
+            watched: AtomicBool::new(false),
+
+            #[cfg(not(feature="async"))]
+            watched: Cell::new(false),
+
+            #[cfg(feature="async")]
+            packed: AtomicU64::new(GLOBAL_UNASSIGNED << 32),
+
+            #[cfg(feature="async")]
+            value: RwLock::new(None),
+
+            #[cfg(not(feature="async"))]
+            value: RefCell::new(None),
+
+            #[cfg(feature="async")]
+            binding: OnceLock::new(),
+
+            #[cfg(not(feature="async"))]
+            binding: OnceCell::new()
+        };
+    }
+
+    /// Current value, None if the global is unassigned.
+    #[inline(always)]
+    pub fn get(&self) -> Option<RawValue<State>>
+    {
+        if let Some(binding) = self.binding.get()
+        {
+            return Some((binding.getter)());
+        }
+
+        #[cfg(feature="async")]
+        {
+            let packed = self.packed.load(Ordering::Acquire);
+            return match packed >> 32 {
+                GLOBAL_UNASSIGNED => None,
+                GLOBAL_BOXED => self.value.read().unwrap().clone(),
+                _ => unpack_global(packed)
+            };
+        }
+
+        #[cfg(not(feature="async"))]
+        return self.value.borrow().clone();
+    }
+
+    /// Assigns the global, handing the value to the host instead if it is bound.
+    #[inline(always)]
+    pub fn set(&self, value: RawValue<State>) -> Result<(), &'static str>
+    {
+        if let Some(binding) = self.binding.get()
+        {
+            return (binding.setter)(&value);
+        }
+
+        #[cfg(feature="async")]
+        {
+            if let Some(packed) = pack_global(&value)
+            {
+                self.packed.store(packed, Ordering::Release);
+                return Ok(());
+            }
+
+            let mut value_write = self.value.write().unwrap();
+            *value_write = Some(value);
+            self.packed.store(GLOBAL_BOXED << 32, Ordering::Release);
+            return Ok(());
+        }
+
+        #[cfg(not(feature="async"))]
+        {
+            *self.value.borrow_mut() = Some(value);
+            return Ok(());
+        }
+    }
+
+    /// Unassigns the global, returning what it held. Bound globals belong to the host and are left alone.
+    pub fn take(&self) -> Option<RawValue<State>>
+    {
+        if self.is_bound()
+        {
+            return None;
+        }
+
+        #[cfg(feature="async")]
+        {
+            let mut value_write = self.value.write().unwrap();
+            let boxed = value_write.take();
+
+            let packed = self.packed.swap(GLOBAL_UNASSIGNED << 32, Ordering::AcqRel);
+            return match packed >> 32 {
+                GLOBAL_BOXED => boxed,
+                _ => unpack_global(packed)
+            };
+        }
+
+        #[cfg(not(feature="async"))]
+        return self.value.borrow_mut().take();
+    }
+
+    pub fn is_bound(&self) -> bool
+    {
+        return self.binding.get().is_some();
+    }
+
+    pub fn name(&self) -> &str
+    {
+        return &self.name;
+    }
+
+    #[inline(always)]
+    pub fn is_watched(&self) -> bool
+    {
+        #[cfg(feature="async")]
+        return self.watched.load(Ordering::Relaxed);
+
+        #[cfg(not(feature="async"))]
+        return self.watched.get();
+    }
+
+    pub(crate) fn set_watched(&self, watched: bool)
+    {
+        #[cfg(feature="async")]
+        self.watched.store(watched, Ordering::Relaxed);
+
+        #[cfg(not(feature="async"))]
+        self.watched.set(watched);
+    }
+
+    /// Hands the global over to the host. A global can only be bound once.
+    pub(crate) fn bind(&self, binding: VariableBinding<State>) -> Result<(), &'static str>
+    {
+        self.binding.set(binding).map_err(|_| "Variable is Already Bound")?;
+        self.take();
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FloatValue {
+    pub value: f32
+}
+
+#[derive(Debug, Clone)]
+pub struct IntegerValue {
+    pub value: i32
+}
+
+#[derive(Debug, Clone)]
+pub struct StringValue {
+    pub value: SharedString
+}
+
+#[derive(Debug, Clone)]
+pub struct BooleanValue {
+    pub value: bool
+}
+
+#[derive(Debug, Clone)]
+pub struct TaggedValue {
+    pub id: TagIdentifier
+}
+
+#[derive(Debug, Clone)]
+pub struct VariableValue<State> where State: Clone {
+    value: VariableReference<State>
+}
+
+// VariableSoftRef = Unresolved; requires a runtime lookup
+// VariableHardRef = Resolved; memory can be read/write directly
+
+/// Wrapper value representing a stored value in the virtual machine runtime.
+#[derive(Debug, Clone)]
+pub enum SystemValue<State> where State: Clone {
+    Raw {
+        value: RawValue<State>
+    },
+
+    Variable {
+        value: VariableReference<State>
+    }
+}
+
+impl<State> SystemValue<State> where State: Clone {
+    #[inline(always)]
+    pub fn as_raw(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> RawValue<State> {
+        return match self {
+            SystemValue::Raw { value } => {
+                value.clone()
+            },
+
+            SystemValue::Variable { value } => {
+                match value.deref(vm, frame) {
+                    Ok(dereferenced) => {
+                        dereferenced
+                    },
                     Err(_) => {
                         // For now we mimic Torque where invalid lookups return ""
-                        RawValue::String { 0: StringValue { value: "".to_owned() }}
+                        RawValue::String { 0: StringValue { value: SharedString::default() }}
                     }
                 }
             }
         } 
     }
 
+    /// Like as_raw, but moves a raw value out rather than cloning it.
+    #[inline(always)]
+    pub fn into_raw(self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> RawValue<State> {
+        return match self {
+            SystemValue::Raw { value } => value,
+            SystemValue::Variable { value: _ } => self.as_raw(vm, frame)
+        };
+    }
+
     #[inline(always)]
-    pub fn as_variable(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> Result<VariableReference<State>, &'static str> {
+    pub fn as_variable(&self, _vm: &VirtualMachine<State>, _frame: &StackFrame<State>) -> Result<VariableReference<State>, &'static str> {
         return match self {
             SystemValue::Raw { value: _ } => {
                 Err("Not a Variable")
@@ -433,49 +1032,73 @@ pub enum RawValue<State> where State: Clone {
     Integer(IntegerValue),
     String(StringValue),
     Boolean(BooleanValue),
+    Tagged(TaggedValue),
     Variable(VariableValue<State>)
 }
 
 impl<State> RawValue<State> where State: Clone {
     #[inline(always)]
-    fn equals(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>, rhs: &RawValue<State>) -> bool {
+    pub(crate) fn equals(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>, rhs: &RawValue<State>) -> bool {
         return self.as_float(vm, frame) == rhs.as_float(vm, frame);
     }
 
     #[inline(always)]
     pub fn as_string(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> String {
+        if let RawValue::Variable { 0: VariableValue { value }} = self
+        {
+            return match value.deref(vm, frame) {
+                Ok(dereferenced) => {
+                    dereferenced.as_string(vm, frame)
+                }
+                Err(_) => {
+                    "".to_owned()
+                }
+            };
+        }
+
+        return self.string_value().unwrap_or_default();
+    }
+
+    /// Text of the value, or None for a variable reference as reading it needs a VM and frame.
+    pub fn string_value(&self) -> Option<String> {
         return match self {
             RawValue::Float(value) => {
-                (*value).value.to_string()
+                Some(value.value.to_string())
             },
 
             RawValue::Integer { 0: IntegerValue { value }} => {
-                (*value).to_string()
+                Some((*value).to_string())
             },
 
             RawValue::String { 0: StringValue { value }} => {
-                (*value).clone()
+                Some(value.as_ref().clone())
             },
 
             RawValue::Boolean { 0: BooleanValue { value }} => {
-                (*value).to_string()
+                Some((*value).to_string())
             },
 
-            RawValue::Variable { 0: VariableValue { value }} => {
-                match value.deref(vm, frame) {
-                    Ok(dereferenced) => {
-                        dereferenced.as_string(vm, frame)
-                    }
-                    Err(_) => {
-                        "".to_owned()
-                    }
-                }
+            RawValue::Tagged { 0: TaggedValue { id }} => {
+                Some(tag_to_token(*id))
+            },
+
+            RawValue::Variable { 0: VariableValue { value: _ }} => {
+                None
             }
         }
     }
 
+    /// Borrows the text of string values, only allocating when another type has to be converted.
+    #[inline(always)]
+    pub fn as_str(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> Cow<'_, str> {
+        return match self {
+            RawValue::String { 0: StringValue { value }} => Cow::Borrowed(value.as_str()),
+            _ => Cow::Owned(self.as_string(vm, frame))
+        };
+    }
+
     #[inline(always)]
-    fn add(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
+    pub(crate) fn add(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
         let lhs = self.as_float(vm, frame);
         let rhs = rhs.as_float(vm, frame);
 
@@ -483,7 +1106,7 @@ impl<State> RawValue<State> where State: Clone {
     }
 
     #[inline(always)]
-    fn subtract(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
+    pub(crate) fn subtract(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
         let lhs = self.as_float(vm, frame);
         let rhs = rhs.as_float(vm, frame);
 
@@ -491,7 +1114,7 @@ impl<State> RawValue<State> where State: Clone {
     }
 
     #[inline(always)]
-    fn multiply(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
+    pub(crate) fn multiply(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
         let lhs = self.as_float(vm, frame);
         let rhs = rhs.as_float(vm, frame);
 
@@ -499,159 +1122,260 @@ impl<State> RawValue<State> where State: Clone {
     }
 
     #[inline(always)]
-    fn divide(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
+    pub(crate) fn divide(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
         let lhs = self.as_float(vm, frame);
         let rhs = rhs.as_float(vm, frame);
 
         return lhs / rhs;
     }
 
+    /// Integer division; Torque yields 0 rather than faulting on a zero divisor
     #[inline(always)]
-    fn negate(&mut self, vm: &mut VirtualMachine<State>, frame: &StackFrame<State>) {
-        return match self {
-            RawValue::Float(value) => {
-                (*value).value = -value.value
-            },
+    pub(crate) fn integer_divide(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> i32 {
+        let lhs = self.as_integer(vm, frame);
+        let rhs = rhs.as_integer(vm, frame);
 
-            RawValue::Integer { 0: IntegerValue { value }} => {
-                *value = -(*value);
-            },
+        if rhs == 0 {
+            return 0;
+        }
+        return lhs.wrapping_div(rhs);
+    }
 
-            RawValue::String { 0: StringValue { value }} => {
-                // FIXME
-            },
+    /// Integer remainder; like integer division a zero divisor yields 0, as does the overflowing i32::MIN % -1
+    #[inline(always)]
+    pub(crate) fn modulus(&self, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> i32 {
+        let lhs = self.as_integer(vm, frame);
+        let rhs = rhs.as_integer(vm, frame);
 
-            RawValue::Boolean { 0: BooleanValue { value }} => {
-                *value = !(*value);
-            },
+        return lhs.checked_rem(rhs).unwrap_or(0);
+    }
 
-            RawValue::Variable { 0: VariableValue { value }} => {
-                // FIXME
-            }
-        }
+    #[inline(always)]
+    pub(crate) fn negate(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
+        return -self.as_float(vm, frame);
     }
 
     #[inline(always)]
     pub fn as_float(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> f32 {
+        if let RawValue::Variable { 0: VariableValue { value }} = self
+        {
+            return match value.deref(vm, frame) {
+                Ok(dereferenced) => {
+                    dereferenced.as_float(vm, frame)
+                }
+                Err(_) => {
+                    0.0
+                }
+            };
+        }
+
+        return self.float_value().unwrap_or(0.0);
+    }
+
+    /// Numeric value, or None for a variable reference as reading it needs a VM and frame.
+    #[inline(always)]
+    pub fn float_value(&self) -> Option<f32> {
         return match self {
             RawValue::Float(value) => {
-                value.value
+                Some(value.value)
             },
 
             RawValue::Integer { 0: IntegerValue {value }} => {
-                *value as f32
+                Some(*value as f32)
             },
 
             RawValue::String { 0: StringValue { value }} => {
-                match (*value).parse::<f32>() {
-                    Ok(result) => {
-                        result
-                    }
-                    Err(_) => {
-                        0.0
-                    }
-                }
+                Some((*value).parse::<f32>().unwrap_or(0.0))
             },
 
             RawValue::Boolean { 0: BooleanValue { value }} => {
-                if *value { 1.0 } else { 0.0 }
+                Some(if *value { 1.0 } else { 0.0 })
+            },
+
+            // The "\x01<id>" token never parses as a number
+            RawValue::Tagged { 0: TaggedValue { id: _ }} => {
+                Some(0.0)
             },
             
-            RawValue::Variable { 0: VariableValue { value }} => {
-                match value.deref(vm, frame) {
-                    Ok(dereferenced) => {
-                        dereferenced.as_float(vm, frame)
-                    }
-                    Err(_) => {
-                        0.0
-                    }
-                }      
+            RawValue::Variable { 0: VariableValue { value: _ }} => {
+                None
             }
         } 
     }
 
     #[inline(always)]
     pub fn as_integer(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> i32 {
+        if let RawValue::Variable { 0: VariableValue { value }} = self
+        {
+            return match value.deref(vm, frame) {
+                Ok(dereferenced) => {
+                    dereferenced.as_integer(vm, frame)
+                }
+                Err(_) => {
+                    0
+                }
+            };
+        }
+
+        return self.integer_value().unwrap_or(0);
+    }
+
+    /// Integer value, or None for a variable reference as reading it needs a VM and frame.
+    #[inline(always)]
+    pub fn integer_value(&self) -> Option<i32> {
         return match self {
             RawValue::Float(value) => {
-                value.value as i32
+                Some(value.value as i32)
             },
 
             RawValue::Integer { 0: IntegerValue { value }} => {
-                *value
+                Some(*value)
             },
 
             RawValue::String { 0: StringValue { value }} => {
-                match (*value).parse::<i32>() {
-                    Ok(value) => {
-                        value
-                    },
-                    Err(_) => {
-                        0
-                    }
-                }
+                Some((*value).parse::<i32>().unwrap_or(0))
             },
 
             RawValue::Boolean { 0: BooleanValue { value }} => {
-                if *value { 1 } else { 0 }
+                Some(if *value { 1 } else { 0 })
             },
 
-            RawValue::Variable { 0: VariableValue { value }} => {
-                match value.deref(vm, frame) {
-                    Ok(dereferenced) => {
-                        dereferenced.as_integer(vm, frame)
-                    }
-                    Err(_) => {
-                        0
-                    }
-                }          
+            RawValue::Tagged { 0: TaggedValue { id: _ }} => {
+                Some(0)
+            },
+
+            RawValue::Variable { 0: VariableValue { value: _ }} => {
+                None
             }
         } 
     }
 
     #[inline(always)]
-    pub fn as_boolean(&self, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> bool {  
+    pub fn as_boolean(&self, _vm: &VirtualMachine<State>, _frame: &StackFrame<State>) -> bool {
+        // FIXME: Hardcoded for variables
+        return self.boolean_value().unwrap_or(true);
+    }
+
+    /// Truthiness of the value, or None for a variable reference.
+    #[inline(always)]
+    pub fn boolean_value(&self) -> Option<bool> {
         return match self {
             RawValue::Float(value) => {
-                value.value != 0.0
+                Some(value.value != 0.0)
             },
 
             RawValue::Integer { 0: IntegerValue { value }} => {
-                (*value) != 0
+                Some((*value) != 0)
             },
 
             RawValue::String { 0: StringValue { value }} => {
                 match (*value).parse::<f32>() {
                     Ok(value) => {
-                        value != 0.0
+                        Some(value != 0.0)
                     },
                     Err(_) => {
-                        false
+                        Some(false)
                     }
                 }
             },
 
             RawValue::Boolean { 0: BooleanValue { value }} => {
-                *value
+                Some(*value)
+            },
+
+            RawValue::Tagged { 0: TaggedValue { id: _ }} => {
+                Some(false)
             },
 
-            RawValue::Variable { 0: VariableValue { value }} => {
-                // FIXME: Hardcoded
-                true
+            RawValue::Variable { 0: VariableValue { value: _ }} => {
+                None
             }
         }
     }
 }
 
+impl<State> From<f32> for RawValue<State> where State: Clone
+{
+    fn from(value: f32) -> Self
+    {
+        return RawValue::Float { 0: FloatValue { value: value }};
+    }
+}
 
-pub struct PushFloat
+impl<State> From<i32> for RawValue<State> where State: Clone
 {
-    pub value: f32
+    fn from(value: i32) -> Self
+    {
+        return RawValue::Integer { 0: IntegerValue { value: value }};
+    }
 }
 
-pub enum OpCode<State>
+impl<State> From<bool> for RawValue<State> where State: Clone
 {
-    // General state management
+    fn from(value: bool) -> Self
+    {
+        return RawValue::Boolean { 0: BooleanValue { value: value }};
+    }
+}
+
+impl<State> From<&str> for RawValue<State> where State: Clone
+{
+    fn from(value: &str) -> Self
+    {
+        return RawValue::String { 0: StringValue { value: Arc::new(value.to_owned()) }};
+    }
+}
+
+impl<State> From<String> for RawValue<State> where State: Clone
+{
+    fn from(value: String) -> Self
+    {
+        return RawValue::String { 0: StringValue { value: Arc::new(value) }};
+    }
+}
+
+
+/// Relational operators that can be fused with the conditional jump consuming their result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison
+{
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Equals,
+    NotEquals
+}
+
+impl Comparison
+{
+    #[inline(always)]
+    pub fn apply<State>(&self, lhs: &RawValue<State>, rhs: &RawValue<State>, vm: &VirtualMachine<State>, frame: &StackFrame<State>) -> bool where State: Clone
+    {
+        return match self {
+            Comparison::LessThan => lhs.as_float(vm, frame) < rhs.as_float(vm, frame),
+            Comparison::LessThanOrEqual => lhs.as_float(vm, frame) <= rhs.as_float(vm, frame),
+            Comparison::GreaterThan => lhs.as_float(vm, frame) > rhs.as_float(vm, frame),
+            Comparison::GreaterThanOrEqual => lhs.as_float(vm, frame) >= rhs.as_float(vm, frame),
+            Comparison::Equals => lhs.equals(vm, frame, rhs),
+            Comparison::NotEquals => !lhs.equals(vm, frame, rhs)
+        };
+    }
+}
+
+#[derive(Clone)]
+pub struct PushFloat
+{
+    pub value: f32
+}
+
+/// Binary operations take their operands in source order: the left-hand side is pushed first and
+/// the right-hand side ends up on top of the stack. Assignment follows the same convention, with the
+/// variable as its left-hand side and the value to store on top.
+#[derive(Clone)]
+pub enum OpCode<State> where State: Clone
+{
+    // General state management
     PushFloat(PushFloat),
 
     PushInteger {
@@ -660,6 +1384,16 @@ pub enum OpCode<State>
     PushString {
         value: String
     },
+
+    /// PushString with the constant already in shared storage, see InstructionSequence::intern_string_constants
+    PushSharedString {
+        value: SharedString
+    },
+
+    /// Pushes a value computed at load time, see InstructionSequence::fold_constants
+    PushConstant {
+        value: RawValue<State>
+    },
     Pop {
 
     },
@@ -672,11 +1406,23 @@ pub enum OpCode<State>
     JumpFalse {
         target: AddressValue
     },
+
+    /// Short circuit for ||: jumps leaving true on the stack if the top value is truthy, otherwise pops it.
+    /// `a || b` lowers to `[a] JumpTrueOrPop end [b] ToBoolean end:`
+    JumpTrueOrPop {
+        target: AddressValue
+    },
+
+    /// Short circuit for &&: jumps leaving false on the stack if the top value is falsy, otherwise pops it.
+    /// `a && b` lowers to `[a] JumpFalseOrPop end [b] ToBoolean end:`
+    JumpFalseOrPop {
+        target: AddressValue
+    },
     NOP {
 
     },
 
-    // Crappy workaround op for testing
+    /// Exchanges the top two values of the stack
     Swap {
 
     },
@@ -687,17 +1433,38 @@ pub enum OpCode<State>
     Concat {
 
     },
+
+    /// Concat joining both values with a separator; backs the SPC, TAB and NL operators
+    ConcatSeparator {
+        separator: char
+    },
     Negate { 
 
     },
     Not { 
 
+    },
+
+    /// Replaces the top of the stack with its truthiness
+    ToBoolean {
+
     },
     CallFunction {
         target: Vec<String>
     },
 
+    /// CallFunction with the target hashed ahead of time and the resolved function cached on the instruction
+    CallCached {
+        site: CallSite<State>
+    },
+
+    /// Pops the top of the stack as the function's return value and stops executing the sequence
+    Return {
+
+    },
+
     // Logical Instructions
+    // NOTE: These evaluate eagerly; && and || should be lowered with JumpFalseOrPop/JumpTrueOrPop instead
     LogicalAnd {
 
     },
@@ -711,6 +1478,29 @@ pub enum OpCode<State>
     },
     BitwiseOr {
 
+    },
+    BitwiseXor {
+
+    },
+
+    /// Shift amounts are masked to the integer width
+    ShiftLeft {
+
+    },
+
+    /// Logical shift; Torque treats bitwise operands as unsigned
+    ShiftRight {
+
+    },
+
+    /// The ~ operator
+    BitwiseNot {
+
+    },
+
+    /// Equivalent to BitwiseNot; matches the name of Torque's own opcode
+    OnesComplement {
+
     },
 
     // Arithmetic
@@ -730,9 +1520,17 @@ pub enum OpCode<State>
 
     },
 
+    /// Truncating division of both operands as integers
+    IntegerDivide {
+
+    },
+
     // Relational
     LessThan {
 
+    },
+    LessThanOrEqual {
+
     },
     GreaterThan {
 
@@ -751,178 +1549,1005 @@ pub enum OpCode<State>
     },
     StringNotEqual {
 
-    },
+    },
+
+    PushVariable {
+        variable: VariableReference<State>
+    },
+
+    /// Pushes the current value of a pre-resolved local
+    LoadLocal {
+        slot: LocalSlot
+    },
+
+    /// Stores the top of the stack into a pre-resolved local, leaving a reference to the local in its place
+    StoreLocal {
+        slot: LocalSlot
+    },
+
+    // Superinstructions, produced by InstructionSequence::optimize
+    /// Assignment followed by Pop
+    AssignAndPop {
+
+    },
+
+    /// StoreLocal followed by Pop
+    AssignLocalAndPop {
+        slot: LocalSlot
+    },
+
+    /// `%x = %x + amount` on a pre-resolved local, leaving nothing on the stack
+    IncrementLocal {
+        slot: LocalSlot,
+        amount: f32
+    },
+
+    /// A comparison followed by JumpTrue (jump_if true) or JumpFalse (jump_if false)
+    CompareAndJump {
+        comparison: Comparison,
+        jump_if: bool,
+        target: AddressValue
+    },
+
+    // Tagged strings
+    /// Interns the string into the tagged string table and pushes its tag
+    PushTaggedString {
+        value: String
+    },
+
+    /// Resolves a tag to its string; any other value is passed through unchanged
+    Detag {
+
+    },
+
+    /// Resolves a tag or a bare tag ID to its string, producing "" if the tag is unknown
+    GetTaggedString {
+
+    }
+}
+
+impl<State> OpCode<State> where State: Clone
+{
+    /// The address this op may jump to, if it is a jump.
+    pub fn jump_address(&self) -> Option<&AddressValue>
+    {
+        return match self {
+            OpCode::Jump { target } | OpCode::JumpTrue { target } | OpCode::JumpFalse { target } |
+            OpCode::JumpTrueOrPop { target } | OpCode::JumpFalseOrPop { target } |
+            OpCode::CompareAndJump { comparison: _, jump_if: _, target } => Some(target),
+            _ => None
+        };
+    }
+
+    /// Mutable access to the address this op may jump to, if it is a jump.
+    pub fn jump_address_mut(&mut self) -> Option<&mut AddressValue>
+    {
+        return match self {
+            OpCode::Jump { target } | OpCode::JumpTrue { target } | OpCode::JumpFalse { target } |
+            OpCode::JumpTrueOrPop { target } | OpCode::JumpFalseOrPop { target } |
+            OpCode::CompareAndJump { comparison: _, jump_if: _, target } => Some(target),
+            _ => None
+        };
+    }
+}
+
+//type InstructionSequence = Vec<OpCode>;
+pub struct InstructionSequence<State> where State: Clone
+{
+    pub ops: Vec<OpCode<State>>,
+
+    /// Identifier of the local held in each slot, filled in by resolve_local_slots
+    pub local_slots: Arc<Vec<VariableIdentifier>>,
+
+    /// Threaded form of ops, built on first use. Passes discard it, but editing ops directly after it
+    /// has been built does not, see discard_compiled.
+    #[cfg(feature="async")]
+    threaded: OnceLock<ThreadedSequence<State>>,
+
+    /// Threaded form of ops, built on first use. Passes discard it, but editing ops directly after it
+    /// has been built does not, see discard_compiled.
+    #[cfg(not(feature="async"))]
+    threaded: OnceCell<ThreadedSequence<State>>,
+
+    /// Loops found for the JIT, along with their compiled code
+    #[cfg(feature="jit")]
+    pub(crate) jit: std::sync::OnceLock<JitRegions>
+}
+
+impl<State> Clone for InstructionSequence<State> where State: Clone
+{
+    /// Clones start without a threaded form, as they are usually cloned to be edited
+    fn clone(&self) -> Self
+    {
+        let mut sequence = Self::new(self.ops.clone());
+        sequence.local_slots = self.local_slots.clone();
+        return sequence;
+    }
+}
+
+impl<State> InstructionSequence<State> where State: Clone
+{
+    pub fn new(ops: Vec<OpCode<State>>) -> Self
+    {
+        return Self
+        {
+            ops: ops,
+            local_slots: Arc::new(Vec::new()),
+
+            #[cfg(feature="async")]
+            threaded: OnceLock::new(),
+
+            #[cfg(not(feature="async"))]
+            threaded: OnceCell::new(),
+
+            #[cfg(feature="jit")]
+            jit: std::sync::OnceLock::new()
+        };
+    }
+
+    /// Returns the threaded form of this sequence, decoding it the first time.
+    pub fn threaded(&self) -> &ThreadedSequence<State>
+    {
+        return self.threaded.get_or_init(|| ThreadedSequence::compile(self));
+    }
+
+    /// Drops the threaded form and any compiled loops so they are rebuilt from the current ops.
+    pub fn discard_compiled(&mut self)
+    {
+        self.threaded.take();
+
+        #[cfg(feature="jit")]
+        self.jit.take();
+    }
+}
+
+/// Calls deeper than this fail by default, see VirtualMachine::set_max_call_depth
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 4096;
+
+thread_local! {
+    /// Frames execute_frames has running on this thread, across every VM and every native that runs script again,
+    /// such as eval() or Function::invoke. Those still use the host stack, so they count towards max_call_depth.
+    static ACTIVE_FRAMES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// The sequence execute_frames was asked to run, in whichever form it was given.
+pub(crate) enum RootSequence<'s, State> where State: Clone
+{
+    Match(&'s InstructionSequence<State>),
+    Threaded(&'s ThreadedSequence<State>)
+}
+
+/// A caller waiting on a script function: what it was running, its frame with its own locals and operand stack,
+/// and where it resumes once the callee is done.
+struct CallFrame<State> where State: Clone
+{
+    /// None for the root sequence
+    function: Option<Arc<Function<State>>>,
+    threaded: bool,
+    return_address: usize,
+    frame: StackFrame<State>
+}
+
+pub struct StackFrame<State> where State: Clone
+{
+    /// Current VM thread-local stack state
+    pub stack: Vec<SystemValue<State>>,
+
+    /// Local thread-local variables allocated at this frame, for names not resolved to a slot
+    pub locals: HashMap<VariableIdentifier, RawValue<State>>,
+
+    /// Pre-resolved locals, indexed by slot
+    pub slots: Vec<RawValue<State>>,
+
+    /// Identifier of the local held in each slot, used to route dynamic names to their slot
+    pub slot_identifiers: Arc<Vec<VariableIdentifier>>,
+
+    /// Set by Return, handed back to whoever made the call
+    pub return_value: Option<RawValue<State>>,
+
+    /// Script function the sequence stopped to call, along with the index to resume at, see begin_call
+    pub(crate) pending_call: Option<(Arc<Function<State>>, usize)>
+}
+
+impl<State> Default for StackFrame<State> where State: Clone
+{
+    fn default() -> Self
+    {
+        return Self::new();
+    }
+}
+
+impl<State> StackFrame<State> where State: Clone
+{
+    pub fn new() -> Self
+    {
+        return Self
+        {
+            stack: Vec::new(),
+            locals: HashMap::new(),
+            slots: Vec::new(),
+            slot_identifiers: Arc::new(Vec::new()),
+            return_value: None,
+            pending_call: None
+        };
+    }
+
+    /// Allocates a frame with storage for the locals resolved in the given sequence.
+    pub fn for_sequence(instructions: &InstructionSequence<State>) -> Self
+    {
+        return Self::with_slots(&instructions.local_slots);
+    }
+
+    /// Allocates a frame with storage for the given resolved locals.
+    pub fn with_slots(local_slots: &Arc<Vec<VariableIdentifier>>) -> Self
+    {
+        return Self
+        {
+            stack: Vec::new(),
+            locals: HashMap::new(),
+            slots: vec![RawValue::String { 0: StringValue { value: SharedString::default() }}; local_slots.len()],
+            slot_identifiers: local_slots.clone(),
+            return_value: None,
+            pending_call: None
+        };
+    }
+
+    /// Finds the slot a local was resolved to, if any.
+    #[inline(always)]
+    pub fn find_slot(&self, identifier: VariableIdentifier) -> Option<LocalSlot>
+    {
+        return self.slot_identifiers.iter().position(|slot_identifier| *slot_identifier == identifier);
+    }
+
+    /// Sets a local by name, going through its slot if it was resolved to one.
+    pub fn set_local(&mut self, name: &str, value: RawValue<State>)
+    {
+        let identifier = variable_name_to_identifier(name.to_owned());
+
+        match self.find_slot(identifier) {
+            Some(slot) => {
+                self.slots[slot] = value;
+            },
+            None => {
+                self.locals.insert(identifier, value);
+            }
+        }
+    }
+
+    /// Binds the arguments a caller left on top of its stack to the named parameters, in order.
+    /// Missing ones are left as "".
+    pub(crate) fn bind_arguments(&mut self, parameters: &[String], caller: &StackFrame<State>, vm: &VirtualMachine<State>)
+    {
+        let first = caller.stack.len().saturating_sub(parameters.len());
+        for (parameter, argument) in parameters.iter().zip(caller.stack[first ..].iter())
+        {
+            self.set_local(parameter, argument.as_raw(vm, caller));
+        }
+    }
+
+    /// Puts the result of a call in place of the callee's last argument, leaving the stack as deep as before.
+    /// Calls without parameters leave nothing on the stack to replace, so their result is dropped.
+    pub(crate) fn hand_back(&mut self, parameters: &[String], value: Option<RawValue<State>>)
+    {
+        if parameters.is_empty()
+        {
+            return;
+        }
+
+        if let (Some(value), Some(argument)) = (value, self.stack.last_mut())
+        {
+            *argument = SystemValue::Raw { value: value };
+        }
+    }
+}
+
+// Under async a VM, and the functions and sequences loaded into it, can be shared between threads
+// as long as the application state can.
+#[cfg(feature="async")]
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[allow(dead_code)]
+    fn assert_thread_safe<State: Clone + Send + Sync>()
+    {
+        assert_send_sync::<VirtualMachine<'static, State>>();
+        assert_send_sync::<Function<State>>();
+        assert_send_sync::<InstructionSequence<State>>();
+        assert_send_sync::<GlobalHandle<State>>();
+    }
+};
+
+pub struct VirtualMachine<'a, State> where State: Clone
+{
+    /// A mapping of global string identifiers to their storage
+    #[cfg(feature="async")]
+    pub globals: Arc<RwLock<HashMap<VariableIdentifier, GlobalHandle<State>>>>,
+
+    /// A mapping of global string identifiers to their storage
+    #[cfg(not(feature="async"))]
+    pub globals: RefCell<HashMap<VariableIdentifier, GlobalHandle<State>>>,
+
+    /// Network string table backing tagged strings
+    #[cfg(feature="async")]
+    pub tagged_strings: Arc<RwLock<TaggedStringTable>>,
+
+    /// Network string table backing tagged strings
+    #[cfg(not(feature="async"))]
+    pub tagged_strings: RefCell<TaggedStringTable>,
+
+    /// Root namespaces, see namespace and namespace_mut
+    #[cfg(not(feature="async"))]
+    pub root_namespace: RefCell<Namespace<'a, State>>,
+
+    /// Root namespaces, see namespace and namespace_mut
+    #[cfg(feature="async")]
+    pub root_namespace: RwLock<Namespace<'a, State>>,
+
+    /// Backend interpret runs sequences with, including the bodies of called functions
+    #[cfg(not(feature="async"))]
+    dispatch: Cell<DispatchMode>,
+
+    /// Backend interpret runs sequences with, including the bodies of called functions
+    #[cfg(feature="async")]
+    dispatch: AtomicU8,
+
+    /// Whether code loaded from now on goes through the peephole pass, see set_peephole
+    #[cfg(not(feature="async"))]
+    peephole: Cell<bool>,
+
+    /// Whether code loaded from now on goes through the peephole pass, see set_peephole
+    #[cfg(feature="async")]
+    peephole: AtomicBool,
+
+    /// Deepest script calls may nest on a thread, see set_max_call_depth
+    #[cfg(not(feature="async"))]
+    max_call_depth: Cell<usize>,
+
+    /// Deepest script calls may nest on a thread, see set_max_call_depth
+    #[cfg(feature="async")]
+    max_call_depth: AtomicUsize,
+
+    /// Callbacks on script writes to globals, see watch_global
+    pub(crate) watchers: WatchRegistry<State>,
+
+    /// Compiler used by eval, see set_compiler
+    #[cfg(feature="async")]
+    pub(crate) compiler: RwLock<Option<ScriptCompiler<State>>>,
+
+    /// Compiler used by eval, see set_compiler
+    #[cfg(not(feature="async"))]
+    pub(crate) compiler: RefCell<Option<ScriptCompiler<State>>>,
+
+    /// Scripts exec reads and compiles, see set_file_system
+    pub(crate) files: FileRegistry<State>,
+
+    /// Application state, user provided
+    pub state: State
+}
+
+/// Slot an op stores the value below it on the stack to, used to spot `%x = %x @ ...` ahead of a concat.
+#[inline(always)]
+pub(crate) fn store_slot<State>(next: Option<&OpCode<State>>) -> Option<LocalSlot> where State: Clone
+{
+    return match next {
+        Some(OpCode::StoreLocal { slot }) | Some(OpCode::AssignLocalAndPop { slot }) => Some(*slot),
+        _ => None
+    };
+}
+
+/// Name of a global as written in script, where the $ sigil is optional.
+fn global_name(name: &str) -> &str
+{
+    return name.strip_prefix('$').unwrap_or(name);
+}
+
+#[inline(always)]
+pub(crate) fn process_address(offset_out: &mut usize, address: &AddressValue)
+{
+    match address {
+        AddressValue::RelativeOffset { offset } => {
+            // Offsets are relative to the instruction following the jump
+            *offset_out = offset_out.wrapping_add_signed(*offset as isize);
+        },
+        AddressValue::AbsoluteTarget { index } => {
+            *offset_out = *index;
+        }
+    }
+}
+
+impl<'a, State> VirtualMachine<'a, State> where State: Clone
+{
+    #[inline(always)]
+    #[cfg(feature="async")]
+    pub fn new(state: State) -> Self {
+        let globals: Arc<RwLock<HashMap<VariableIdentifier, GlobalHandle<State>>>> = Arc::new(RwLock::new(HashMap::new()));
+        let mut globals_write = globals.write().unwrap();
+        globals_write.reserve(1024);
+        drop(globals_write);
+
+        let vm = Self {
+            globals: globals,
+            state: state,
+            tagged_strings: Arc::new(RwLock::new(TaggedStringTable::new())),
+            root_namespace: RwLock::new(Namespace::new()),
+            dispatch: AtomicU8::new(DispatchMode::default() as u8),
+            peephole: AtomicBool::new(true),
+            max_call_depth: AtomicUsize::new(DEFAULT_MAX_CALL_DEPTH),
+            watchers: WatchRegistry::new(),
+            compiler: RwLock::new(None),
+            files: FileRegistry::new(),
+        };
+
+        vm.add_tagged_string_builtins().unwrap();
+        return vm;
+    }
+
+    #[inline(always)]
+    #[cfg(not(feature="async"))]
+    pub fn new(state: State) -> Self {
+        let mut globals: HashMap<VariableIdentifier, GlobalHandle<State>> = HashMap::new();
+        globals.reserve(1024);
+
+        let vm = Self {
+            root_namespace: RefCell::new(Namespace::new()),
+            dispatch: Cell::new(DispatchMode::default()),
+            max_call_depth: Cell::new(DEFAULT_MAX_CALL_DEPTH),
+            watchers: WatchRegistry::new(),
+            compiler: RefCell::new(None),
+            files: FileRegistry::new(),
+            globals: RefCell::new(globals),
+            tagged_strings: RefCell::new(TaggedStringTable::new()),
+            peephole: Cell::new(true),
+            state: state
+        };
+
+        vm.add_tagged_string_builtins().unwrap();
+        return vm;
+    }
+
+    /// Registers a function, running the load time passes over it first, see InstructionSequence::prepare.
+    pub fn add_function(&self, function: Function<State>, path: &[String]) -> Result<(), &'static str>
+    {
+        let function = self.prepare_function(function);
+        return self.namespace_mut().add_function_entry_slice(function, path);
+    }
+
+    /// Runs the load time passes, ahead of taking the namespace to add the function.
+    pub(crate) fn prepare_function(&self, function: Function<State>) -> Function<State>
+    {
+        let mut function = function;
+        if let Function::VirtualFunction { parameters: _, instructions } = &mut function
+        {
+            instructions.prepare(self);
+        }
+
+        return function;
+    }
+
+    /// Calls a script function by its namespace path, e.g. `["Game", "onTick"]`, producing its return value.
+    pub fn call(&self, path: &[&str], arguments: &[RawValue<State>]) -> Result<RawValue<State>, &'static str>
+    {
+        let path: Vec<String> = path.iter().map(|name| name.to_string()).collect();
+
+        // Release the namespace before calling so the callee can add functions
+        let function = self.namespace().lookup_function_cached(&path)?;
+        return function.invoke(self, arguments);
+    }
+
+    /// Calls a method on an object, passing the object itself as the first argument (%this).
+    /// The method is looked up in the namespace named after the object, then in the namespaces of the class
+    /// registered under that name, in order.
+    pub fn call_method(&self, object: &RawValue<State>, name: &str, arguments: &[RawValue<State>]) -> Result<RawValue<State>, &'static str>
+    {
+        let object_name = object.as_string(self, &StackFrame::new()).to_lowercase();
+
+        let function = {
+            let namespace = self.namespace();
+
+            let mut search = vec![object_name.clone()];
+            #[cfg(not(feature="async"))]
+            let classes = namespace.classes.borrow();
+
+            #[cfg(feature="async")]
+            let classes = namespace.classes.read().unwrap();
+
+            if let Some(class) = classes.get(&object_name)
+            {
+                search.extend(class.namespaces.iter().cloned());
+            }
+            drop(classes);
+
+            search.iter().find_map(|namespace_name| namespace.lookup_function_cached(&vec![namespace_name.clone(), name.to_owned()]).ok())
+        };
+
+        let function = function.ok_or("Method Lookup Failed")?;
+
+        let mut method_arguments = Vec::with_capacity(arguments.len() + 1);
+        method_arguments.push(object.clone());
+        method_arguments.extend(arguments.iter().cloned());
+
+        return function.invoke(self, &method_arguments);
+    }
+
+    /// Read access to the root namespace. Don't hold on to it across a call, as the callee may add functions.
+    #[cfg(not(feature="async"))]
+    pub fn namespace(&self) -> Ref<'_, Namespace<'a, State>>
+    {
+        return self.root_namespace.borrow();
+    }
+
+    /// Read access to the root namespace. Don't hold on to it across a call, as the callee may add functions.
+    #[cfg(feature="async")]
+    pub fn namespace(&self) -> RwLockReadGuard<'_, Namespace<'a, State>>
+    {
+        return self.root_namespace.read().unwrap();
+    }
+
+    /// Write access to the root namespace.
+    #[cfg(not(feature="async"))]
+    pub fn namespace_mut(&self) -> RefMut<'_, Namespace<'a, State>>
+    {
+        return self.root_namespace.borrow_mut();
+    }
+
+    /// Write access to the root namespace.
+    #[cfg(feature="async")]
+    pub fn namespace_mut(&self) -> RwLockWriteGuard<'_, Namespace<'a, State>>
+    {
+        return self.root_namespace.write().unwrap();
+    }
+
+    /// Backend interpret currently runs sequences with.
+    #[cfg(not(feature="async"))]
+    #[inline(always)]
+    pub fn dispatch(&self) -> DispatchMode
+    {
+        return self.dispatch.get();
+    }
+
+    /// Backend interpret currently runs sequences with.
+    #[cfg(feature="async")]
+    #[inline(always)]
+    pub fn dispatch(&self) -> DispatchMode
+    {
+        return match self.dispatch.load(Ordering::Relaxed) {
+            mode if mode == DispatchMode::Threaded as u8 => DispatchMode::Threaded,
+            _ => DispatchMode::Match
+        };
+    }
+
+    /// Switches the backend interpret uses, including for calls made by sequences already running.
+    pub fn set_dispatch(&self, mode: DispatchMode)
+    {
+        #[cfg(not(feature="async"))]
+        self.dispatch.set(mode);
+
+        #[cfg(feature="async")]
+        self.dispatch.store(mode as u8, Ordering::Relaxed);
+    }
+
+    /// Whether code loaded from now on goes through the peephole pass. Always false without the peephole feature.
+    #[cfg(not(feature="async"))]
+    pub fn peephole(&self) -> bool
+    {
+        return cfg!(feature="peephole") && self.peephole.get();
+    }
+
+    /// Whether code loaded from now on goes through the peephole pass. Always false without the peephole feature.
+    #[cfg(feature="async")]
+    pub fn peephole(&self) -> bool
+    {
+        return cfg!(feature="peephole") && self.peephole.load(Ordering::Relaxed);
+    }
+
+    /// Turns the peephole pass on or off for code loaded from now on, e.g. to compare against unoptimized output.
+    /// Code already loaded keeps the form it was loaded in.
+    pub fn set_peephole(&self, enabled: bool)
+    {
+        #[cfg(not(feature="async"))]
+        self.peephole.set(enabled);
+
+        #[cfg(feature="async")]
+        self.peephole.store(enabled, Ordering::Relaxed);
+    }
+
+    /// How deep script calls may nest before failing with "Maximum Call Depth Exceeded".
+    #[cfg(not(feature="async"))]
+    pub fn max_call_depth(&self) -> usize
+    {
+        return self.max_call_depth.get();
+    }
+
+    /// How deep script calls may nest before failing with "Maximum Call Depth Exceeded".
+    #[cfg(feature="async")]
+    pub fn max_call_depth(&self) -> usize
+    {
+        return self.max_call_depth.load(Ordering::Relaxed);
+    }
+
+    /// Limits how deep script calls may nest. Frames live on the heap, so the limit only guards against runaway recursion.
+    /// Calls made while a native has re-entered the VM count on top of the calls that led to the native.
+    pub fn set_max_call_depth(&self, depth: usize)
+    {
+        #[cfg(not(feature="async"))]
+        self.max_call_depth.set(depth);
+
+        #[cfg(feature="async")]
+        self.max_call_depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Returns the storage for a global, creating an unassigned entry under the given name if it doesn't exist yet.
+    #[cfg(feature="async")]
+    pub fn global_handle(&self, identifier: VariableIdentifier, name: &str) -> GlobalHandle<State>
+    {
+        if let Some(handle) = self.globals.read().unwrap().get(&identifier)
+        {
+            return handle.clone();
+        }
+
+        let mut globals_write = self.globals.write().unwrap();
+        return globals_write.entry(identifier).or_insert_with(|| self.create_global(name)).clone();
+    }
+
+    /// Returns the storage for a global, creating an unassigned entry under the given name if it doesn't exist yet.
+    #[cfg(not(feature="async"))]
+    pub fn global_handle(&self, identifier: VariableIdentifier, name: &str) -> GlobalHandle<State>
+    {
+        let mut globals_write = self.globals.borrow_mut();
+        return globals_write.entry(identifier).or_insert_with(|| self.create_global(name)).clone();
+    }
+
+    /// Reads a global by name, with or without the leading $. None if it was never assigned or has been deleted.
+    pub fn get_global(&self, name: &str) -> Option<RawValue<State>>
+    {
+        return self.find_global_handle(global_name(name))?.get();
+    }
+
+    /// Assigns a global by name, with or without the leading $. Fails if the global is bound and rejects the value.
+    pub fn set_global(&self, name: &str, value: RawValue<State>) -> Result<(), &'static str>
+    {
+        let name = global_name(name);
+        return self.global_handle(variable_name_to_identifier(name.to_owned()), name).set(value);
+    }
+
+    /// Unassigns a global by name, returning the value it held. Its storage is kept so resolved handles stay valid.
+    pub fn delete_global(&self, name: &str) -> Option<RawValue<State>>
+    {
+        return self.find_global_handle(global_name(name))?.take();
+    }
+
+    /// Binds a global to the host, so every read and write from script or the host goes through the given accessors.
+    /// Sequences that already resolved the global see the binding too.
+    pub fn bind_global(&self, name: &str, getter: VariableGetter<State>, setter: VariableSetter<State>) -> Result<(), &'static str>
+    {
+        let name = global_name(name);
+        let handle = self.global_handle(variable_name_to_identifier(name.to_owned()), name);
+        return handle.bind(VariableBinding { getter: getter, setter: setter });
+    }
+
+    /// Returns the storage for a global if it has one, without creating it.
+    fn find_global_handle(&self, name: &str) -> Option<GlobalHandle<State>>
+    {
+        #[cfg(feature="async")]
+        let globals_read = self.globals.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let globals_read = self.globals.borrow();
+
+        return globals_read.get(&variable_name_to_identifier(name.to_owned())).cloned();
+    }
+
+    /// Every assigned global whose name matches the pattern, sorted by name. Supports * and ? as in deleteVariables().
+    pub fn matching_globals(&self, pattern: &str) -> Vec<(String, RawValue<State>)>
+    {
+        let pattern = global_name(pattern);
+
+        #[cfg(feature="async")]
+        let globals_read = self.globals.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let globals_read = self.globals.borrow();
+
+        let mut matches: Vec<(String, RawValue<State>)> = globals_read.values()
+            .filter(|handle| glob_match(pattern, handle.name()))
+            .filter_map(|handle| handle.get().map(|value| (handle.name().to_owned(), value)))
+            .collect();
+
+        matches.sort_by_key(|(name, _)| name.to_lowercase());
+        return matches;
+    }
+
+    /// Implements deleteVariables(): unassigns every global matching the pattern, returning how many were assigned.
+    pub fn delete_globals(&self, pattern: &str) -> usize
+    {
+        let pattern = global_name(pattern);
+
+        #[cfg(feature="async")]
+        let globals_read = self.globals.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let globals_read = self.globals.borrow();
+
+        let mut deleted: usize = 0;
+        for handle in globals_read.values().filter(|handle| glob_match(pattern, handle.name()))
+        {
+            if handle.take().is_some()
+            {
+                deleted += 1;
+            }
+        }
+
+        return deleted;
+    }
+
+    /// Script source assigning every global matching the pattern its current value, one `$name = "value";` per line.
+    pub fn format_globals(&self, pattern: &str) -> String
+    {
+        let mut source = String::new();
+        for (name, value) in self.matching_globals(pattern)
+        {
+            source.push_str(&format!("${} = \"{}\";\n", name, expand_escapes(&value.as_string(self, &StackFrame::new()))));
+        }
+
+        return source;
+    }
+
+    /// Implements export(): writes the globals matching the pattern as .cs source, returning how many were written.
+    /// Where the source ends up, and whether it replaces or appends to a file, is up to the writer the host passes.
+    pub fn export_globals(&self, pattern: &str, writer: &mut impl Write) -> Result<usize, &'static str>
+    {
+        let source = self.format_globals(pattern);
+        writer.write_all(source.as_bytes()).map_err(|_| "Failed to Write Export")?;
+
+        return Ok(source.lines().count());
+    }
+
+    /// Interns a string into the tagged string table, returning its tag.
+    pub fn tag_string(&self, value: &str) -> TagIdentifier
+    {
+        #[cfg(feature="async")]
+        let mut tagged_write = self.tagged_strings.write().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let mut tagged_write = self.tagged_strings.borrow_mut();
+
+        return tagged_write.add(value);
+    }
+
+    /// Looks up the string behind a tag.
+    pub fn lookup_tagged_string(&self, id: TagIdentifier) -> Option<String>
+    {
+        #[cfg(feature="async")]
+        let tagged_read = self.tagged_strings.read().unwrap();
 
-    PushVariable {
-        variable: VariableReference<State>
+        #[cfg(not(feature="async"))]
+        let tagged_read = self.tagged_strings.borrow();
+
+        return tagged_read.lookup(id).map(|value| value.to_owned());
     }
-}
 
-impl<State> OpCode<State>
-{
-    fn get_type(&self) -> String
+    /// Implements detag(): resolves a tag to its string and passes any other value through unchanged.
+    pub fn detag(&self, value: &RawValue<State>, frame: &StackFrame<State>) -> String
     {
-        return match self {
-            OpCode::PushFloat (value) => "Error".to_owned(),
-
-            OpCode::PushInteger { value } => "Error".to_owned(),
-            OpCode::PushString { value } => "Error".to_owned(),
-            OpCode::Pop {  } => "Error".to_owned(),
-            OpCode::Jump { target } => "Error".to_owned(),
-            OpCode::JumpTrue { target } => "Error".to_owned(),
-            OpCode::JumpFalse { target } => "Error".to_owned(),
-            OpCode::NOP {  } => "Error".to_owned(),
-            OpCode::Swap {  } => "Error".to_owned(),
-            OpCode::Assignment {  } => "Error".to_owned(),
-            OpCode::Concat {  } => "Error".to_owned(),
-            OpCode::Negate {  } => "Error".to_owned(),
-            OpCode::Not {  } => "Error".to_owned(),
-            OpCode::CallFunction { target } => "Error".to_owned(),
-            OpCode::LogicalAnd {  } => "Error".to_owned(),
-            OpCode::LogicalOr {  } => "Error".to_owned(),
-            OpCode::BitwiseAnd {  } => "Error".to_owned(),
-            OpCode::BitwiseOr {  } => "Error".to_owned(),
-            OpCode::Add {  } => "Error".to_owned(),
-            OpCode::Minus {  } => "Error".to_owned(),
-            OpCode::Modulus {  } => "Error".to_owned(),
-            OpCode::Multiply {  } => "Error".to_owned(),
-            OpCode::Divide {  } => "Error".to_owned(),
-            OpCode::LessThan {  } => "Error".to_owned(),
-            OpCode::GreaterThan {  } => "Error".to_owned(),
-            OpCode::GreaterThanOrEqual {  } => "Error".to_owned(),
-            OpCode::Equals {  } => "Error".to_owned(),
-            OpCode::NotEquals {  } => "Error".to_owned(),
-            OpCode::StringEquals {  } => "Error".to_owned(),
-            OpCode::StringNotEqual {  } => "Error".to_owned(),
-            OpCode::PushVariable { variable } => "Error".to_owned()
+        let tag = match value {
+            RawValue::Tagged { 0: TaggedValue { id }} => Some(*id),
+            _ => token_to_tag(&value.as_string(self, frame))
+        };
+
+        return match tag {
+            Some(id) => self.lookup_tagged_string(id).unwrap_or_default(),
+            None => value.as_string(self, frame)
         };
     }
-}
 
-//type InstructionSequence = Vec<OpCode>;
-pub struct InstructionSequence<State>
-{
-    pub ops: Vec<OpCode<State>>
-}
+    /// Implements getTaggedString(): accepts a tag or a bare tag ID, producing "" if it is unknown.
+    pub fn get_tagged_string(&self, value: &RawValue<State>, frame: &StackFrame<State>) -> String
+    {
+        let tag = match value {
+            RawValue::Tagged { 0: TaggedValue { id }} => Some(*id),
+            _ => {
+                let token = value.as_string(self, frame);
+                token_to_tag(&token).or_else(|| token.parse::<TagIdentifier>().ok())
+            }
+        };
 
-fn print_type_of<T>(_: &T) {
-    println!("{}", std::any::type_name::<T>())
-}
+        return tag.and_then(|id| self.lookup_tagged_string(id)).unwrap_or_default();
+    }
 
-impl<State> InstructionSequence<State>
-{
-    pub fn serialize(&self)
+    /// Makes detag() and getTaggedString() callable from script, taking their argument from the top of the stack.
+    fn add_tagged_string_builtins(&self) -> Result<(), &'static str>
     {
-        let mut buffer = Vec::<u8>::new();
-        buffer.reserve(2048);
-        
-        for op in self.ops.iter()
+        self.add_function(Function::NativeFunction {
+            parameters: vec!["tag".to_owned()],
+            binding: Box::new(|vm, frame| -> NativeResult<State> {
+                let value = frame.stack.last().ok_or("detag() expects a Tag")?.as_raw(vm, frame);
+                Ok(Some(RawValue::String { 0: StringValue { value: Arc::new(vm.detag(&value, frame)) }}))
+            })
+        }, &["detag".to_owned()])?;
+
+        return self.add_function(Function::NativeFunction {
+            parameters: vec!["tag".to_owned()],
+            binding: Box::new(|vm, frame| -> NativeResult<State> {
+                let value = frame.stack.last().ok_or("getTaggedString() expects a Tag")?.as_raw(vm, frame);
+                Ok(Some(RawValue::String { 0: StringValue { value: Arc::new(vm.get_tagged_string(&value, frame)) }}))
+            })
+        }, &["getTaggedString".to_owned()]);
+    }
+
+    /// Implements @ and its separator forms. If the result is about to be stored straight back into the local the
+    /// left-hand side was loaded from, as in `%x = %x @ ...`, the local gives up its string so it can be appended
+    /// to in place instead of copied.
+    #[inline(always)]
+    pub(crate) fn concat(&self, frame: &mut StackFrame<State>, lhs: SystemValue<State>, rhs: SystemValue<State>, separator: Option<char>, store_slot: Option<LocalSlot>) -> RawValue<State>
+    {
+        let rhs = rhs.into_raw(self, frame);
+        let lhs = lhs.into_raw(self, frame);
+
+        if let (Some(slot), RawValue::String { 0: StringValue { value: lhs_string }}) = (store_slot, &lhs)
         {
-            let type_name = op.get_type();
+            let aliases_local = match frame.slots.get(slot) {
+                Some(RawValue::String { 0: StringValue { value: slot_string }}) => Arc::ptr_eq(lhs_string, slot_string),
+                _ => false
+            };
 
-            // FIXME: Unstable feature here? We need to ensure the hash algorithm remains static
-            let mut hasher = SipHasher::new();
-            hasher.write(type_name.as_bytes());
-            let opcode_id = hasher.finish();
+            if aliases_local
+            {
+                frame.slots[slot] = RawValue::Boolean { 0: BooleanValue { value: false }};
+            }
+        }
 
-            println!("OP: {}", opcode_id);
+        let mut result = match lhs {
+            RawValue::String { 0: StringValue { value }} => value,
+            other => Arc::new(other.as_string(self, frame))
+        };
 
-            opcode_id.write_to(&mut buffer, ByteOrder::LittleEndian).unwrap();
+        // Only copies if something else still holds the string
+        let buffer = Arc::make_mut(&mut result);
+        if let Some(separator) = separator
+        {
+            buffer.push(separator);
         }
-        println!("Done");
+        buffer.push_str(&rhs.as_str(self, frame));
+
+        return RawValue::String { 0: StringValue { value: result }};
     }
-}
 
-pub struct StackFrame<State> where State: Clone
-{
-    /// Current VM thread-local stack state
-    #[cfg(not(feature="register-vm"))]
-    pub stack: Vec<SystemValue<State>>,
+    pub fn interpret(&self, instructions: &InstructionSequence<State>) -> Result<(), &'static str>
+    {
+        self.execute(instructions, StackFrame::for_sequence(instructions))?;
+        return Ok(());
+    }
 
-    /// Local thread-local variables allocated at this frame
-    pub locals: HashMap<VariableIdentifier, RawValue<State>>
-}
+    /// Runs a sequence in a frame the caller has already set up, producing whatever it returned.
+    pub fn execute(&self, instructions: &InstructionSequence<State>, frame: StackFrame<State>) -> Result<Option<RawValue<State>>, &'static str>
+    {
+        let coalescing = self.watchers.enter();
 
-pub struct VirtualMachine<'a, State> where State: Clone
-{
-    /// A mapping of global string identifiers to their value
-    #[cfg(feature="async")]
-    pub globals: Arc<RwLock<HashMap<VariableIdentifier, RawValue<State>>>>,
+        let result = match self.dispatch() {
+            DispatchMode::Threaded => self.execute_threaded(instructions.threaded(), frame),
+            DispatchMode::Match => self.execute_frames(RootSequence::Match(instructions), frame)
+        };
 
-    /// A mapping of global string identifiers to their value
-    #[cfg(not(feature="async"))]
-    pub globals: RefCell<HashMap<VariableIdentifier, RawValue<State>>>,
+        if coalescing
+        {
+            self.leave_watch_scope();
+        }
+        return result;
+    }
 
-    /// Root namespaces
-    pub root_namespace: RefCell<Namespace<'a, State>>,
+    /// Runs a sequence along with every script function it calls. Calls are kept on a stack of frames rather than
+    /// recursing, so deep script recursion fails cleanly once it passes max_call_depth instead of overflowing the host.
+    pub(crate) fn execute_frames(&self, root: RootSequence<'_, State>, frame: StackFrame<State>) -> Result<Option<RawValue<State>>, &'static str>
+    {
+        // A native re-entering the VM starts one call deeper than the frame that called it
+        let base = ACTIVE_FRAMES.get();
+        if base > self.max_call_depth()
+        {
+            return Err("Maximum Call Depth Exceeded");
+        }
 
-    /// Application state, user provided
-    pub state: State
-}
+        ACTIVE_FRAMES.set(base + 1);
+        let result = self.run_frames(root, frame, base);
+        ACTIVE_FRAMES.set(base);
+        return result;
+    }
 
-#[inline(always)]
-fn process_address(offset_out: &mut usize, address: &AddressValue)
-{
-    match address {
-        AddressValue::RelativeOffset { offset } => {
-            // FIXME: Duplicate code
-            if *offset < 0 {
-                let calculated_offset = (*offset * -1) as usize;
-                *offset_out -= calculated_offset;
+    /// The loop behind execute_frames, with base frames already running on this thread.
+    fn run_frames(&self, root: RootSequence<'_, State>, frame: StackFrame<State>, base: usize) -> Result<Option<RawValue<State>>, &'static str>
+    {
+        let mut calls: Vec<CallFrame<State>> = Vec::new();
+        let mut function: Option<Arc<Function<State>>> = None;
+        let mut threaded = matches!(root, RootSequence::Threaded(_));
+        let mut frame = frame;
+        let mut index: usize = 0;
+
+        loop {
+            match (&function, &root) {
+                (None, RootSequence::Match(sequence)) => self.run_match(sequence, &mut frame, index)?,
+                (None, RootSequence::Threaded(sequence)) => self.run_threaded(sequence, &mut frame, index)?,
+                (Some(callee), _) => {
+                    let Function::VirtualFunction { parameters: _, instructions } = callee.as_ref() else { return Err("Native Function on Call Stack") };
+                    if threaded
+                    {
+                        self.run_threaded(instructions.threaded(), &mut frame, index)?;
+                    }
+                    else
+                    {
+                        self.run_match(instructions, &mut frame, index)?;
+                    }
+                }
             }
-            else {
-                let calculated_offset = (*offset * -1) as usize;
-                *offset_out += calculated_offset;
+
+            // The sequence stopped to call a script function
+            if let Some((callee, return_address)) = frame.pending_call.take()
+            {
+                if base + calls.len() >= self.max_call_depth()
+                {
+                    return Err("Maximum Call Depth Exceeded");
+                }
+
+                let Function::VirtualFunction { parameters, instructions } = callee.as_ref() else { return Err("Native Function on Call Stack") };
+                let mut callee_frame = StackFrame::for_sequence(instructions);
+                callee_frame.bind_arguments(parameters, &frame, self);
+
+                calls.push(CallFrame {
+                    function: function.replace(callee),
+                    threaded: threaded,
+                    return_address: return_address,
+                    frame: std::mem::replace(&mut frame, callee_frame)
+                });
+                ACTIVE_FRAMES.set(base + 1 + calls.len());
+
+                // Calls pick up the current backend, as set_dispatch promises
+                threaded = self.dispatch() == DispatchMode::Threaded;
+                index = 0;
+                continue;
+            }
+
+            // The sequence finished or returned, its result goes back to the caller the same way a native's does
+            match calls.pop() {
+                Some(caller) => {
+                    ACTIVE_FRAMES.set(base + 1 + calls.len());
+                    let callee = std::mem::replace(&mut function, caller.function);
+                    let value = frame.return_value.take();
+                    threaded = caller.threaded;
+                    index = caller.return_address;
+                    frame = caller.frame;
+
+                    if let Some(callee) = callee
+                    {
+                        frame.hand_back(callee.parameters(), value);
+                    }
+                },
+                None => {
+                    return Ok(frame.return_value);
+                }
             }
-        },
-        AddressValue::AbsoluteTarget { index } => {
-            *offset_out = *index;
         }
     }
-}
 
-impl<State> VirtualMachine<'_, State> where State: Clone
-{
+    /// Calls native functions straight away, while script functions are left on the frame for execute_frames to run.
+    /// Returns whether the sequence has to stop so the call can be made.
     #[inline(always)]
-    #[cfg(feature="async")]
-    pub fn new(state: State) -> Self {
-        let globals: Arc<RwLock<HashMap<VariableIdentifier, RawValue<State>>>> = Arc::new(RwLock::new(HashMap::new()));
-        let mut globals_write = globals.write().unwrap();
-        globals_write.reserve(1024);
-        drop(globals_write);
+    pub(crate) fn begin_call(&self, function: Arc<Function<State>>, frame: &mut StackFrame<State>, return_address: usize) -> Result<bool, &'static str>
+    {
+        if let Function::NativeFunction { parameters, binding } = function.as_ref()
+        {
+            let value = (binding)(self, frame)?;
+            frame.hand_back(parameters, value);
+            return Ok(false);
+        }
 
-        return Self {
-            globals: globals,
-            state: state,
-            root_namespace: RefCell::new(Namespace::new()),
-        };
+        frame.pending_call = Some((function, return_address));
+        return Ok(true);
     }
 
-    #[inline(always)]
-    #[cfg(not(feature="async"))]
-    pub fn new(state: State) -> Self {
-        let mut globals: HashMap<VariableIdentifier, RawValue<State>> = HashMap::new();
-        globals.reserve(1024);
-
-        return Self {
-            root_namespace: RefCell::new(Namespace::new()),
-            globals: RefCell::new(globals),
-            state: state
-        };
-    }
-    
-    pub fn interpret(&self, instructions: &InstructionSequence<State>) -> Result<(), &'static str>
+    /// Runs a sequence from the given index until it finishes, returns or stops to make a call, see execute_frames.
+    fn run_match(&self, instructions: &InstructionSequence<State>, frame: &mut StackFrame<State>, start: usize) -> Result<(), &'static str>
     {
-        // Allocate new frame
-        let mut stack: Vec<SystemValue<State>> = Vec::new();
-        stack.reserve(1024);
-        let mut frame = StackFrame {
-            locals: HashMap::new(),
-            stack: stack
-        };
         
-        let mut continue_running: bool = true;
-        let mut current_index: usize = 0;
+        let continue_running: bool = true;
+        let mut current_index: usize = start;
         
         // Ensure the total number of ops is read once and cached
         let op_count = instructions.ops.len();
@@ -939,17 +2564,17 @@ impl<State> VirtualMachine<'_, State> where State: Clone
             current_index += 1;
             match current_instruction {
                 OpCode::Swap {} => {  
-                    let lhs = frame.stack.pop();
-                    let rhs = frame.stack.pop();
+                    let top = frame.stack.pop();
+                    let below = frame.stack.pop();
                     
                     #[cfg(feature="fault-checks")]
-                    if lhs.is_none() || rhs.is_none()
+                    if top.is_none() || below.is_none()
                     {
                         return Err("Failed to load Values off Stack for Swap");
                     }
 
-                    frame.stack.push(rhs.unwrap());
-                    frame.stack.push(lhs.unwrap());
+                    frame.stack.push(top.unwrap());
+                    frame.stack.push(below.unwrap());
                 },
                 OpCode::PushFloat (value) => {
                     frame.stack.push(SystemValue::Raw { value: RawValue::Float { 0: FloatValue { value: value.value }}});
@@ -958,8 +2583,14 @@ impl<State> VirtualMachine<'_, State> where State: Clone
                     frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: *value }}});
                 },
                 OpCode::PushString { value } => {
-                    // NOTE: Continuous string alloc here
-                    frame.stack.push(SystemValue::Raw { value: RawValue::String { 0: StringValue { value: value.to_string() }}});
+                    // NOTE: Continuous string alloc here, PushSharedString avoids it
+                    frame.stack.push(SystemValue::Raw { value: RawValue::String { 0: StringValue { value: Arc::new(value.to_string()) }}});
+                },
+                OpCode::PushSharedString { value } => {
+                    frame.stack.push(SystemValue::Raw { value: RawValue::String { 0: StringValue { value: value.clone() }}});
+                },
+                OpCode::PushConstant { value } => {
+                    frame.stack.push(SystemValue::Raw { value: value.clone() });
                 },
                 OpCode::Pop {  } => {
                     // For now we let the application halt if stack is empty
@@ -973,6 +2604,11 @@ impl<State> VirtualMachine<'_, State> where State: Clone
                 },
                 OpCode::Jump { target } => {
                     process_address(&mut current_index, target);
+
+                    #[cfg(feature="jit")]
+                    {
+                        current_index = self.jit_enter(instructions, frame, current_index);
+                    }
                 },
                 OpCode::JumpTrue { target } => {
                     let current_value = frame.stack.pop();
@@ -982,8 +2618,13 @@ impl<State> VirtualMachine<'_, State> where State: Clone
                         return Err("Failed to Load condition for JumpTrue from Stack");
                     }
 
-                    if current_value.unwrap().as_raw(self, &frame).as_boolean(self, &frame) {
+                    if current_value.unwrap().as_raw(self, frame).as_boolean(self, frame) {
                         process_address(&mut current_index, target);
+
+                        #[cfg(feature="jit")]
+                        {
+                            current_index = self.jit_enter(instructions, frame, current_index);
+                        }
                     }
                 },
                 OpCode::JumpFalse { target } => {
@@ -994,7 +2635,38 @@ impl<State> VirtualMachine<'_, State> where State: Clone
                         return Err("Failed to Load condition for JumpTrue from Stack");
                     }
 
-                    if !current_value.unwrap().as_raw(self, &frame).as_boolean(self, &frame) {
+                    if !current_value.unwrap().as_raw(self, frame).as_boolean(self, frame) {
+                        process_address(&mut current_index, target);
+
+                        #[cfg(feature="jit")]
+                        {
+                            current_index = self.jit_enter(instructions, frame, current_index);
+                        }
+                    }
+                },
+                OpCode::JumpTrueOrPop { target } => {
+                    let current_value = frame.stack.pop();
+
+                    #[cfg(feature="fault-checks")]
+                    if current_value.is_none() {
+                        return Err("Failed to Load condition for JumpTrueOrPop from Stack");
+                    }
+
+                    if current_value.unwrap().as_raw(self, frame).as_boolean(self, frame) {
+                        frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: true }}});
+                        process_address(&mut current_index, target);
+                    }
+                },
+                OpCode::JumpFalseOrPop { target } => {
+                    let current_value = frame.stack.pop();
+
+                    #[cfg(feature="fault-checks")]
+                    if current_value.is_none() {
+                        return Err("Failed to Load condition for JumpFalseOrPop from Stack");
+                    }
+
+                    if !current_value.unwrap().as_raw(self, frame).as_boolean(self, frame) {
+                        frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: false }}});
                         process_address(&mut current_index, target);
                     }
                 },
@@ -1002,8 +2674,8 @@ impl<State> VirtualMachine<'_, State> where State: Clone
 
                 },
                 OpCode::Assignment {  } => {
-                    let lhs = frame.stack.pop();
                     let rhs = frame.stack.pop();
+                    let lhs = frame.stack.pop();
 
                     #[cfg(feature="fault-checks")]
                     if lhs.is_none() || rhs.is_none() {
@@ -1013,141 +2685,291 @@ impl<State> VirtualMachine<'_, State> where State: Clone
                     let lhs_unwrapped = lhs.unwrap();
 
                     // FIXME: Assuming variable lookup succeeds
-                    lhs_unwrapped.as_variable(self, &frame).unwrap().perform_assignment(self, &mut frame, &rhs.unwrap());
+                    lhs_unwrapped.as_variable(self, frame).unwrap().perform_assignment(self, frame, &rhs.unwrap())?;
 
                     frame.stack.push(lhs_unwrapped); // Push a reference to current variable back to stack
                 },
-                OpCode::Concat {  } => {
+                OpCode::AssignAndPop {  } => {
+                    let rhs = frame.stack.pop();
+                    let lhs = frame.stack.pop();
+
+                    #[cfg(feature="fault-checks")]
+                    if lhs.is_none() || rhs.is_none() {
+                        return Err("Failed to Load lhs & rhs from Stack for AssignAndPop");
+                    }
+
+                    // FIXME: Assuming variable lookup succeeds
+                    lhs.unwrap().as_variable(self, frame).unwrap().perform_assignment(self, frame, &rhs.unwrap())?;
+                },
+                OpCode::AssignLocalAndPop { slot } => {
+                    let current_value = frame.stack.pop();
+
+                    #[cfg(feature="fault-checks")]
+                    if current_value.is_none() || *slot >= frame.slots.len() {
+                        return Err("Failed to Load Value from Stack for AssignLocalAndPop");
+                    }
+
+                    frame.slots[*slot] = current_value.unwrap().into_raw(self, frame);
+                },
+                OpCode::IncrementLocal { slot, amount } => {
+                    #[cfg(feature="fault-checks")]
+                    if *slot >= frame.slots.len() {
+                        return Err("Local Slot out of Range for IncrementLocal");
+                    }
+
+                    let result = frame.slots[*slot].as_float(self, frame) + *amount;
+                    frame.slots[*slot] = RawValue::Float { 0: FloatValue { value: result }};
+                },
+                OpCode::CompareAndJump { comparison, jump_if, target } => {
+                    let rhs = frame.stack.pop();
                     let lhs = frame.stack.pop();
+
+                    #[cfg(feature="fault-checks")]
+                    if lhs.is_none() || rhs.is_none() {
+                        return Err("Failed to Load lhs & rhs from Stack for CompareAndJump");
+                    }
+
+                    if comparison.apply(&lhs.unwrap().into_raw(self, frame), &rhs.unwrap().into_raw(self, frame), self, frame) == *jump_if {
+                        process_address(&mut current_index, target);
+
+                        #[cfg(feature="jit")]
+                        {
+                            current_index = self.jit_enter(instructions, frame, current_index);
+                        }
+                    }
+                },
+                OpCode::Concat {  } => {
                     let rhs = frame.stack.pop();
+                    let lhs = frame.stack.pop();
 
                     #[cfg(feature="fault-checks")]
                     if lhs.is_none() || rhs.is_none() {
                         return Err("Failed to Load lhs & rhs from Stack for Concat");
                     }
 
-                    let mut result = lhs.unwrap().as_raw(self, &frame).as_string(self, &frame);
-                    result.push_str(&rhs.unwrap().as_raw(self, &frame).as_string(self, &frame));
+                    let result = self.concat(frame, lhs.unwrap(), rhs.unwrap(), None, store_slot(instructions.ops.get(current_index)));
+                    frame.stack.push(SystemValue::Raw { value: result });
+                },
+                OpCode::ConcatSeparator { separator } => {
+                    let rhs = frame.stack.pop();
+                    let lhs = frame.stack.pop();
+
+                    #[cfg(feature="fault-checks")]
+                    if lhs.is_none() || rhs.is_none() {
+                        return Err("Failed to Load lhs & rhs from Stack for ConcatSeparator");
+                    }
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::String { 0: StringValue { value: result }}});
+                    let result = self.concat(frame, lhs.unwrap(), rhs.unwrap(), Some(*separator), store_slot(instructions.ops.get(current_index)));
+                    frame.stack.push(SystemValue::Raw { value: result });
                 },
                 OpCode::Negate {  } => {
-                    panic!("Not Implemented");
-                     let current_value = frame.stack.last_mut().unwrap();
+                    let current_value = frame.stack.pop().unwrap();
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Float { 0: FloatValue { value: current_value.as_raw(self, frame).negate(self, frame) }}});
                 },
                 OpCode::Not {  } => {
                     let current_value = frame.stack.pop().unwrap();
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: !current_value.as_raw(self, &frame).as_boolean(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: !current_value.as_raw(self, frame).as_boolean(self, frame) }}});
+                },
+                OpCode::ToBoolean {  } => {
+                    let current_value = frame.stack.pop().unwrap();
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: current_value.as_raw(self, frame).as_boolean(self, frame) }}});
                 },
                 OpCode::CallFunction { target } => {
-                    let mut namespace_write = self.root_namespace.borrow_mut();
-                    let function_lookup = namespace_write.lookup_function_cached(target).unwrap();
-                    function_lookup.call(self, &frame).unwrap();
+                    // Release the namespace before calling so the callee can make calls of its own
+                    let function_lookup = self.namespace().lookup_function_cached(target)?;
+                    if self.begin_call(function_lookup, frame, current_index)?
+                    {
+                        return Ok(());
+                    }
+                },
+                OpCode::CallCached { site } => {
+                    let function = site.resolve(self)?;
+                    if self.begin_call(function, frame, current_index)?
+                    {
+                        return Ok(());
+                    }
+                },
+                OpCode::Return {  } => {
+                    let current_value = frame.stack.pop();
+                    frame.return_value = current_value.map(|value| value.into_raw(self, frame));
+                    return Ok(());
                 },
                 OpCode::LogicalAnd {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, &frame).as_boolean(self, &frame) && rhs.as_raw(self, &frame).as_boolean(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, frame).as_boolean(self, frame) && rhs.as_raw(self, frame).as_boolean(self, frame) }}});
                 },
                 OpCode::LogicalOr {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, &frame).as_boolean(self, &frame) || rhs.as_raw(self, &frame).as_boolean(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, frame).as_boolean(self, frame) || rhs.as_raw(self, frame).as_boolean(self, frame) }}});
                 },
                 OpCode::BitwiseAnd {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: lhs.as_raw(self, &frame).as_integer(self, &frame) & rhs.as_raw(self, &frame).as_integer(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: lhs.as_raw(self, frame).as_integer(self, frame) & rhs.as_raw(self, frame).as_integer(self, frame) }}});
                 },
                 OpCode::BitwiseOr {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
                     
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: lhs.as_raw(self, &frame).as_integer(self, &frame) | rhs.as_raw(self, &frame).as_integer(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: lhs.as_raw(self, frame).as_integer(self, frame) | rhs.as_raw(self, frame).as_integer(self, frame) }}});
                 },
-                OpCode::Add {  } => {
+                OpCode::BitwiseXor {  } => {
+                    let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
+
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: lhs.as_raw(self, frame).as_integer(self, frame) ^ rhs.as_raw(self, frame).as_integer(self, frame) }}});
+                },
+                OpCode::ShiftLeft {  } => {
+                    let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
+
+                    let result = lhs.as_raw(self, frame).as_integer(self, frame).wrapping_shl(rhs.as_raw(self, frame).as_integer(self, frame) as u32);
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: result }}});
+                },
+                OpCode::ShiftRight {  } => {
+                    let rhs = frame.stack.pop().unwrap();
                     let lhs = frame.stack.pop().unwrap();
+
+                    let result = (lhs.as_raw(self, frame).as_integer(self, frame) as u32).wrapping_shr(rhs.as_raw(self, frame).as_integer(self, frame) as u32);
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: result as i32 }}});
+                },
+                OpCode::BitwiseNot {  } | OpCode::OnesComplement {  } => {
+                    let current_value = frame.stack.pop().unwrap();
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: !current_value.as_raw(self, frame).as_integer(self, frame) }}});
+                },
+                OpCode::Add {  } => {
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    let result = lhs.as_raw(self, &frame).add(&rhs.as_raw(self, &frame), self, &frame);
+                    let result = lhs.as_raw(self, frame).add(&rhs.as_raw(self, frame), self, frame);
                     frame.stack.push(SystemValue::Raw { value: RawValue::Float { 0: FloatValue { value: result }}});
                 },
                 OpCode::Minus {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    let result = lhs.as_raw(self, &frame).subtract(&rhs.as_raw(self, &frame), self, &frame);
+                    let result = lhs.as_raw(self, frame).subtract(&rhs.as_raw(self, frame), self, frame);
 
                     frame.stack.push(SystemValue::Raw { value: RawValue::Float { 0: FloatValue { value: result }}});
                 },
                 OpCode::Modulus {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: lhs.as_raw(self, &frame).as_integer(self, &frame) % rhs.as_raw(self, &frame).as_integer(self, &frame) }}});
+                    let result = lhs.as_raw(self, frame).modulus(&rhs.as_raw(self, frame), self, frame);
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: result }}});
                 },
                 OpCode::Multiply {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    let result = lhs.as_raw(self, &frame).multiply(&rhs.as_raw(self, &frame), self, &frame);
+                    let result = lhs.as_raw(self, frame).multiply(&rhs.as_raw(self, frame), self, frame);
                     frame.stack.push(SystemValue::Raw { value: RawValue::Float { 0: FloatValue { value: result }}});
                 },
                 OpCode::Divide {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    let result = lhs.as_raw(self, &frame).divide(&rhs.as_raw(self, &frame), self, &frame);
+                    let result = lhs.as_raw(self, frame).divide(&rhs.as_raw(self, frame), self, frame);
                     
                     frame.stack.push(SystemValue::Raw { value: RawValue::Float { 0: FloatValue { value: result }}});
                 },
+                OpCode::IntegerDivide {  } => {
+                    let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
+
+                    let result = lhs.as_raw(self, frame).integer_divide(&rhs.as_raw(self, frame), self, frame);
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Integer { 0: IntegerValue { value: result }}});
+                },
                 OpCode::LessThan {  } => {
+                    let rhs = frame.stack.pop().unwrap();
                     let lhs = frame.stack.pop().unwrap();
+
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, frame).as_float(self, frame) < rhs.as_raw(self, frame).as_float(self, frame) }}});
+                },
+                OpCode::LessThanOrEqual {  } => {
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, &frame).as_float(self, &frame) < rhs.as_raw(self, &frame).as_float(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, frame).as_float(self, frame) <= rhs.as_raw(self, frame).as_float(self, frame) }}});
                 },
                 OpCode::GreaterThan {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, &frame).as_float(self, &frame) > rhs.as_raw(self, &frame).as_float(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, frame).as_float(self, frame) > rhs.as_raw(self, frame).as_float(self, frame) }}});
                 },
                 OpCode::GreaterThanOrEqual {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, &frame).as_float(self, &frame) >= rhs.as_raw(self, &frame).as_float(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, frame).as_float(self, frame) >= rhs.as_raw(self, frame).as_float(self, frame) }}});
                 },
                 OpCode::Equals {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.equals(self, &frame, rhs) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.equals(self, frame, rhs) }}});
                 },
                 OpCode::NotEquals {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: !lhs.equals(self, &frame, rhs) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: !lhs.equals(self, frame, rhs) }}});
                 },
                 OpCode::StringEquals {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, &frame).as_string(self, &frame) == rhs.as_raw(self, &frame).as_string(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, frame).as_str(self, frame) == rhs.as_raw(self, frame).as_str(self, frame) }}});
                 },
                 OpCode::StringNotEqual {  } => {
-                    let lhs = frame.stack.pop().unwrap();
                     let rhs = frame.stack.pop().unwrap();
+                    let lhs = frame.stack.pop().unwrap();
 
-                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, &frame).as_string(self, &frame) != rhs.as_raw(self, &frame).as_string(self, &frame) }}});
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Boolean { 0: BooleanValue { value: lhs.as_raw(self, frame).as_str(self, frame) != rhs.as_raw(self, frame).as_str(self, frame) }}});
                 },
                 OpCode::PushVariable { variable } => {
                     frame.stack.push(SystemValue::Variable { value: variable.clone() });
+                },
+                OpCode::LoadLocal { slot } => {
+                    #[cfg(feature="fault-checks")]
+                    if *slot >= frame.slots.len() {
+                        return Err("Local Slot out of Range for LoadLocal");
+                    }
+
+                    let value = frame.slots[*slot].clone();
+                    frame.stack.push(SystemValue::Raw { value: value });
+                },
+                OpCode::StoreLocal { slot } => {
+                    let current_value = frame.stack.pop();
+
+                    #[cfg(feature="fault-checks")]
+                    if current_value.is_none() || *slot >= frame.slots.len() {
+                        return Err("Failed to Load Value from Stack for StoreLocal");
+                    }
+
+                    frame.slots[*slot] = current_value.unwrap().into_raw(self, frame);
+                    frame.stack.push(SystemValue::Variable { value: VariableReference::LocalSlot { slot: *slot, phantom: PhantomData }});
+                },
+                OpCode::PushTaggedString { value } => {
+                    let id = self.tag_string(value);
+                    frame.stack.push(SystemValue::Raw { value: RawValue::Tagged { 0: TaggedValue { id }}});
+                },
+                OpCode::Detag {  } => {
+                    let current_value = frame.stack.pop().unwrap().as_raw(self, frame);
+                    let result = self.detag(&current_value, frame);
+                    frame.stack.push(SystemValue::Raw { value: RawValue::String { 0: StringValue { value: Arc::new(result) }}});
+                },
+                OpCode::GetTaggedString {  } => {
+                    let current_value = frame.stack.pop().unwrap().as_raw(self, frame);
+                    let result = self.get_tagged_string(&current_value, frame);
+                    frame.stack.push(SystemValue::Raw { value: RawValue::String { 0: StringValue { value: Arc::new(result) }}});
                 }
             }
         }
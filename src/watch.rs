@@ -0,0 +1,368 @@
+#[cfg(feature="async")]
+use std::sync::{Arc, Mutex, RwLock, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}};
+
+#[cfg(not(feature="async"))]
+use std::{cell::{Cell, RefCell}, rc::Rc};
+
+use crate::util::glob_match;
+use crate::vm::{VirtualMachine, RawValue, GlobalHandle, GlobalStorage};
+
+/// Identifies a watcher so it can be removed again.
+pub type WatchId = u64;
+
+/// Called with the global's name, its previous value (None if it was unassigned) and its new value.
+#[cfg(feature="async")]
+pub type GlobalWatcher<State> = Box<dyn Fn(&str, Option<&RawValue<State>>, &RawValue<State>) + Send + Sync>;
+
+/// Called with the global's name, its previous value (None if it was unassigned) and its new value.
+#[cfg(not(feature="async"))]
+pub type GlobalWatcher<State> = Box<dyn Fn(&str, Option<&RawValue<State>>, &RawValue<State>)>;
+
+#[cfg(feature="async")]
+type SharedWatcher<State> = Arc<GlobalWatcher<State>>;
+
+#[cfg(not(feature="async"))]
+type SharedWatcher<State> = Rc<GlobalWatcher<State>>;
+
+struct Watch<State> where State: Clone
+{
+    id: WatchId,
+    pattern: String,
+    callback: SharedWatcher<State>
+}
+
+/// A change held back while coalescing, keeping the value from before the first write.
+struct PendingChange<State> where State: Clone
+{
+    handle: GlobalHandle<State>,
+    old: Option<RawValue<State>>,
+    new: RawValue<State>
+}
+
+/// Watchers registered on a VM, along with the changes held back while coalescing.
+pub struct WatchRegistry<State> where State: Clone
+{
+    #[cfg(feature="async")]
+    watches: RwLock<Vec<Watch<State>>>,
+
+    #[cfg(not(feature="async"))]
+    watches: RefCell<Vec<Watch<State>>>,
+
+    #[cfg(feature="async")]
+    next_id: AtomicU64,
+
+    #[cfg(not(feature="async"))]
+    next_id: Cell<u64>,
+
+    #[cfg(feature="async")]
+    coalesce: AtomicBool,
+
+    #[cfg(not(feature="async"))]
+    coalesce: Cell<bool>,
+
+    /// Number of sequences currently running that started while coalescing
+    #[cfg(feature="async")]
+    depth: AtomicUsize,
+
+    /// Number of sequences currently running that started while coalescing
+    #[cfg(not(feature="async"))]
+    depth: Cell<usize>,
+
+    #[cfg(feature="async")]
+    pending: Mutex<Vec<PendingChange<State>>>,
+
+    #[cfg(not(feature="async"))]
+    pending: RefCell<Vec<PendingChange<State>>>
+}
+
+impl<State> Default for WatchRegistry<State> where State: Clone
+{
+    fn default() -> Self
+    {
+        return Self::new();
+    }
+}
+
+impl<State> WatchRegistry<State> where State: Clone
+{
+    #[cfg(feature="async")]
+    pub fn new() -> Self
+    {
+        return Self
+        {
+            watches: RwLock::new(Vec::new()),
+            next_id: AtomicU64::new(0),
+            coalesce: AtomicBool::new(false),
+            depth: AtomicUsize::new(0),
+            pending: Mutex::new(Vec::new())
+        };
+    }
+
+    #[cfg(not(feature="async"))]
+    pub fn new() -> Self
+    {
+        return Self
+        {
+            watches: RefCell::new(Vec::new()),
+            next_id: Cell::new(0),
+            coalesce: Cell::new(false),
+            depth: Cell::new(0),
+            pending: RefCell::new(Vec::new())
+        };
+    }
+
+    fn matches(&self, name: &str) -> bool
+    {
+        #[cfg(feature="async")]
+        let watches_read = self.watches.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let watches_read = self.watches.borrow();
+
+        return watches_read.iter().any(|watch| glob_match(&watch.pattern, name));
+    }
+
+    fn callbacks(&self, name: &str) -> Vec<SharedWatcher<State>>
+    {
+        #[cfg(feature="async")]
+        let watches_read = self.watches.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let watches_read = self.watches.borrow();
+
+        return watches_read.iter().filter(|watch| glob_match(&watch.pattern, name)).map(|watch| watch.callback.clone()).collect();
+    }
+
+    fn is_coalescing(&self) -> bool
+    {
+        #[cfg(feature="async")]
+        return self.coalesce.load(Ordering::Relaxed);
+
+        #[cfg(not(feature="async"))]
+        return self.coalesce.get();
+    }
+
+    fn depth(&self) -> usize
+    {
+        #[cfg(feature="async")]
+        return self.depth.load(Ordering::Acquire);
+
+        #[cfg(not(feature="async"))]
+        return self.depth.get();
+    }
+
+    /// Called as a sequence starts. Returns whether it has to call leave once it is done.
+    pub(crate) fn enter(&self) -> bool
+    {
+        if !self.is_coalescing()
+        {
+            return false;
+        }
+
+        #[cfg(feature="async")]
+        self.depth.fetch_add(1, Ordering::AcqRel);
+
+        #[cfg(not(feature="async"))]
+        self.depth.set(self.depth.get() + 1);
+
+        return true;
+    }
+
+    /// Returns the changes to deliver if this was the outermost sequence.
+    fn leave(&self) -> Vec<PendingChange<State>>
+    {
+        #[cfg(feature="async")]
+        let outermost = self.depth.fetch_sub(1, Ordering::AcqRel) == 1;
+
+        #[cfg(not(feature="async"))]
+        let outermost = {
+            self.depth.set(self.depth.get() - 1);
+            self.depth.get() == 0
+        };
+
+        if !outermost
+        {
+            return Vec::new();
+        }
+
+        #[cfg(feature="async")]
+        return std::mem::take(&mut *self.pending.lock().unwrap());
+
+        #[cfg(not(feature="async"))]
+        return std::mem::take(&mut *self.pending.borrow_mut());
+    }
+
+    /// Holds a change back, merging it with any earlier change to the same global.
+    fn defer(&self, handle: &GlobalHandle<State>, old: Option<RawValue<State>>, new: RawValue<State>)
+    {
+        #[cfg(feature="async")]
+        let mut pending = self.pending.lock().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let mut pending = self.pending.borrow_mut();
+
+        #[cfg(feature="async")]
+        let existing = pending.iter_mut().find(|change| Arc::ptr_eq(&change.handle, handle));
+
+        #[cfg(not(feature="async"))]
+        let existing = pending.iter_mut().find(|change| Rc::ptr_eq(&change.handle, handle));
+
+        match existing {
+            Some(change) => {
+                change.new = new;
+            },
+            None => {
+                pending.push(PendingChange { handle: handle.clone(), old: old, new: new });
+            }
+        }
+    }
+}
+
+impl<State> VirtualMachine<'_, State> where State: Clone
+{
+    /// Calls the watcher whenever a script assigns a global matching the pattern, e.g. "$Pref::Video::*".
+    /// Patterns support * and ? and, like global names, are case insensitive.
+    pub fn watch_global(&self, pattern: &str, callback: GlobalWatcher<State>) -> WatchId
+    {
+        let pattern = pattern.strip_prefix('$').unwrap_or(pattern).to_owned();
+
+        #[cfg(feature="async")]
+        let id = self.watchers.next_id.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(not(feature="async"))]
+        let id = {
+            let id = self.watchers.next_id.get();
+            self.watchers.next_id.set(id + 1);
+            id
+        };
+
+        // Never hold the watch list while taking the globals table, create_global takes them the other way round
+        {
+            #[cfg(feature="async")]
+            let mut watches_write = self.watchers.watches.write().unwrap();
+
+            #[cfg(not(feature="async"))]
+            let mut watches_write = self.watchers.watches.borrow_mut();
+
+            #[cfg(feature="async")]
+            watches_write.push(Watch { id: id, pattern: pattern.clone(), callback: Arc::new(callback) });
+
+            #[cfg(not(feature="async"))]
+            watches_write.push(Watch { id: id, pattern: pattern.clone(), callback: Rc::new(callback) });
+        }
+
+        #[cfg(feature="async")]
+        let globals_read = self.globals.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let globals_read = self.globals.borrow();
+
+        for handle in globals_read.values().filter(|handle| glob_match(&pattern, handle.name()))
+        {
+            handle.set_watched(true);
+        }
+
+        return id;
+    }
+
+    /// Removes a watcher, returning whether it was registered.
+    pub fn unwatch_global(&self, id: WatchId) -> bool
+    {
+        {
+            #[cfg(feature="async")]
+            let mut watches_write = self.watchers.watches.write().unwrap();
+
+            #[cfg(not(feature="async"))]
+            let mut watches_write = self.watchers.watches.borrow_mut();
+
+            let count = watches_write.len();
+            watches_write.retain(|watch| watch.id != id);
+            if watches_write.len() == count
+            {
+                return false;
+            }
+        }
+
+        #[cfg(feature="async")]
+        let globals_read = self.globals.read().unwrap();
+
+        #[cfg(not(feature="async"))]
+        let globals_read = self.globals.borrow();
+
+        for handle in globals_read.values().filter(|handle| handle.is_watched())
+        {
+            handle.set_watched(self.watchers.matches(handle.name()));
+        }
+
+        return true;
+    }
+
+    /// While enabled, watchers hear about each changed global once, when the outermost sequence running on this VM
+    /// returns, with the value from before the first write and the value after the last.
+    pub fn set_coalesce_watchers(&self, coalesce: bool)
+    {
+        #[cfg(feature="async")]
+        self.watchers.coalesce.store(coalesce, Ordering::Relaxed);
+
+        #[cfg(not(feature="async"))]
+        self.watchers.coalesce.set(coalesce);
+    }
+
+    /// Storage for a newly referenced global, flagged if a watcher is already waiting on its name.
+    pub(crate) fn create_global(&self, name: &str) -> GlobalHandle<State>
+    {
+        let storage = GlobalStorage::new(name);
+        storage.set_watched(self.watchers.matches(name));
+
+        #[cfg(feature="async")]
+        return Arc::new(storage);
+
+        #[cfg(not(feature="async"))]
+        return Rc::new(storage);
+    }
+
+    /// Assigns a global on behalf of a script, notifying watchers once the write succeeds.
+    #[inline(always)]
+    pub(crate) fn assign_global(&self, handle: &GlobalHandle<State>, value: RawValue<State>) -> Result<(), &'static str>
+    {
+        if !handle.is_watched()
+        {
+            return handle.set(value);
+        }
+
+        let old = handle.get();
+        handle.set(value.clone())?;
+
+        // Bound globals may have converted the value on the way in
+        let new = handle.get().unwrap_or(value);
+
+        if self.watchers.is_coalescing() && self.watchers.depth() > 0
+        {
+            self.watchers.defer(handle, old, new);
+        }
+        else
+        {
+            self.notify_watchers(handle.name(), old.as_ref(), &new);
+        }
+
+        return Ok(());
+    }
+
+    /// Called once a sequence that entered the registry finishes, delivering held back changes if it was the outermost.
+    pub(crate) fn leave_watch_scope(&self)
+    {
+        for change in self.watchers.leave()
+        {
+            self.notify_watchers(change.handle.name(), change.old.as_ref(), &change.new);
+        }
+    }
+
+    fn notify_watchers(&self, name: &str, old: Option<&RawValue<State>>, new: &RawValue<State>)
+    {
+        // Release the watch list first so callbacks can add or remove watchers
+        for callback in self.watchers.callbacks(name)
+        {
+            (callback)(name, old, new);
+        }
+    }
+}